@@ -1,10 +1,19 @@
 use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use chrono::{Duration, Utc};
-use dialoguer::{Input, Password};
+use colored::Colorize;
+use dialoguer::{Input, Password, Select};
 use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{IsTerminal, Read};
+use std::process::Command;
 use std::time::Duration as StdDuration;
 
 use crate::config::Config;
+use crate::credentials::CredentialsLock;
+use crate::device::{self, DeviceFlow};
+use crate::oauth::{self, Pkce};
 
 #[derive(Debug, Serialize)]
 struct SignUpRequest {
@@ -18,11 +27,56 @@ struct SignInRequest {
     password: String,
 }
 
+/// The kind of one-time code being verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OtpType {
+    /// Confirms a new account
+    Signup,
+    /// Signs in to an existing account
+    Email,
+    /// Starts a password reset
+    Recovery,
+}
+
+#[derive(Debug, Serialize)]
+struct OtpRequest {
+    email: String,
+    create_user: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct PkceTokenRequest {
+    auth_code: String,
+    code_verifier: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EmailRequest {
+    email: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ResendRequest {
+    email: String,
+    #[serde(rename = "type")]
+    type_: OtpType,
+}
+
+#[derive(Debug, Serialize, Default)]
+struct UpdateUserRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 struct VerifyOtpRequest {
     email: String,
     token: String,
-    type_: String,
+    #[serde(rename = "type")]
+    type_: OtpType,
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,6 +93,105 @@ struct AuthResponse {
 struct User {
     id: String,
     email: String,
+    #[serde(default)]
+    factors: Vec<Factor>,
+}
+
+/// A multi-factor authentication factor registered on the account
+#[derive(Debug, Deserialize, Clone)]
+pub struct Factor {
+    pub id: String,
+    #[serde(default)]
+    pub friendly_name: Option<String>,
+    pub factor_type: String,
+    pub status: String,
+}
+
+impl Factor {
+    pub fn is_verified(&self) -> bool {
+        self.status == "verified"
+    }
+    
+    pub fn label(&self) -> String {
+        match &self.friendly_name {
+            Some(name) if !name.is_empty() => format!("{} ({})", name, self.factor_type),
+            _ => self.factor_type.clone(),
+        }
+    }
+}
+
+/// Whether a session must be stepped up with a second factor before it's usable
+pub fn mfa_required(claims: &TokenClaims, factors: &[Factor]) -> bool {
+    factors.iter().any(|f| f.is_verified() && f.factor_type == "totp")
+        && claims.aal.as_deref() != Some("aal2")
+}
+
+#[derive(Debug, Serialize)]
+struct EnrollFactorRequest {
+    factor_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    friendly_name: Option<String>,
+}
+
+/// A newly registered, not yet verified TOTP factor
+#[derive(Debug, Deserialize)]
+pub struct EnrollFactorResponse {
+    pub id: String,
+    pub totp: TotpEnrollment,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    /// `otpauth://` URI for authenticator apps
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChallengeResponse {
+    id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyFactorRequest {
+    challenge_id: String,
+    code: String,
+}
+
+/// Claims carried in a Supabase access token
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TokenClaims {
+    #[serde(default)]
+    pub sub: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub iss: Option<String>,
+    #[serde(default)]
+    pub exp: Option<i64>,
+    /// Authenticator assurance level: `aal1` for a password, `aal2` after MFA
+    #[serde(default)]
+    pub aal: Option<String>,
+}
+
+impl TokenClaims {
+    /// Decode the payload of a JWT without verifying its signature.
+    ///
+    /// Only the server can verify the token, so this is for display purposes.
+    pub fn decode(token: &str) -> Result<Self> {
+        let payload = token
+            .split('.')
+            .nth(1)
+            .context("Token is not a JWT")?;
+            
+        let bytes = URL_SAFE_NO_PAD
+            .decode(payload.trim_end_matches('='))
+            .context("Failed to decode token payload")?;
+            
+        serde_json::from_slice(&bytes).context("Failed to parse token claims")
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -73,6 +226,176 @@ impl ErrorResponse {
         }
         None
     }
+    
+    /// Turn a failed Supabase response into an error such as "Login failed: ..."
+    async fn into_error(response: reqwest::Response, action: &str) -> anyhow::Error {
+        let error: ErrorResponse = match response.json().await {
+            Ok(error) => error,
+            Err(e) => return anyhow::Error::new(e).context("Failed to parse error response"),
+        };
+        
+        match error.get_description() {
+            Some(desc) => anyhow::anyhow!("{} failed: {} - {}", action, error.get_error(), desc),
+            None => anyhow::anyhow!("{} failed: {}", action, error.get_error()),
+        }
+    }
+}
+
+/// Credentials supplied without prompting, for scripts and CI
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub email: String,
+    pub password: String,
+}
+
+impl Credentials {
+    /// Gather credentials from flags, `LYNX_FM_EMAIL`/`LYNX_FM_PASSWORD` and
+    /// `password_command`, in that order.
+    ///
+    /// Returns `None` when there is no password source, so the caller can
+    /// fall back to prompting.
+    pub fn resolve(config: &Config, email: Option<String>, password_stdin: bool) -> Result<Option<Self>> {
+        let password = if password_stdin {
+            Some(read_password_stdin()?)
+        } else if let Some(password) = env_var("LYNX_FM_PASSWORD") {
+            Some(password)
+        } else if let Some(command) = &config.password_command {
+            Some(run_password_command(command)?)
+        } else {
+            None
+        };
+        
+        let Some(password) = password else {
+            return Ok(None);
+        };
+        
+        let email = email
+            .or_else(|| env_var("LYNX_FM_EMAIL"))
+            .or_else(|| config.email.clone())
+            .context("A password was provided but no email. Pass --email or set LYNX_FM_EMAIL")?;
+            
+        Ok(Some(Self { email, password }))
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn read_password_stdin() -> Result<String> {
+    let mut password = String::new();
+    std::io::stdin()
+        .read_to_string(&mut password)
+        .context("Failed to read password from stdin")?;
+        
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        anyhow::bail!("No password received on stdin");
+    }
+    
+    Ok(password)
+}
+
+fn run_password_command(command: &str) -> Result<String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .with_context(|| format!("Failed to run password command: {}", command))?;
+    
+    if !output.status.success() {
+        anyhow::bail!(
+            "Password command exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    
+    // Like git credential helpers, only the first line is the password
+    let stdout = String::from_utf8(output.stdout).context("Password command printed invalid UTF-8")?;
+    let password = stdout.lines().next().unwrap_or_default().to_string();
+    if password.is_empty() {
+        anyhow::bail!("Password command printed nothing");
+    }
+    
+    Ok(password)
+}
+
+/// Use the given email, falling back to `LYNX_FM_EMAIL`, the configured
+/// email and finally a prompt
+fn prompt_email(email: Option<String>, config: &Config) -> Result<String> {
+    match email.or_else(|| env_var("LYNX_FM_EMAIL")).or_else(|| config.email.clone()) {
+        Some(email) => Ok(email),
+        None => Ok(Input::new()
+            .with_prompt("Email")
+            .interact_text()?),
+    }
+}
+
+fn prompt_new_password() -> Result<String> {
+    Ok(Password::new()
+        .with_prompt("New password (min 8 characters)")
+        .with_confirmation("Confirm password", "Passwords don't match")
+        .interact()?)
+}
+
+fn select_factor(factors: &[Factor]) -> Result<&Factor> {
+    match factors {
+        [] => anyhow::bail!("No verified MFA factor found"),
+        [factor] => Ok(factor),
+        _ => {
+            require_totp_terminal()?;
+            let labels: Vec<String> = factors.iter().map(Factor::label).collect();
+            let index = Select::new()
+                .with_prompt("Choose an authenticator")
+                .items(&labels)
+                .default(0)
+                .interact()?;
+            Ok(&factors[index])
+        }
+    }
+}
+
+fn prompt_totp_code() -> Result<String> {
+    require_totp_terminal()?;
+    let code: String = Input::new()
+        .with_prompt("Authenticator code")
+        .interact_text()?;
+        
+    Ok(code.trim().to_string())
+}
+
+fn require_terminal() -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "Not logged in and no terminal available to prompt for credentials. \
+             Set LYNX_FM_EMAIL and LYNX_FM_PASSWORD, configure password_command, \
+             or run `lynx-fm login --email <email> --password-stdin`"
+        );
+    }
+    
+    Ok(())
+}
+
+/// Fail with a clear message when `what` can't be typed in because there is no terminal
+fn require_terminal_for(what: &str) -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("No terminal available to prompt for {}. Run the command interactively", what);
+    }
+    
+    Ok(())
+}
+
+fn require_totp_terminal() -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "This account requires a TOTP code from an authenticator app, \
+             but there is no terminal to prompt for it. Run the command interactively"
+        );
+    }
+    
+    Ok(())
 }
 
 pub struct AuthClient {
@@ -83,13 +406,175 @@ pub struct AuthClient {
 impl AuthClient {
     pub fn new(config: Config) -> Self {
         let client = reqwest::Client::builder()
-            .timeout(StdDuration::from_secs(30))
+            .timeout(StdDuration::from_secs(config.request_timeout_secs))
             .build()
             .expect("Failed to build HTTP client");
             
         Self { config, client }
     }
     
+    /// Save the tokens from a successful auth response
+    fn store_session(&self, auth_data: AuthResponse) -> Result<Config> {
+        self.store_tokens(auth_data.access_token, auth_data.refresh_token, auth_data.expires_in)
+    }
+    
+    fn store_tokens(&self, access_token: String, refresh_token: String, expires_in: i64) -> Result<Config> {
+        let expiry = Utc::now() + Duration::seconds(expires_in);
+        
+        let mut new_config = self.config.clone();
+        new_config.auth_token = Some(access_token);
+        new_config.refresh_token = Some(refresh_token);
+        new_config.token_expiry = Some(expiry.timestamp());
+        
+        new_config.save()?;
+        
+        Ok(new_config)
+    }
+    
+    /// Save a new session, first stepping up with a TOTP code if the account requires MFA
+    async fn finish_login(&self, auth_data: AuthResponse) -> Result<Config> {
+        let factors = auth_data.user.as_ref()
+            .map(|user| user.factors.clone())
+            .unwrap_or_default();
+        let claims = TokenClaims::decode(&auth_data.access_token).unwrap_or_default();
+        
+        if !mfa_required(&claims, &factors) {
+            return self.store_session(auth_data);
+        }
+        
+        // The aal1 token is only good for completing the challenge, so it isn't saved
+        let mut pending = self.config.clone();
+        pending.auth_token = Some(auth_data.access_token);
+        
+        println!("Two-factor authentication is enabled for this account.");
+        let verified: Vec<Factor> = factors.into_iter()
+            .filter(|f| f.is_verified() && f.factor_type == "totp")
+            .collect();
+        let factor = select_factor(&verified)?;
+        
+        Self::new(pending).verify_factor(&factor.id, &prompt_totp_code()?).await
+    }
+    
+    /// Answer a challenge for `factor_id` with a TOTP code and save the aal2 session
+    async fn verify_factor(&self, factor_id: &str, code: &str) -> Result<Config> {
+        let token = self.config.auth_token.as_ref()
+            .context("You need to log in first")?;
+            
+        let url = format!("{}/auth/v1/factors/{}/challenge", self.config.supabase_url, factor_id);
+        
+        let response = self.client
+            .post(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .context("Failed to send MFA challenge request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "MFA challenge").await);
+        }
+        
+        let challenge: ChallengeResponse = response.json().await
+            .context("Failed to parse MFA challenge response")?;
+            
+        let url = format!("{}/auth/v1/factors/{}/verify", self.config.supabase_url, factor_id);
+        
+        let response = self.client
+            .post(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .json(&VerifyFactorRequest {
+                challenge_id: challenge.id,
+                code: code.to_string(),
+            })
+            .send()
+            .await
+            .context("Failed to send MFA verification request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "MFA verification").await);
+        }
+        
+        let auth_data: AuthResponse = response.json().await
+            .context("Failed to parse auth response")?;
+            
+        self.store_session(auth_data)
+    }
+    
+    /// List the MFA factors registered on the logged-in account
+    pub async fn list_factors(&self) -> Result<Vec<Factor>> {
+        let token = self.config.auth_token.as_ref()
+            .context("You need to log in first")?;
+            
+        let url = format!("{}/auth/v1/user", self.config.supabase_url);
+        
+        let response = self.client
+            .get(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .context("Failed to send user request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "Listing factors").await);
+        }
+        
+        let user: User = response.json().await
+            .context("Failed to parse user response")?;
+            
+        Ok(user.factors)
+    }
+    
+    /// Register a new TOTP factor; it stays unverified until a first code is checked
+    pub async fn enroll_factor(&self, name: Option<String>) -> Result<EnrollFactorResponse> {
+        let token = self.config.auth_token.as_ref()
+            .context("You need to log in first")?;
+            
+        let url = format!("{}/auth/v1/factors", self.config.supabase_url);
+        
+        let response = self.client
+            .post(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .json(&EnrollFactorRequest {
+                factor_type: "totp".to_string(),
+                friendly_name: name,
+            })
+            .send()
+            .await
+            .context("Failed to send enroll request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "Enroll").await);
+        }
+        
+        response.json().await.context("Failed to parse enroll response")
+    }
+    
+    pub async fn unenroll_factor(&self, factor_id: &str) -> Result<()> {
+        let token = self.config.auth_token.as_ref()
+            .context("You need to log in first")?;
+            
+        let url = format!("{}/auth/v1/factors/{}", self.config.supabase_url, factor_id);
+        
+        let response = self.client
+            .delete(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .context("Failed to send unenroll request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "Unenroll").await);
+        }
+        
+        Ok(())
+    }
+    
     pub async fn signup(&self, email: &str, password: &str) -> Result<()> {
         let url = format!("{}/auth/v1/signup", self.config.supabase_url);
         
@@ -106,21 +591,37 @@ impl AuthClient {
             .context("Failed to send signup request")?;
             
         if !response.status().is_success() {
-            let error: ErrorResponse = response.json().await
-                .context("Failed to parse error response")?;
-                
-            if let Some(desc) = error.get_description() {
-                anyhow::bail!("Signup failed: {} - {}", error.get_error(), desc);
-            } else {
-                anyhow::bail!("Signup failed: {}", error.get_error());
-            }
+            return Err(ErrorResponse::into_error(response, "Signup").await);
         }
         
         println!("Signup successful! Please check your email for a verification code.");
         Ok(())
     }
     
-    pub async fn verify_otp(&self, email: &str, token: &str) -> Result<Config> {
+    /// Email a one-time login code to an existing account
+    pub async fn request_otp(&self, email: &str) -> Result<()> {
+        let url = format!("{}/auth/v1/otp", self.config.supabase_url);
+        
+        let response = self.client
+            .post(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Content-Type", "application/json")
+            .json(&OtpRequest {
+                email: email.to_string(),
+                create_user: false,
+            })
+            .send()
+            .await
+            .context("Failed to send login code request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "Login code request").await);
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn verify_otp(&self, email: &str, token: &str, otp_type: OtpType) -> Result<Config> {
         let url = format!("{}/auth/v1/verify", self.config.supabase_url);
         
         let response = self.client
@@ -130,37 +631,118 @@ impl AuthClient {
             .json(&VerifyOtpRequest {
                 email: email.to_string(),
                 token: token.to_string(),
-                type_: "signup".to_string(),
+                type_: otp_type,
             })
             .send()
             .await
             .context("Failed to send verification request")?;
             
         if !response.status().is_success() {
-            let error: ErrorResponse = response.json().await
-                .context("Failed to parse error response")?;
-                
-            if let Some(desc) = error.get_description() {
-                anyhow::bail!("Verification failed: {} - {}", error.get_error(), desc);
-            } else {
-                anyhow::bail!("Verification failed: {}", error.get_error());
-            }
+            return Err(ErrorResponse::into_error(response, "Verification").await);
         }
         
         let auth_data: AuthResponse = response.json().await
             .context("Failed to parse auth response")?;
             
-        let expiry = Utc::now() + Duration::seconds(auth_data.expires_in);
+        let new_config = self.finish_login(auth_data).await?;
         
-        let mut new_config = self.config.clone();
-        new_config.auth_token = Some(auth_data.access_token);
-        new_config.refresh_token = Some(auth_data.refresh_token);
-        new_config.token_expiry = Some(expiry.timestamp());
+        match otp_type {
+            OtpType::Signup => println!("Email verification successful! You are now logged in."),
+            OtpType::Email => println!("Login successful!"),
+            OtpType::Recovery => println!("Code accepted."),
+        }
+        Ok(new_config)
+    }
+    
+    /// Email a password recovery code
+    pub async fn recover_password(&self, email: &str) -> Result<()> {
+        let url = format!("{}/auth/v1/recover", self.config.supabase_url);
         
-        new_config.save()?;
+        let response = self.client
+            .post(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Content-Type", "application/json")
+            .json(&EmailRequest {
+                email: email.to_string(),
+            })
+            .send()
+            .await
+            .context("Failed to send password recovery request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "Password recovery").await);
+        }
         
-        println!("Email verification successful! You are now logged in.");
-        Ok(new_config)
+        Ok(())
+    }
+    
+    /// Send the signup verification email again
+    pub async fn resend_verification(&self, email: &str) -> Result<()> {
+        let url = format!("{}/auth/v1/resend", self.config.supabase_url);
+        
+        let response = self.client
+            .post(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Content-Type", "application/json")
+            .json(&ResendRequest {
+                email: email.to_string(),
+                type_: OtpType::Signup,
+            })
+            .send()
+            .await
+            .context("Failed to send resend verification request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "Resend verification").await);
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn change_password(&self, password: &str) -> Result<()> {
+        self.update_user(
+            UpdateUserRequest {
+                password: Some(password.to_string()),
+                ..Default::default()
+            },
+            "Password change",
+        )
+        .await
+    }
+    
+    /// Request an email change; Supabase confirms it by emailing the new address
+    pub async fn change_email(&self, email: &str) -> Result<()> {
+        self.update_user(
+            UpdateUserRequest {
+                email: Some(email.to_string()),
+                ..Default::default()
+            },
+            "Email change",
+        )
+        .await
+    }
+    
+    async fn update_user(&self, request: UpdateUserRequest, action: &str) -> Result<()> {
+        let token = self.config.auth_token.as_ref()
+            .context("You need to log in first")?;
+            
+        let url = format!("{}/auth/v1/user", self.config.supabase_url);
+        
+        let response = self.client
+            .put(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json")
+            .json(&request)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send {} request", action.to_lowercase()))?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, action).await);
+        }
+        
+        Ok(())
     }
     
     pub async fn login(&self, email: &str, password: &str) -> Result<Config> {
@@ -179,37 +761,57 @@ impl AuthClient {
             .context("Failed to send login request")?;
             
         if !response.status().is_success() {
-            let error: ErrorResponse = response.json().await
-                .context("Failed to parse error response")?;
-                
-            if let Some(desc) = error.get_description() {
-                anyhow::bail!("Login failed: {} - {}", error.get_error(), desc);
-            } else {
-                anyhow::bail!("Login failed: {}", error.get_error());
-            }
+            return Err(ErrorResponse::into_error(response, "Login").await);
         }
         
         let auth_data: AuthResponse = response.json().await
             .context("Failed to parse auth response")?;
             
-        let expiry = Utc::now() + Duration::seconds(auth_data.expires_in);
+        let new_config = self.finish_login(auth_data).await?;
         
-        let mut new_config = self.config.clone();
-        new_config.auth_token = Some(auth_data.access_token);
-        new_config.refresh_token = Some(auth_data.refresh_token);
-        new_config.token_expiry = Some(expiry.timestamp());
+        println!("Login successful!");
+        Ok(new_config)
+    }
+    
+    /// Exchange a PKCE authorization code for a session
+    pub async fn exchange_code(&self, code: &str, verifier: &str) -> Result<Config> {
+        let url = format!("{}/auth/v1/token?grant_type=pkce", self.config.supabase_url);
         
-        new_config.save()?;
+        let response = self.client
+            .post(&url)
+            .header("apikey", &self.config.supabase_anon_key)
+            .header("Content-Type", "application/json")
+            .json(&PkceTokenRequest {
+                auth_code: code.to_string(),
+                code_verifier: verifier.to_string(),
+            })
+            .send()
+            .await
+            .context("Failed to send code exchange request")?;
+            
+        if !response.status().is_success() {
+            return Err(ErrorResponse::into_error(response, "Login").await);
+        }
+        
+        let auth_data: AuthResponse = response.json().await
+            .context("Failed to parse auth response")?;
+            
+        let new_config = self.finish_login(auth_data).await?;
         
         println!("Login successful!");
         Ok(new_config)
     }
     
     pub async fn refresh_token(&self) -> Result<Config> {
-        if self.config.refresh_token.is_none() {
-            anyhow::bail!("No refresh token available");
-        }
-        
+        let auth_data = self.request_refresh().await?;
+        self.store_session(auth_data)
+    }
+    
+    /// Trade the refresh token for a new session without saving it
+    async fn request_refresh(&self) -> Result<AuthResponse> {
+        let refresh_token = self.config.current_refresh_token()?
+            .context("No refresh token available")?;
+            
         let url = format!("{}/auth/v1/token?grant_type=refresh_token", self.config.supabase_url);
         
         let response = self.client
@@ -217,36 +819,24 @@ impl AuthClient {
             .header("apikey", &self.config.supabase_anon_key)
             .header("Content-Type", "application/json")
             .json(&serde_json::json!({
-                "refresh_token": self.config.refresh_token.as_ref().unwrap()
+                "refresh_token": refresh_token
             }))
             .send()
             .await
             .context("Failed to send refresh token request")?;
             
         if !response.status().is_success() {
-            let error: ErrorResponse = response.json().await
-                .context("Failed to parse error response")?;
-                
-            if let Some(desc) = error.get_description() {
-                anyhow::bail!("Token refresh failed: {} - {}", error.get_error(), desc);
-            } else {
-                anyhow::bail!("Token refresh failed: {}", error.get_error());
-            }
+            return Err(ErrorResponse::into_error(response, "Token refresh").await);
         }
         
-        let auth_data: AuthResponse = response.json().await
-            .context("Failed to parse auth response")?;
-            
-        let expiry = Utc::now() + Duration::seconds(auth_data.expires_in);
-        
-        let mut new_config = self.config.clone();
-        new_config.auth_token = Some(auth_data.access_token);
-        new_config.refresh_token = Some(auth_data.refresh_token);
-        new_config.token_expiry = Some(expiry.timestamp());
-        
-        new_config.save()?;
-        
-        Ok(new_config)
+        response.json().await
+            .context("Failed to parse auth response")
+    }
+    
+    /// Refresh the session and save it with the credentials lock already held
+    async fn refresh_holding(&self, lock: &CredentialsLock) -> Result<Config> {
+        let auth_data = self.request_refresh().await?;
+        lock.hold(|| self.store_session(auth_data))
     }
     
     pub async fn logout(&self) -> Result<Config> {
@@ -293,7 +883,7 @@ impl AuthClient {
             .with_prompt("Enter verification code")
             .interact_text()?;
             
-        let new_config = client.verify_otp(&email, &token).await?;
+        let new_config = client.verify_otp(&email, &token, OtpType::Signup).await?;
         Ok(new_config)
     }
     
@@ -301,6 +891,7 @@ impl AuthClient {
         let config = Config::load()?;
         let client = Self::new(config.clone());
         
+        require_terminal()?;
         println!("=== Login to your account ===");
         
         let email: String = Input::new()
@@ -315,30 +906,246 @@ impl AuthClient {
         Ok(new_config)
     }
     
+    /// Log in using flags, environment or `password_command`, prompting only
+    /// for whatever is still missing
+    pub async fn login_with(email: Option<String>, password_stdin: bool) -> Result<Config> {
+        let config = Config::load()?;
+        let client = Self::new(config.clone());
+        
+        if let Some(credentials) = Credentials::resolve(&config, email.clone(), password_stdin)? {
+            return client.login(&credentials.email, &credentials.password).await;
+        }
+        
+        match email {
+            Some(email) => {
+                require_terminal()?;
+                let password: String = Password::new()
+                    .with_prompt(format!("Password for {}", email))
+                    .interact()?;
+                    
+                client.login(&email, &password).await
+            }
+            None => Self::interactive_login().await,
+        }
+    }
+    
+    /// Passwordless login: email a one-time code and verify it
+    pub async fn otp_login(email: Option<String>) -> Result<Config> {
+        let config = Config::load()?;
+        let client = Self::new(config.clone());
+        
+        require_terminal_for("the emailed login code")?;
+        let email = prompt_email(email, &config)?;
+        
+        client.request_otp(&email).await?;
+        
+        println!("A login code has been sent to {}.", email);
+        let token: String = Input::new()
+            .with_prompt("Enter login code")
+            .interact_text()?;
+            
+        client.verify_otp(&email, token.trim(), OtpType::Email).await
+    }
+    
+    /// Sign in through an OAuth/SSO provider using PKCE and a loopback redirect
+    pub async fn provider_login(provider: &str, open_browser: bool) -> Result<Config> {
+        let config = Config::load()?;
+        let client = Self::new(config.clone());
+        
+        let pkce = Pkce::new();
+        let state = oauth::new_state();
+        let (listener, redirect_to) = oauth::bind_loopback().await?;
+        let url = oauth::authorize_url(&config.supabase_url, provider, &redirect_to, &pkce, &state)?;
+        
+        if open_browser && oauth::open_browser(&url) {
+            println!("Opened your browser to sign in with {}.", provider);
+            println!("If nothing happened, open this URL:");
+        } else {
+            println!("Open this URL in your browser to sign in with {}:", provider);
+        }
+        println!("  {}", url);
+        println!("Waiting for the login to complete...");
+        
+        let code = oauth::wait_for_callback(&listener, &state).await?;
+        client.exchange_code(&code, &pkce.verifier).await
+    }
+    
+    /// Log in from a device without a browser by approving it elsewhere
+    pub async fn device_login() -> Result<Config> {
+        let config = Config::load()?;
+        let client = Self::new(config.clone());
+        let flow = DeviceFlow::new(&config.music_server_url, config.request_timeout_secs);
+        
+        let authorization = flow.start().await?;
+        
+        if let Ok(qr) = device::qr_code(authorization.qr_target()) {
+            println!("{}", qr);
+        }
+        println!("To sign in, visit {}", authorization.verification_uri);
+        println!("and enter the code: {}", authorization.user_code.bold());
+        println!("Waiting for approval...");
+        
+        let tokens = flow.poll(&authorization).await?;
+        let new_config = client.store_tokens(tokens.access_token, tokens.refresh_token, tokens.expires_in)?;
+        
+        println!("Login successful!");
+        Ok(new_config)
+    }
+    
+    /// Reset a forgotten password with an emailed recovery code
+    pub async fn interactive_reset_password(email: Option<String>) -> Result<Config> {
+        let config = Config::load()?;
+        let client = Self::new(config.clone());
+        
+        require_terminal()?;
+        let email = prompt_email(email, &config)?;
+        
+        client.recover_password(&email).await?;
+        
+        println!("A recovery code has been sent to {}.", email);
+        let token: String = Input::new()
+            .with_prompt("Enter recovery code")
+            .interact_text()?;
+            
+        // The recovery code signs us in, which authorises the password update
+        let new_config = client.verify_otp(&email, token.trim(), OtpType::Recovery).await?;
+        
+        let password = prompt_new_password()?;
+        Self::new(new_config.clone()).change_password(&password).await?;
+        
+        println!("Password reset successful! You are now logged in.");
+        Ok(new_config)
+    }
+    
+    /// Register a new TOTP factor and confirm it with a first code
+    pub async fn interactive_mfa_enroll(name: Option<String>) -> Result<()> {
+        let config = Self::ensure_authenticated().await?;
+        let client = Self::new(config);
+        
+        // Check before enrolling, so no unverified factor is left behind
+        require_totp_terminal()?;
+        
+        let enrollment = client.enroll_factor(name).await?;
+        
+        println!("Scan this QR code with your authenticator app:");
+        if let Ok(qr) = device::qr_code(&enrollment.totp.uri) {
+            println!("{}", qr);
+        }
+        println!("Or enter the secret manually: {}", enrollment.totp.secret.bold());
+        
+        client.verify_factor(&enrollment.id, &prompt_totp_code()?).await?;
+        
+        println!("Two-factor authentication enabled!");
+        Ok(())
+    }
+    
+    pub async fn interactive_mfa_list() -> Result<()> {
+        let config = Self::ensure_authenticated().await?;
+        let factors = Self::new(config).list_factors().await?;
+        
+        if factors.is_empty() {
+            println!("No MFA factors enrolled.");
+            return Ok(());
+        }
+        
+        for factor in factors {
+            let status = if factor.is_verified() {
+                factor.status.green()
+            } else {
+                factor.status.yellow()
+            };
+            println!("{}  {}  {}", factor.id, factor.label(), status);
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn interactive_mfa_unenroll(factor_id: &str) -> Result<()> {
+        let config = Self::ensure_authenticated().await?;
+        Self::new(config).unenroll_factor(factor_id).await?;
+        
+        println!("Factor {} removed.", factor_id);
+        Ok(())
+    }
+    
+    pub async fn interactive_change_password() -> Result<()> {
+        let config = Self::ensure_authenticated().await?;
+        
+        require_terminal()?;
+        let password = prompt_new_password()?;
+        Self::new(config).change_password(&password).await?;
+        
+        println!("Password changed successfully!");
+        Ok(())
+    }
+    
+    pub async fn interactive_change_email(new_email: &str) -> Result<()> {
+        let config = Self::ensure_authenticated().await?;
+        
+        Self::new(config).change_email(new_email).await?;
+        
+        println!("Email change requested. Follow the confirmation link sent to {} to finish.", new_email);
+        Ok(())
+    }
+    
+    pub async fn interactive_resend_verification(email: Option<String>) -> Result<()> {
+        let config = Config::load()?;
+        let client = Self::new(config.clone());
+        
+        let email = prompt_email(email, &config)?;
+        
+        client.resend_verification(&email).await?;
+        
+        println!("Verification email sent to {}.", email);
+        Ok(())
+    }
+    
     pub async fn ensure_authenticated() -> Result<Config> {
-        let mut config = Config::load()?;
-        
-        if !config.is_authenticated() {
-            if config.refresh_token.is_some() {
-                // Try to refresh the token
-                let client = Self::new(config.clone());
-                match client.refresh_token().await {
-                    Ok(new_config) => {
-                        config = new_config;
-                    }
-                    Err(_) => {
-                        // If refresh fails, clear auth and prompt for login
-                        config.clear_auth()?;
-                        config = Self::interactive_login().await?;
-                    }
+        // Hold the lock from reading the tokens until fresh ones are saved, so two processes
+        // don't both spend the same refresh token
+        let lock = Config::lock_credentials()?;
+        let config = lock.hold(Config::load)?;
+        
+        // A token passed in can't be refreshed, and logging in would replace it
+        if Config::token_overridden()? {
+            if config.token_expiry.is_some_and(|expiry| expiry <= chrono::Utc::now().timestamp()) {
+                anyhow::bail!("The access token from LYNX_FM_TOKEN or --token has expired");
+            }
+            return Ok(config);
+        }
+        
+        if config.is_authenticated() {
+            return Ok(config);
+        }
+        
+        if config.has_refresh_token() {
+            // Try to refresh the token
+            if let Ok(new_config) = Self::new(config.clone()).refresh_holding(&lock).await {
+                return Ok(new_config);
+            }
+            
+            // Tokens saved by another lynx-fm since we read ours may still be good
+            let mut latest = lock.hold(Config::load)?;
+            if latest.is_authenticated() {
+                return Ok(latest);
+            }
+            let replaced = latest.refresh_token != config.refresh_token
+                || latest.sealed_refresh_token != config.sealed_refresh_token;
+            if replaced && latest.has_refresh_token() {
+                if let Ok(new_config) = Self::new(latest.clone()).refresh_holding(&lock).await {
+                    return Ok(new_config);
                 }
-            } else {
-                // No refresh token, prompt for login
-                println!("You need to log in first.");
-                config = Self::interactive_login().await?;
             }
+            
+            // If refresh fails, clear auth and log in again
+            lock.hold(|| latest.clear_auth())?;
+        } else {
+            // No refresh token, log in again
+            println!("You need to log in first.");
         }
         
-        Ok(config)
+        // Logging in waits on the user, so don't keep other processes waiting too
+        drop(lock);
+        Self::login_with(None, false).await
     }
 } 
\ No newline at end of file
@@ -0,0 +1,617 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::music::{Album, Artist, CatalogChanges, Genre, MusicClient, SyncToken, Track};
+
+/// Bumped whenever the schema changes, with a step added to `MIGRATIONS`; kept in `PRAGMA user_version`
+const SCHEMA_VERSION: i32 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE tracks (
+        id TEXT PRIMARY KEY,
+        title TEXT,
+        artist TEXT,
+        album TEXT,
+        genre TEXT,
+        duration REAL,
+        track_number INTEGER,
+        year INTEGER,
+        added_at TEXT,
+        updated_at TEXT,
+        first_sync INTEGER NOT NULL,
+        last_sync INTEGER NOT NULL
+    );
+    CREATE VIRTUAL TABLE tracks_fts USING fts5(
+        id UNINDEXED, title, artist, album, genre,
+        tokenize = 'unicode61 remove_diacritics 2'
+    );
+    CREATE TABLE syncs (
+        id INTEGER PRIMARY KEY,
+        synced_at INTEGER NOT NULL,
+        complete INTEGER NOT NULL,
+        added INTEGER NOT NULL DEFAULT 0,
+        updated INTEGER NOT NULL DEFAULT 0,
+        removed INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE sync_state (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+/// Schema changes indexed by the version they upgrade from; `SCHEMA` is version 1
+const MIGRATIONS: &[&str] = &[
+    "",
+    // Forget the sync cursor so the next sync is a full one that fills in the new columns
+    "ALTER TABLE tracks ADD COLUMN bpm REAL;
+     ALTER TABLE tracks ADD COLUMN rating REAL;
+     DELETE FROM sync_state;",
+];
+
+const TRACK_COLUMNS: &str =
+    "tracks.id, title, artist, album, genre, duration, track_number, year, added_at, updated_at, bpm, rating";
+
+/// Most results a local search returns
+const SEARCH_LIMIT: usize = 500;
+
+/// What a sync changed
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// The server said nothing changed
+    pub unchanged: bool,
+    /// This was the first sync, so everything counts as added
+    pub initial: bool,
+    pub added: Vec<Track>,
+    pub updated: usize,
+    pub removed: usize,
+    /// Tracks in the local catalog afterwards
+    pub total: usize,
+}
+
+/// The track catalog mirrored into a local SQLite database, with a full-text index
+pub struct LocalCatalog {
+    conn: Connection,
+}
+
+impl LocalCatalog {
+    /// The active profile's catalog
+    pub fn open_default() -> Result<Self> {
+        Self::open(&Config::catalog_file(&Config::profile_name()?)?)
+    }
+    
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        
+        let mut conn = Connection::open(path)
+            .with_context(|| format!("Failed to open catalog database {}", path.display()))?;
+            
+        // Create and upgrade in one transaction, so a failed step leaves the old version intact
+        // and a second lynx-fm waits rather than upgrading at the same time
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let version: i32 = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            anyhow::bail!(
+                "Catalog database {} was created by a newer version of lynx-fm",
+                path.display()
+            );
+        }
+        if version == 0 {
+            tx.execute_batch(SCHEMA).context("Failed to create catalog database")?;
+        }
+        if version < SCHEMA_VERSION {
+            for (from, migration) in MIGRATIONS.iter().enumerate().skip(version.max(1) as usize) {
+                tx.execute_batch(migration)
+                    .with_context(|| format!("Failed to upgrade catalog database from version {}", from))?;
+            }
+            tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        tx.commit()?;
+        
+        Ok(Self { conn })
+    }
+    
+    pub fn track_count(&self) -> Result<usize> {
+        let count: i64 = self.conn.query_row("SELECT count(*) FROM tracks", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+    
+    /// When the last sync finished, as a Unix timestamp
+    pub fn last_sync(&self) -> Result<Option<i64>> {
+        Ok(self.conn.query_row("SELECT max(synced_at) FROM syncs", [], |row| row.get(0))?)
+    }
+    
+    /// Validators and cursor for an incremental sync, or `None` before the first sync
+    pub fn sync_token(&self) -> Result<Option<SyncToken>> {
+        if self.last_sync()?.is_none() {
+            return Ok(None);
+        }
+        
+        let state = |key: &str| -> Result<Option<String>> {
+            Ok(self.conn
+                .query_row("SELECT value FROM sync_state WHERE key = ?1", [key], |row| row.get(0))
+                .optional()?)
+        };
+        
+        Ok(Some(SyncToken {
+            etag: state("etag")?,
+            last_modified: state("last_modified")?,
+            cursor: state("cursor")?,
+        }))
+    }
+    
+    /// Store what the server sent. A complete listing also drops tracks it no longer has.
+    pub fn apply(&mut self, changes: CatalogChanges) -> Result<SyncReport> {
+        let tx = self.conn.transaction()?;
+        
+        let initial = tx.query_row("SELECT count(*) = 0 FROM syncs", [], |row| row.get(0))?;
+        let mut report = SyncReport { initial, ..SyncReport::default() };
+        
+        let (tracks, complete, etag, last_modified) = match changes {
+            CatalogChanges::Unchanged => {
+                report.unchanged = true;
+                (Vec::new(), false, None, None)
+            }
+            CatalogChanges::Changed { tracks, complete, etag, last_modified } => (tracks, complete, etag, last_modified),
+        };
+        
+        tx.execute(
+            "INSERT INTO syncs (synced_at, complete) VALUES (?1, ?2)",
+            params![Utc::now().timestamp(), complete],
+        )?;
+        let sync_id = tx.last_insert_rowid();
+        
+        for track in &tracks {
+            let existing = tx
+                .query_row(
+                    &format!("SELECT {} FROM tracks WHERE id = ?1", TRACK_COLUMNS),
+                    [&track.id],
+                    track_from_row,
+                )
+                .optional()?;
+                
+            match existing {
+                None => {
+                    upsert_track(&tx, track, sync_id)?;
+                    report.added.push(track.clone());
+                }
+                Some(old) if old != *track => {
+                    upsert_track(&tx, track, sync_id)?;
+                    report.updated += 1;
+                }
+                Some(_) => {
+                    tx.execute("UPDATE tracks SET last_sync = ?1 WHERE id = ?2", params![sync_id, track.id])?;
+                }
+            }
+        }
+        
+        if complete {
+            tx.execute(
+                "DELETE FROM tracks_fts WHERE id IN (SELECT id FROM tracks WHERE last_sync < ?1)",
+                [sync_id],
+            )?;
+            report.removed = tx.execute("DELETE FROM tracks WHERE last_sync < ?1", [sync_id])?;
+        }
+        
+        tx.execute(
+            "UPDATE syncs SET added = ?1, updated = ?2, removed = ?3 WHERE id = ?4",
+            params![report.added.len(), report.updated, report.removed, sync_id],
+        )?;
+        
+        // A 304 keeps the validators that produced it
+        if !report.unchanged {
+            let cursor: Option<String> = tx.query_row(
+                "SELECT max(coalesce(updated_at, added_at)) FROM tracks",
+                [],
+                |row| row.get(0),
+            )?;
+            set_state(&tx, "etag", etag.as_deref())?;
+            set_state(&tx, "last_modified", last_modified.as_deref())?;
+            set_state(&tx, "cursor", cursor.as_deref())?;
+        }
+        
+        tx.commit().context("Failed to save synced catalog")?;
+        
+        report.total = self.track_count()?;
+        Ok(report)
+    }
+    
+    /// Every synced track
+    pub fn tracks(&self) -> Result<Vec<Track>> {
+        let mut statement = self.conn.prepare(&format!("SELECT {} FROM tracks", TRACK_COLUMNS))?;
+        
+        let tracks = statement
+            .query_map([], track_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tracks)
+    }
+    
+    /// The synced track with this ID, if any
+    pub fn track(&self, id: &str) -> Result<Option<Track>> {
+        let sql = format!("SELECT {} FROM tracks WHERE id = ?1", TRACK_COLUMNS);
+        Ok(self.conn.query_row(&sql, [id], track_from_row).optional()?)
+    }
+    
+    /// Full-text search over title, artist, album and genre.
+    ///
+    /// Every word must match the start of a word in the track; `Artist - Title`
+    /// works because the dash is ignored. Exact title or `Artist - Title` matches come first.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Track>> {
+        let terms: Vec<String> = query
+            .split_whitespace()
+            .filter(|word| *word != "-")
+            .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+            .collect();
+            
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM tracks
+             JOIN (SELECT id AS match_id, bm25(tracks_fts) AS score FROM tracks_fts WHERE tracks_fts MATCH ?1)
+               ON match_id = tracks.id
+             ORDER BY score
+             LIMIT ?2",
+            TRACK_COLUMNS
+        ))?;
+        
+        let mut tracks = statement
+            .query_map(params![terms.join(" "), limit as i64], track_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+            
+        tracks.sort_by_key(|track| !is_exact(track, query));
+        Ok(tracks)
+    }
+    
+    /// Titles, artists and albums starting with `prefix`, for shell completion
+    pub fn complete(&self, prefix: &str, limit: usize) -> Result<Vec<String>> {
+        let pattern = format!(
+            "{}%",
+            prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+        );
+        
+        let mut statement = self.conn.prepare(
+            "SELECT value FROM (
+                 SELECT title AS value FROM tracks
+                 UNION SELECT artist FROM tracks
+                 UNION SELECT album FROM tracks
+             )
+             WHERE value LIKE ?1 ESCAPE '\\'
+             ORDER BY value COLLATE NOCASE
+             LIMIT ?2",
+        )?;
+        
+        let values = statement
+            .query_map(params![pattern, limit as i64], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(values)
+    }
+    
+    /// Tracks that arrived after the first sync and no earlier than `since`, newest first
+    pub fn added_since(&self, since: i64) -> Result<Vec<Track>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM tracks
+             JOIN syncs ON syncs.id = tracks.first_sync
+             WHERE syncs.synced_at >= ?1 AND syncs.id > (SELECT min(id) FROM syncs)
+             ORDER BY syncs.id DESC, artist, album, track_number, title",
+            TRACK_COLUMNS
+        ))?;
+        
+        let tracks = statement
+            .query_map([since], track_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tracks)
+    }
+}
+
+fn track_from_row(row: &Row) -> rusqlite::Result<Track> {
+    Ok(Track {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        artist: row.get(2)?,
+        album: row.get(3)?,
+        genre: row.get(4)?,
+        duration: row.get(5)?,
+        track_number: row.get(6)?,
+        year: row.get(7)?,
+        added_at: row.get(8)?,
+        updated_at: row.get(9)?,
+        bpm: row.get(10)?,
+        rating: row.get(11)?,
+    })
+}
+
+fn upsert_track(tx: &Transaction, track: &Track, sync_id: i64) -> Result<()> {
+    tx.execute(
+        "INSERT INTO tracks (id, title, artist, album, genre, duration, track_number, year,
+                             added_at, updated_at, bpm, rating, first_sync, last_sync)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13)
+         ON CONFLICT (id) DO UPDATE SET
+             title = excluded.title, artist = excluded.artist, album = excluded.album,
+             genre = excluded.genre, duration = excluded.duration,
+             track_number = excluded.track_number, year = excluded.year,
+             added_at = excluded.added_at, updated_at = excluded.updated_at,
+             bpm = excluded.bpm, rating = excluded.rating,
+             last_sync = excluded.last_sync",
+        params![
+            track.id,
+            track.title,
+            track.artist,
+            track.album,
+            track.genre,
+            track.duration,
+            track.track_number,
+            track.year,
+            track.added_at,
+            track.updated_at,
+            track.bpm,
+            track.rating,
+            sync_id,
+        ],
+    )?;
+    
+    tx.execute("DELETE FROM tracks_fts WHERE id = ?1", [&track.id])?;
+    tx.execute(
+        "INSERT INTO tracks_fts (id, title, artist, album, genre) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![track.id, track.title, track.artist, track.album, track.genre],
+    )?;
+    
+    Ok(())
+}
+
+fn set_state(tx: &Transaction, key: &str, value: Option<&str>) -> Result<()> {
+    match value {
+        Some(value) => tx.execute(
+            "INSERT OR REPLACE INTO sync_state (key, value) VALUES (?1, ?2)",
+            [key, value],
+        )?,
+        None => tx.execute("DELETE FROM sync_state WHERE key = ?1", [key])?,
+    };
+    Ok(())
+}
+
+/// Bring the local catalog up to date, incrementally unless `full` is set
+pub async fn sync(client: &MusicClient, catalog: &mut LocalCatalog, full: bool) -> Result<SyncReport> {
+    let token = if full { None } else { catalog.sync_token()? };
+    let changes = client.catalog_changes(token.as_ref()).await?;
+    catalog.apply(changes)
+}
+
+/// Search with the server's search endpoint, falling back to the synced catalog
+pub async fn search(client: &MusicClient, query: &str) -> Result<Vec<Track>> {
+    if let Some(results) = search_without_sync(client, query).await? {
+        return Ok(results);
+    }
+    sync_and_search(client, query).await
+}
+
+/// Like [`search`], but `None` rather than syncing the whole catalog when the server can't search
+pub async fn search_without_sync(client: &MusicClient, query: &str) -> Result<Option<Vec<Track>>> {
+    let server_error = match client.search_tracks(query).await {
+        Ok(Some(tracks)) => return Ok(Some(tracks)),
+        Ok(None) => None,
+        // Only an unreachable server means offline; anything else it answered is a real error
+        Err(e) if is_connection_error(&e) => Some(e),
+        Err(e) => return Err(e),
+    };
+    
+    let catalog = LocalCatalog::open_default()?;
+    if catalog.last_sync()?.is_some() {
+        return catalog.search(query, SEARCH_LIMIT).map(Some);
+    }
+    
+    match server_error {
+        // Offline and never synced: nothing to search
+        Some(e) => Err(e.context("No synced catalog for offline search; run `lynx-fm sync` while online")),
+        None => Ok(None),
+    }
+}
+
+/// Online without a search endpoint: sync once and search locally from then on
+pub async fn sync_and_search(client: &MusicClient, query: &str) -> Result<Vec<Track>> {
+    let mut catalog = LocalCatalog::open_default()?;
+    sync(client, &mut catalog, false).await?;
+    catalog.search(query, SEARCH_LIMIT)
+}
+
+/// Whether the server couldn't be reached at all, as opposed to answering with an error
+pub fn is_connection_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+    })
+}
+
+/// Every track from the server, or from the synced catalog when the server can't be reached
+pub async fn all_tracks(client: &MusicClient) -> Result<Vec<Track>> {
+    match client.list_tracks().await {
+        Ok(tracks) => Ok(tracks),
+        Err(e) if !is_connection_error(&e) => Err(e),
+        Err(e) => {
+            let local = LocalCatalog::open_default()?;
+            if local.last_sync()?.is_none() {
+                return Err(e.context("No synced catalog to use offline; run `lynx-fm sync` while online"));
+            }
+            local.tracks()
+        }
+    }
+}
+
+pub async fn artists(client: &MusicClient) -> Result<Vec<Artist>> {
+    let listing = client.list_artists().await;
+    server_or_derived(client, listing, group_artists).await
+}
+
+/// Albums, optionally only those by artists whose name contains `artist`
+pub async fn albums(client: &MusicClient, artist: Option<&str>) -> Result<Vec<Album>> {
+    let listing = client.list_albums(artist).await;
+    let albums = server_or_derived(client, listing, group_albums).await?;
+    
+    // Servers may ignore the artist filter
+    Ok(match artist {
+        Some(artist) => albums
+            .into_iter()
+            .filter(|album| contains(album.artist.as_deref(), artist))
+            .collect(),
+        None => albums,
+    })
+}
+
+pub async fn genres(client: &MusicClient) -> Result<Vec<Genre>> {
+    let listing = client.list_genres().await;
+    server_or_derived(client, listing, group_genres).await
+}
+
+/// Prefer the server's listing; without the endpoint, or offline, derive it from the tracks.
+///
+/// Errors the server answers with, such as 401 or 500, are reported rather than hidden.
+async fn server_or_derived<T>(
+    client: &MusicClient,
+    listing: Result<Option<Vec<T>>>,
+    derive: fn(&[Track]) -> Vec<T>,
+) -> Result<Vec<T>> {
+    match listing {
+        Ok(Some(items)) => Ok(items),
+        Ok(None) => Ok(derive(&all_tracks(client).await?)),
+        Err(e) if !is_connection_error(&e) => Err(e),
+        Err(e) => {
+            let local = LocalCatalog::open_default()?;
+            if local.last_sync()?.is_none() {
+                return Err(e.context("No synced catalog to browse offline; run `lynx-fm sync` while online"));
+            }
+            Ok(derive(&local.tracks()?))
+        }
+    }
+}
+
+pub fn group_artists(tracks: &[Track]) -> Vec<Artist> {
+    let mut artists: BTreeMap<String, (Artist, BTreeSet<&str>)> = BTreeMap::new();
+    
+    for track in tracks {
+        let Some(name) = &track.artist else { continue };
+        let (artist, albums) = artists.entry(name.to_lowercase()).or_insert_with(|| {
+            (Artist { name: name.clone(), albums: Some(0), tracks: Some(0) }, BTreeSet::new())
+        });
+        
+        *artist.tracks.get_or_insert(0) += 1;
+        if let Some(album) = &track.album {
+            albums.insert(album);
+        }
+        artist.albums = Some(albums.len());
+    }
+    
+    artists.into_values().map(|(artist, _)| artist).collect()
+}
+
+pub fn group_albums(tracks: &[Track]) -> Vec<Album> {
+    let mut albums: BTreeMap<(String, String), Album> = BTreeMap::new();
+    
+    for track in tracks {
+        let Some(title) = &track.album else { continue };
+        let artist = track.artist.as_deref().unwrap_or("");
+        let album = albums
+            .entry((artist.to_lowercase(), title.to_lowercase()))
+            .or_insert_with(|| Album {
+                title: title.clone(),
+                artist: track.artist.clone(),
+                tracks: Some(0),
+                ..Album::default()
+            });
+            
+        *album.tracks.get_or_insert(0) += 1;
+        album.year = album.year.max(track.year);
+    }
+    
+    // By artist, then oldest album first
+    let mut albums: Vec<Album> = albums.into_values().collect();
+    albums.sort_by_key(|album| {
+        (
+            album.artist.as_deref().unwrap_or("").to_lowercase(),
+            album.year.is_none(),
+            album.year,
+            album.title.to_lowercase(),
+        )
+    });
+    albums
+}
+
+pub fn group_genres(tracks: &[Track]) -> Vec<Genre> {
+    let mut genres: BTreeMap<String, Genre> = BTreeMap::new();
+    
+    for track in tracks {
+        let Some(name) = &track.genre else { continue };
+        let genre = genres
+            .entry(name.to_lowercase())
+            .or_insert_with(|| Genre { name: name.clone(), tracks: Some(0) });
+        *genre.tracks.get_or_insert(0) += 1;
+    }
+    
+    genres.into_values().collect()
+}
+
+/// Tracks by an artist and/or on an album, in album order.
+///
+/// Names match case-insensitively; exact matches win over partial ones, so
+/// `--album Substance` doesn't also pick up "Substance 1987".
+pub fn tracks_for(tracks: Vec<Track>, artist: Option<&str>, album: Option<&str>) -> Vec<Track> {
+    let mut tracks = tracks;
+    if let Some(artist) = artist {
+        tracks = select_named(tracks, |track| track.artist.as_deref(), artist);
+    }
+    if let Some(album) = album {
+        tracks = select_named(tracks, |track| track.album.as_deref(), album);
+    }
+    
+    tracks.sort_by_key(|track| {
+        (
+            track.year.is_none(),
+            track.year,
+            track.album.as_deref().unwrap_or("").to_lowercase(),
+            track.track_number.is_none(),
+            track.track_number,
+            track.display_title().to_lowercase(),
+        )
+    });
+    tracks
+}
+
+fn select_named(tracks: Vec<Track>, field: fn(&Track) -> Option<&str>, wanted: &str) -> Vec<Track> {
+    let exact = |track: &Track| field(track).is_some_and(|value| value.eq_ignore_ascii_case(wanted));
+    
+    if tracks.iter().any(exact) {
+        tracks.into_iter().filter(exact).collect()
+    } else {
+        tracks.into_iter().filter(|track| contains(field(track), wanted)).collect()
+    }
+}
+
+fn contains(value: Option<&str>, wanted: &str) -> bool {
+    value.is_some_and(|value| value.to_lowercase().contains(&wanted.to_lowercase()))
+}
+
+/// Pick the track a query means: an exact match, or the only result
+pub fn resolve_exact<'a>(results: &'a [Track], query: &str) -> Option<&'a Track> {
+    let exact: Vec<&Track> = results.iter().filter(|track| is_exact(track, query)).collect();
+    
+    match (exact.as_slice(), results) {
+        ([track], _) => Some(track),
+        ([], [only]) => Some(only),
+        _ => None,
+    }
+}
+
+fn is_exact(track: &Track, query: &str) -> bool {
+    let query = query.trim().to_lowercase();
+    
+    track.id.to_lowercase() == query
+        || track.label().to_lowercase() == query
+        || track.display_title().to_lowercase() == query
+}
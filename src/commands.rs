@@ -1,48 +1,295 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
+use serde_json::{Map, Value};
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::display::{parse_span, OutputFormat};
+use crate::history::parse_since;
+use crate::music::TrackSort;
+use crate::playlist::PlaylistFormat;
+use crate::smart::parse_length;
+use crate::stats::ReportFormat;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Lynx.fm CLI - Stream music from your Lynx.fm server", long_about = None)]
 pub struct Cli {
+    /// Use a named profile instead of the active one
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    
+    /// Override the Lynx.fm server URL for this run (saved by `config`)
+    #[arg(long, global = true)]
+    pub server_url: Option<String>,
+    
+    /// Override the Supabase URL for this run (saved by `config`)
+    #[arg(long, global = true)]
+    pub supabase_url: Option<String>,
+    
+    /// Override the Supabase anonymous key for this run (saved by `config`)
+    #[arg(long, global = true)]
+    pub supabase_key: Option<String>,
+    
+    /// Use this access token for this run
+    #[arg(long, global = true)]
+    pub token: Option<String>,
+    
+    /// Override a setting for this run (e.g. -c music_server_url=http://localhost:3500)
+    #[arg(short = 'c', long = "override", global = true, value_parser = parse_key_value)]
+    pub overrides: Vec<(String, String)>,
+    
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
-    /// Configure the CLI with Supabase and server URLs
+    /// View and change settings (`config get/set/unset/list/edit/path`)
+    #[command(args_conflicts_with_subcommands = true)]
     Config {
-        /// Supabase URL
+        #[command(subcommand)]
+        action: Option<ConfigCommands>,
+        
+        /// Email used for non-interactive logins
         #[arg(long)]
-        supabase_url: Option<String>,
+        email: Option<String>,
         
-        /// Supabase anonymous key
+        /// Shell command that prints your password (e.g. "pass show lynx")
         #[arg(long)]
-        supabase_key: Option<String>,
+        password_command: Option<String>,
         
-        /// Lynx.fm server URL
+        /// Show every setting and where its value comes from
         #[arg(long)]
-        server_url: Option<String>,
+        show_origin: bool,
+    },
+    
+    /// Manage named profiles for different servers and accounts
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
     },
     
     /// Sign up for a new account
     Signup,
     
     /// Log in to your account
-    Login,
+    Login {
+        /// Account email (defaults to LYNX_FM_EMAIL or the configured email)
+        #[arg(long)]
+        email: Option<String>,
+        
+        /// Read the password from stdin instead of prompting
+        #[arg(long)]
+        password_stdin: bool,
+        
+        /// Sign in with a one-time code sent by email instead of a password
+        #[arg(long, conflicts_with = "password_stdin")]
+        otp: bool,
+        
+        /// Sign in through an OAuth/SSO provider (e.g. github, google)
+        #[arg(long, conflicts_with_all = ["password_stdin", "otp", "email"])]
+        provider: Option<String>,
+        
+        /// Print the provider login URL instead of opening a browser
+        #[arg(long, requires = "provider")]
+        no_browser: bool,
+        
+        /// Approve the login from another device (shows a code and QR code)
+        #[arg(long, conflicts_with_all = ["password_stdin", "otp", "email", "provider"])]
+        device: bool,
+    },
     
     /// Log out from your account
     Logout,
     
+    /// Show who you are logged in as
+    Whoami,
+    
+    /// Manage your account
+    Account {
+        #[command(subcommand)]
+        command: AccountCommands,
+    },
+    
     /// Check if the server is healthy
     Health,
     
-    /// Play a random track
-    Random,
+    /// Diagnose config, network, login and audio problems
+    Doctor,
     
-    /// Play a specific track
+    /// Play a random track, optionally limited by genre, artist, length and recent plays
+    Random {
+        /// Only tracks whose genre contains this (case-insensitive)
+        #[arg(long)]
+        genre: Option<String>,
+        
+        /// Only tracks whose artist contains this (case-insensitive)
+        #[arg(long)]
+        artist: Option<String>,
+        
+        /// Only tracks at least this long, e.g. 3m or 3:30
+        #[arg(long, value_parser = parse_length)]
+        min_duration: Option<i64>,
+        
+        /// Skip tracks played within this span, e.g. 24h or 7d
+        #[arg(long, value_parser = parse_span)]
+        exclude_played: Option<i64>,
+        
+        /// Favour highly rated and rarely played tracks
+        #[arg(long)]
+        weighted: bool,
+    },
+    
+    /// Play a track by ID or by search query, e.g. "artist - title"
     Play {
-        /// Track ID to play
-        track_id: String,
+        /// Track ID or search query
+        #[arg(required_unless_present_any = ["album", "artist"])]
+        target: Option<String>,
+        
+        /// Treat the target as a track ID without searching
+        #[arg(long, conflicts_with_all = ["album", "artist"])]
+        id: bool,
+        
+        /// Queue every track on this album, in album order
+        #[arg(long, conflicts_with = "target")]
+        album: Option<String>,
+        
+        /// Queue every track by this artist, album by album
+        #[arg(long, conflicts_with = "target")]
+        artist: Option<String>,
+    },
+    
+    /// Search titles, artists and albums
+    Search {
+        /// Words to look for
+        query: String,
+        
+        /// Show at most this many tracks
+        #[arg(long)]
+        limit: Option<usize>,
+        
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    
+    /// List artists in the catalog
+    Artists {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    
+    /// List albums in the catalog
+    Albums {
+        /// Only albums by artists whose name contains this text
+        #[arg(long)]
+        artist: Option<String>,
+        
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    
+    /// List genres in the catalog
+    Genres {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    
+    /// Manage playlists stored on the server, or locally with --local
+    Playlist {
+        /// Use playlists kept on this machine instead of the server's
+        #[arg(long, global = true)]
+        local: bool,
+        
+        #[command(subcommand)]
+        command: PlaylistCommands,
+    },
+    
+    /// Manage smart playlists, which pick tracks from the synced catalog by rule
+    Smart {
+        #[command(subcommand)]
+        command: SmartCommands,
+    },
+    
+    /// Mirror the track catalog locally for offline search
+    Sync {
+        /// Fetch everything instead of only what changed, dropping tracks the server no longer has
+        #[arg(long)]
+        full: bool,
+    },
+    
+    /// Show tracks that recent syncs added to the catalog
+    WhatsNew {
+        /// How many days back to look
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+        
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    
+    /// Show what has been played on this machine, newest first
+    History {
+        /// Only plays since a date (2026-10-01) or within a span (24h, 7d)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+        
+        /// Show at most this many plays
+        #[arg(long)]
+        limit: Option<usize>,
+        
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        
+        /// Write the plays to a .csv or .json file instead
+        #[arg(long, value_name = "FILE")]
+        export: Option<PathBuf>,
+    },
+    
+    /// Summarise the listening history: top tracks, artists and genres, time, skips and hours
+    Stats {
+        /// Only plays since a date (2026-10-01) or within a span (24h, 30d)
+        #[arg(long, value_parser = parse_since, conflicts_with = "year")]
+        since: Option<DateTime<Utc>>,
+        
+        /// Only plays before a date or span
+        #[arg(long, value_parser = parse_since, conflicts_with = "year")]
+        until: Option<DateTime<Utc>>,
+        
+        /// Only plays in this calendar year, for a year in review
+        #[arg(long)]
+        year: Option<i32>,
+        
+        /// How many tracks, artists and genres to rank
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        
+        /// Write a shareable report instead of printing
+        #[arg(long, value_enum)]
+        report: Option<ReportFormat>,
+        
+        /// Where to write the report (defaults to lynx-fm-<period>.html or .md)
+        #[arg(long, value_name = "FILE", requires = "report")]
+        output: Option<PathBuf>,
+    },
+    
+    /// Print synced titles, artists and albums starting with a prefix (for shell completion)
+    #[command(hide = true)]
+    Complete {
+        #[arg(default_value = "")]
+        prefix: String,
+    },
+    
+    /// Browse the track catalog
+    Tracks {
+        #[command(subcommand)]
+        command: TrackCommands,
     },
     
     /// Prefetch tracks for faster playback
@@ -50,4 +297,368 @@ pub enum Commands {
         /// Track IDs to prefetch
         track_ids: Vec<String>,
     },
+    
+    /// Make an authenticated request to the Lynx.fm server
+    Api {
+        /// HTTP method (GET, POST, PUT, PATCH, DELETE)
+        method: String,
+        
+        /// Endpoint path, resolved against the server URL (e.g. /me)
+        path: String,
+        
+        /// JSON request body
+        #[arg(long, short, value_parser = parse_json)]
+        data: Option<serde_json::Value>,
+        
+        /// Extra request header as key=value, replacing any default of that name (can be repeated)
+        #[arg(long = "header", short = 'H', value_parser = parse_key_value)]
+        headers: Vec<(String, String)>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TrackCommands {
+    /// List tracks in the catalog
+    List {
+        /// Sort order
+        #[arg(long, value_enum)]
+        sort: Option<TrackSort>,
+        
+        /// Only tracks whose artist contains this text
+        #[arg(long)]
+        artist: Option<String>,
+        
+        /// Only tracks whose album contains this text
+        #[arg(long)]
+        album: Option<String>,
+        
+        /// Show at most this many tracks
+        #[arg(long)]
+        limit: Option<usize>,
+        
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PlaylistCommands {
+    /// List your playlists
+    List {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    
+    /// Show the tracks in a playlist
+    Show {
+        /// Playlist name or ID
+        playlist: String,
+        
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    
+    /// Create an empty playlist
+    Create {
+        name: String,
+        
+        #[arg(long)]
+        description: Option<String>,
+    },
+    
+    /// Rename a playlist
+    Rename {
+        /// Playlist name or ID
+        playlist: String,
+        
+        /// New name
+        name: String,
+    },
+    
+    /// Delete a playlist
+    Delete {
+        /// Playlist name or ID
+        playlist: String,
+    },
+    
+    /// Append tracks to a playlist
+    Add {
+        /// Playlist name or ID
+        playlist: String,
+        
+        /// Track IDs to add
+        #[arg(required = true)]
+        track_ids: Vec<String>,
+    },
+    
+    /// Remove tracks from a playlist
+    Remove {
+        /// Playlist name or ID
+        playlist: String,
+        
+        /// Track IDs to remove
+        #[arg(required = true)]
+        track_ids: Vec<String>,
+    },
+    
+    /// Move a track to another position
+    Reorder {
+        /// Playlist name or ID
+        playlist: String,
+        
+        /// Current position of the track (1-based)
+        from: usize,
+        
+        /// Position to move it to (1-based)
+        to: usize,
+    },
+    
+    /// Play a playlist from the start
+    Play {
+        /// Playlist name or ID
+        playlist: String,
+        
+        /// Play in random order
+        #[arg(long)]
+        shuffle: bool,
+    },
+    
+    /// Create a playlist from an M3U, M3U8, PLS or XSPF file
+    Import {
+        file: PathBuf,
+        
+        /// Playlist name (defaults to the file's title or name)
+        #[arg(long)]
+        name: Option<String>,
+        
+        /// File format (defaults to the extension)
+        #[arg(long, value_enum)]
+        format: Option<PlaylistFormat>,
+    },
+    
+    /// Write a playlist to an M3U, M3U8, PLS or XSPF file
+    Export {
+        /// Playlist name or ID
+        playlist: String,
+        
+        file: PathBuf,
+        
+        /// File format (defaults to the extension)
+        #[arg(long, value_enum)]
+        format: Option<PlaylistFormat>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SmartCommands {
+    /// Save a rule such as 'genre:techno AND bpm>125 AND NOT played:7d'
+    Create {
+        name: String,
+        
+        rule: String,
+        
+        /// Track order (defaults to artist)
+        #[arg(long, value_enum)]
+        sort: Option<TrackSort>,
+        
+        /// Keep at most this many tracks
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    
+    /// List smart playlists with how many tracks each matches now
+    List,
+    
+    /// Show the tracks a smart playlist matches now
+    Show {
+        name: String,
+        
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    
+    /// Play the tracks a smart playlist matches now
+    Play {
+        name: String,
+        
+        /// Play in random order
+        #[arg(long)]
+        shuffle: bool,
+    },
+    
+    /// Delete a smart playlist
+    Delete {
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// Print the effective value of a setting
+    Get {
+        key: String,
+    },
+    
+    /// Set a setting in the active profile
+    Set {
+        key: String,
+        value: String,
+    },
+    
+    /// Remove a setting from the active profile
+    Unset {
+        key: String,
+    },
+    
+    /// List every setting with its value and description
+    List,
+    
+    /// Open the active config file in $VISUAL or $EDITOR
+    Edit,
+    
+    /// Print the path of the active config file
+    Path,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommands {
+    /// Create a profile; unset values are inherited from the main config
+    Add {
+        /// Profile name
+        name: String,
+        
+        /// Supabase URL
+        #[arg(long)]
+        supabase_url: Option<String>,
+        
+        /// Supabase anonymous key
+        #[arg(long)]
+        supabase_key: Option<String>,
+        
+        /// Lynx.fm server URL
+        #[arg(long)]
+        server_url: Option<String>,
+        
+        /// Switch to the new profile
+        #[arg(long = "use")]
+        activate: bool,
+    },
+    
+    /// List profiles
+    List,
+    
+    /// Switch the active profile
+    Use {
+        /// Profile name
+        name: String,
+    },
+    
+    /// Remove a profile and its stored tokens and cache
+    Remove {
+        /// Profile name
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AccountCommands {
+    /// Reset a forgotten password using an emailed recovery code
+    ResetPassword {
+        /// Account email
+        #[arg(long)]
+        email: Option<String>,
+    },
+    
+    /// Change the password of the logged-in account
+    ChangePassword,
+    
+    /// Change the email address of the logged-in account
+    ChangeEmail {
+        /// New email address
+        new_email: String,
+    },
+    
+    /// Send the signup verification email again
+    ResendVerification {
+        /// Account email
+        #[arg(long)]
+        email: Option<String>,
+    },
+    
+    /// Manage multi-factor authentication
+    Mfa {
+        #[command(subcommand)]
+        command: MfaCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MfaCommands {
+    /// Enroll a TOTP authenticator app
+    Enroll {
+        /// Friendly name for the factor
+        #[arg(long)]
+        name: Option<String>,
+    },
+    
+    /// List enrolled factors
+    List,
+    
+    /// Remove a factor
+    Unenroll {
+        /// Factor ID (see `account mfa list`)
+        factor_id: String,
+    },
+}
+
+impl Cli {
+    /// Config overrides given on the command line, keyed by field name
+    pub fn config_overrides(&self) -> Result<Map<String, Value>> {
+        let mut overrides = Map::new();
+        
+        for (field, raw) in &self.overrides {
+            overrides.insert(field.clone(), Config::parse_override(field, raw)?);
+        }
+        
+        // Dedicated flags win over the generic -c form. Plain `config --server-url ...`
+        // saves the URLs and key instead, so they aren't overrides there.
+        let saving = matches!(self.command, Commands::Config { action: None, show_origin: false, .. });
+        let flags = [
+            ("music_server_url", &self.server_url, !saving),
+            ("supabase_url", &self.supabase_url, !saving),
+            ("supabase_anon_key", &self.supabase_key, !saving),
+            ("auth_token", &self.token, true),
+        ];
+        
+        for (field, value, _) in flags.iter().filter(|(_, _, applies)| *applies) {
+            if let Some(value) = value {
+                overrides.insert(field.to_string(), Value::String(value.clone()));
+            }
+        }
+        
+        Ok(overrides)
+    }
+}
+
+/// Parse a `key=value` argument into its two halves
+pub fn parse_key_value(s: &str) -> Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .with_context(|| format!("Invalid key=value pair: '{}'", s))?;
+    
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("Missing key in '{}'", s);
+    }
+    
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+/// Parse a JSON argument, so malformed bodies are rejected before any request is sent
+pub fn parse_json(s: &str) -> Result<serde_json::Value> {
+    serde_json::from_str(s).context("Invalid JSON")
 } 
\ No newline at end of file
@@ -1,75 +1,1026 @@
 use anyhow::{Context, Result};
 use dirs::home_dir;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
+use crate::auth::TokenClaims;
+use crate::credentials;
+use crate::files;
+
+/// Current layout of `config.json`; bump it and add a step to `MIGRATIONS` when fields change
+pub const CONFIG_VERSION: u32 = 1;
+
+// Missing fields take their default so files written by older versions still load
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
+    /// Layout version the file was written with
+    pub version: u32,
     pub supabase_url: String,
     pub supabase_anon_key: String,
     pub music_server_url: String,
     pub auth_token: Option<String>,
     pub refresh_token: Option<String>,
     pub token_expiry: Option<i64>,
+    /// Refresh token encrypted with a passphrase, used instead of `refresh_token`
+    /// when `encrypt_refresh_token` is on
+    pub sealed_refresh_token: Option<String>,
+    /// Email used for non-interactive logins
+    pub email: Option<String>,
+    /// Shell command that prints the account password (e.g. `pass show lynx`)
+    pub password_command: Option<String>,
+    /// Playback volume, where 1.0 is unchanged
+    pub volume: f32,
+    /// Name of the audio output device; the system default when unset
+    pub audio_device: Option<String>,
+    /// Timeout for server and Supabase requests, in seconds
+    pub request_timeout_secs: u64,
+    /// Color output: `auto`, `color` or `plain`
+    pub theme: String,
+    /// Keep the refresh token encrypted at rest with a passphrase
+    pub encrypt_refresh_token: bool,
+}
+
+/// One step of the migration chain, upgrading a file from version `i` to `i + 1`
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// Migrations indexed by the version they upgrade from
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+/// Files from before versioning (including `~/.music-cli`) share the v1 field names
+fn migrate_v0_to_v1(_layer: &mut Map<String, Value>) -> Result<()> {
+    Ok(())
+}
+
+/// A user-facing setting, as exposed by `lynx-fm config get/set`
+pub struct Setting {
+    pub key: &'static str,
+    pub description: &'static str,
+    validate: fn(&Value) -> Result<()>,
+}
+
+/// Every setting that can be changed with `lynx-fm config set`.
+///
+/// Tokens are not listed here; they are managed by `login` and `logout`.
+pub const SETTINGS: &[Setting] = &[
+    Setting { key: "music_server_url", description: "Lynx.fm server URL", validate: validate_url },
+    Setting { key: "supabase_url", description: "Supabase project URL", validate: validate_url },
+    Setting { key: "supabase_anon_key", description: "Supabase anonymous key", validate: validate_non_empty },
+    Setting { key: "email", description: "Email used for non-interactive logins", validate: validate_email },
+    Setting { key: "password_command", description: "Command that prints your password", validate: validate_non_empty },
+    Setting { key: "volume", description: "Playback volume (0.0 - 2.0)", validate: validate_volume },
+    Setting { key: "audio_device", description: "Audio output device name", validate: validate_non_empty },
+    Setting { key: "request_timeout_secs", description: "Request timeout in seconds (1 - 600)", validate: validate_timeout },
+    Setting { key: "theme", description: "Color output: auto, color or plain", validate: validate_theme },
+    Setting { key: "encrypt_refresh_token", description: "Encrypt the refresh token with a passphrase", validate: validate_any },
+];
+
+impl Setting {
+    pub fn find(key: &str) -> Result<&'static Setting> {
+        SETTINGS
+            .iter()
+            .find(|setting| setting.key == key)
+            .with_context(|| {
+                let keys: Vec<&str> = SETTINGS.iter().map(|s| s.key).collect();
+                format!("Unknown setting '{}'. Available settings: {}", key, keys.join(", "))
+            })
+    }
+    
+    /// Check a value for this setting; `null` (unset) is always allowed
+    pub fn validate(&self, value: &Value) -> Result<()> {
+        if value.is_null() {
+            return Ok(());
+        }
+        
+        (self.validate)(value).with_context(|| format!("Invalid value for {}", self.key))
+    }
+}
+
+fn validate_any(_: &Value) -> Result<()> {
+    Ok(())
+}
+
+fn validate_non_empty(value: &Value) -> Result<()> {
+    if value.as_str().is_some_and(|s| s.trim().is_empty()) {
+        anyhow::bail!("value must not be empty");
+    }
+    Ok(())
+}
+
+fn validate_url(value: &Value) -> Result<()> {
+    let raw = value.as_str().context("expected a URL")?;
+    let url = reqwest::Url::parse(raw).with_context(|| format!("'{}' is not a valid URL", raw))?;
+    
+    if url.scheme() != "http" && url.scheme() != "https" {
+        anyhow::bail!("'{}' must use http or https", raw);
+    }
+    Ok(())
+}
+
+fn validate_email(value: &Value) -> Result<()> {
+    let raw = value.as_str().context("expected an email address")?;
+    if !raw.contains('@') {
+        anyhow::bail!("'{}' is not an email address", raw);
+    }
+    Ok(())
+}
+
+fn validate_volume(value: &Value) -> Result<()> {
+    let volume = value.as_f64().context("expected a number")?;
+    if !(0.0..=2.0).contains(&volume) {
+        anyhow::bail!("volume must be between 0.0 and 2.0");
+    }
+    Ok(())
+}
+
+fn validate_timeout(value: &Value) -> Result<()> {
+    let secs = value.as_u64().context("expected a whole number of seconds")?;
+    if !(1..=600).contains(&secs) {
+        anyhow::bail!("timeout must be between 1 and 600 seconds");
+    }
+    Ok(())
+}
+
+fn validate_theme(value: &Value) -> Result<()> {
+    match value.as_str() {
+        Some("auto" | "color" | "plain") => Ok(()),
+        _ => anyhow::bail!("theme must be one of: auto, color, plain"),
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             supabase_url: "https://your-project.supabase.co".to_string(),
             supabase_anon_key: "your-anon-key".to_string(),
             music_server_url: "https://server.lg.media".to_string(),
             auth_token: None,
             refresh_token: None,
             token_expiry: None,
+            sealed_refresh_token: None,
+            email: None,
+            password_command: None,
+            volume: 1.0,
+            audio_device: None,
+            request_timeout_secs: 30,
+            theme: "auto".to_string(),
+            encrypt_refresh_token: false,
+        }
+    }
+}
+
+/// Settings as stored in one file, where any field may be missing
+type Layer = Map<String, Value>;
+
+/// Name of the profile backed by `config.json` itself
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Fields that belong to a single profile and are never inherited from `config.json`
+const PROFILE_SCOPED_FIELDS: &[&str] = &["auth_token", "refresh_token", "token_expiry", "sealed_refresh_token"];
+
+/// Profile chosen with the global `--profile` flag for this process
+static PROFILE_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Settings given on the command line for this process
+static FLAG_OVERRIDES: OnceLock<Map<String, Value>> = OnceLock::new();
+
+/// Directory name under the platform config, state and cache directories
+const APP_DIR: &str = "lynx-fm";
+
+/// Environment variable that puts the whole tree under one directory
+pub const HOME_ENV: &str = "LYNX_FM_HOME";
+
+/// Prefix for environment variables that override config fields
+pub const ENV_PREFIX: &str = "LYNX_FM_";
+
+/// Short environment variable names for the most common fields
+const ENV_ALIASES: &[(&str, &str)] = &[
+    ("LYNX_FM_SERVER_URL", "music_server_url"),
+    ("LYNX_FM_SUPABASE_KEY", "supabase_anon_key"),
+    ("LYNX_FM_TOKEN", "auth_token"),
+];
+
+/// Fields without a `Setting` entry that can still be overridden for a run
+const OVERRIDE_ONLY_FIELDS: &[&str] = &["auth_token"];
+
+/// Where the effective value of a setting came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    Profile(String, PathBuf),
+    Env(String),
+    Flag,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "file {}", path.display()),
+            Origin::Profile(name, path) => write!(f, "profile '{}' {}", name, path.display()),
+            Origin::Env(var) => write!(f, "env {}", var),
+            Origin::Flag => write!(f, "command line"),
         }
     }
 }
 
 impl Config {
+    /// Settings and profiles: `$XDG_CONFIG_HOME/lynx-fm`, or `$LYNX_FM_HOME` itself
     pub fn config_dir() -> Result<PathBuf> {
-        let mut dir = home_dir().context("Could not find home directory")?;
-        dir.push(".lynx-fm");
+        let dir = match Self::home_override() {
+            Some(home) => home,
+            None => dirs::config_dir()
+                .context("Could not find config directory")?
+                .join(APP_DIR),
+        };
         
         if !dir.exists() {
+            Self::move_legacy_home(&dir)?;
             fs::create_dir_all(&dir).context("Failed to create config directory")?;
         }
         
         Ok(dir)
     }
     
+    /// Tokens and history: `$XDG_STATE_HOME/lynx-fm`, or `$LYNX_FM_HOME/state`
+    pub fn state_dir() -> Result<PathBuf> {
+        if let Some(home) = Self::home_override() {
+            return Ok(home.join("state"));
+        }
+        
+        // Only Linux has a state directory; elsewhere local app data is the closest match
+        Ok(dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .context("Could not find state directory")?
+            .join(APP_DIR))
+    }
+    
+    /// Cached audio: `$XDG_CACHE_HOME/lynx-fm`, or `$LYNX_FM_HOME/cache`
+    pub fn cache_root() -> Result<PathBuf> {
+        if let Some(home) = Self::home_override() {
+            return Ok(home.join("cache"));
+        }
+        
+        Ok(dirs::cache_dir()
+            .context("Could not find cache directory")?
+            .join(APP_DIR))
+    }
+    
+    /// Synced catalog and playlists: `$XDG_DATA_HOME/lynx-fm`, or `$LYNX_FM_HOME/data`
+    pub fn data_dir() -> Result<PathBuf> {
+        if let Some(home) = Self::home_override() {
+            return Ok(home.join("data"));
+        }
+        
+        Ok(dirs::data_dir()
+            .context("Could not find data directory")?
+            .join(APP_DIR))
+    }
+    
+    fn home_override() -> Option<PathBuf> {
+        std::env::var_os(HOME_ENV)
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from)
+    }
+    
+    /// Where everything lived before the XDG layout
+    pub fn legacy_home() -> Result<PathBuf> {
+        Ok(home_dir().context("Could not find home directory")?.join(".lynx-fm"))
+    }
+    
+    /// Move a pre-XDG `~/.lynx-fm` tree into the new config directory, once.
+    ///
+    /// Its cache moves to the cache directory; tokens are split out of the settings
+    /// files the first time each one is read.
+    fn move_legacy_home(config_dir: &Path) -> Result<()> {
+        let legacy = Self::legacy_home()?;
+        
+        if Self::home_override().is_some() || !legacy.is_dir() || legacy == config_dir {
+            return Ok(());
+        }
+        
+        if let Some(parent) = config_dir.parent() {
+            fs::create_dir_all(parent).context("Failed to create config directory")?;
+        }
+        move_dir(&legacy, config_dir)
+            .with_context(|| format!("Failed to move {} to {}", legacy.display(), config_dir.display()))?;
+            
+        let old_cache = config_dir.join("cache");
+        if old_cache.exists() {
+            let cache_root = Self::cache_root()?;
+            
+            if cache_root.exists() {
+                fs::remove_dir_all(&old_cache).context("Failed to remove old cache")?;
+            } else {
+                if let Some(parent) = cache_root.parent() {
+                    fs::create_dir_all(parent).context("Failed to create cache directory")?;
+                }
+                move_dir(&old_cache, &cache_root).context("Failed to move cache")?;
+            }
+        }
+        
+        eprintln!("Moved {} to {}", legacy.display(), config_dir.display());
+        Ok(())
+    }
+    
     pub fn config_file() -> Result<PathBuf> {
         let mut path = Self::config_dir()?;
         path.push("config.json");
         Ok(path)
     }
     
+    pub fn profiles_dir() -> Result<PathBuf> {
+        let mut dir = Self::config_dir()?;
+        dir.push("profiles");
+        Ok(dir)
+    }
+    
+    pub fn profile_file(name: &str) -> Result<PathBuf> {
+        let mut path = Self::profiles_dir()?;
+        path.push(format!("{}.json", name));
+        Ok(path)
+    }
+    
+    /// Per-profile directory for cached data
+    pub fn profile_cache_dir(name: &str) -> Result<PathBuf> {
+        let mut dir = Self::cache_root()?;
+        dir.push(name);
+        Ok(dir)
+    }
+    
+    /// Per-profile file holding the login tokens
+    pub fn credentials_file(name: &str) -> Result<PathBuf> {
+        let mut path = Self::state_dir()?;
+        path.push("credentials");
+        path.push(format!("{}.json", name));
+        Ok(path)
+    }
+    
+    /// Keep other `lynx-fm` processes from writing tokens until the guard is dropped
+    pub fn lock_credentials() -> Result<credentials::CredentialsLock> {
+        credentials::lock(&Self::state_dir()?.join("credentials"))
+    }
+    
+    /// Per-profile database of listening history
+    pub fn history_file(name: &str) -> Result<PathBuf> {
+        let mut path = Self::state_dir()?;
+        path.push("history");
+        path.push(format!("{}.db", name));
+        Ok(path)
+    }
+    
+    /// Per-profile database holding the synced catalog
+    pub fn catalog_file(name: &str) -> Result<PathBuf> {
+        let mut path = Self::data_dir()?;
+        path.push("catalog");
+        path.push(format!("{}.db", name));
+        Ok(path)
+    }
+    
+    /// Per-profile directory of local playlists
+    pub fn playlists_dir(name: &str) -> Result<PathBuf> {
+        let mut dir = Self::data_dir()?;
+        dir.push("playlists");
+        dir.push(name);
+        Ok(dir)
+    }
+    
+    /// Per-profile file of smart playlist rules
+    pub fn smart_playlists_file(name: &str) -> Result<PathBuf> {
+        let mut path = Self::data_dir()?;
+        path.push("smart");
+        path.push(format!("{}.json", name));
+        Ok(path)
+    }
+    
+    fn active_profile_file() -> Result<PathBuf> {
+        let mut path = Self::config_dir()?;
+        path.push("active_profile");
+        Ok(path)
+    }
+    
+    /// Use `name` instead of the active profile for the rest of this process
+    pub fn set_profile_override(name: &str) -> Result<()> {
+        Self::validate_profile_name(name)?;
+        PROFILE_OVERRIDE
+            .set(name.to_string())
+            .map_err(|_| anyhow::anyhow!("Profile override already set"))
+    }
+    
+    /// The profile in effect: `--profile`, then `LYNX_FM_PROFILE`, then `profile use`,
+    /// then the default
+    pub fn profile_name() -> Result<String> {
+        if let Some(name) = PROFILE_OVERRIDE.get() {
+            return Ok(name.clone());
+        }
+        
+        if let Some(name) = std::env::var("LYNX_FM_PROFILE").ok().filter(|name| !name.is_empty()) {
+            Self::validate_profile_name(&name)?;
+            return Ok(name);
+        }
+        
+        Self::active_profile()
+    }
+    
+    /// The profile selected with `profile use`
+    pub fn active_profile() -> Result<String> {
+        let path = Self::active_profile_file()?;
+        
+        if !path.exists() {
+            return Ok(DEFAULT_PROFILE.to_string());
+        }
+        
+        let name = fs::read_to_string(&path)
+            .context("Failed to read active profile")?
+            .trim()
+            .to_string();
+            
+        if name.is_empty() {
+            return Ok(DEFAULT_PROFILE.to_string());
+        }
+        
+        // The file is hand-editable, and the name ends up in paths
+        Self::validate_profile_name(&name)
+            .with_context(|| format!("{} names an invalid profile", path.display()))?;
+            
+        Ok(name)
+    }
+    
+    pub fn set_active_profile(name: &str) -> Result<()> {
+        Self::validate_profile_name(name)?;
+        let path = Self::active_profile_file()?;
+        
+        if name == DEFAULT_PROFILE {
+            if path.exists() {
+                fs::remove_file(&path).context("Failed to reset active profile")?;
+            }
+            return Ok(());
+        }
+        
+        if !Self::profile_exists(name)? {
+            anyhow::bail!("Profile '{}' does not exist", name);
+        }
+        
+        fs::write(&path, name).context("Failed to write active profile")
+    }
+    
+    pub fn profile_exists(name: &str) -> Result<bool> {
+        Ok(name == DEFAULT_PROFILE || Self::profile_file(name)?.exists())
+    }
+    
+    /// All profile names, starting with the default profile
+    pub fn list_profiles() -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let dir = Self::profiles_dir()?;
+        
+        if dir.exists() {
+            for entry in fs::read_dir(&dir).context("Failed to read profiles directory")? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                        names.push(stem.to_string());
+                    }
+                }
+            }
+        }
+        
+        names.sort();
+        names.insert(0, DEFAULT_PROFILE.to_string());
+        Ok(names)
+    }
+    
+    /// Create a profile file holding only the given settings; the rest are inherited
+    pub fn create_profile(name: &str, settings: Map<String, Value>) -> Result<()> {
+        Self::validate_profile_name(name)?;
+        
+        if Self::profile_exists(name)? {
+            anyhow::bail!("Profile '{}' already exists", name);
+        }
+        
+        fs::create_dir_all(Self::profiles_dir()?).context("Failed to create profiles directory")?;
+        write_layer(&Self::profile_file(name)?, settings)
+    }
+    
+    /// Delete a profile along with its tokens and cache
+    pub fn remove_profile(name: &str) -> Result<()> {
+        Self::validate_profile_name(name)?;
+        if name == DEFAULT_PROFILE {
+            anyhow::bail!("The default profile cannot be removed");
+        }
+        
+        let path = Self::profile_file(name)?;
+        if !path.exists() {
+            anyhow::bail!("Profile '{}' does not exist", name);
+        }
+        
+        fs::remove_file(&path).context("Failed to remove profile")?;
+        
+        // Everything else kept per profile goes too, so a new profile of the same name starts clean
+        let files = [
+            Self::credentials_file(name)?,
+            Self::history_file(name)?,
+            Self::catalog_file(name)?,
+            Self::smart_playlists_file(name)?,
+        ];
+        for file in files.iter().filter(|file| file.exists()) {
+            fs::remove_file(file).with_context(|| format!("Failed to remove {}", file.display()))?;
+        }
+        
+        let dirs = [Self::profile_cache_dir(name)?, Self::playlists_dir(name)?];
+        for dir in dirs.iter().filter(|dir| dir.exists()) {
+            fs::remove_dir_all(dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+        
+        if Self::active_profile()? == name {
+            Self::set_active_profile(DEFAULT_PROFILE)?;
+        }
+        
+        Ok(())
+    }
+    
+    pub fn validate_profile_name(name: &str) -> Result<()> {
+        let valid = !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            
+        if !valid {
+            anyhow::bail!("Invalid profile name '{}': use letters, digits, '-' and '_'", name);
+        }
+        
+        Ok(())
+    }
+    
+    /// Load the effective config: flag > env > profile > file > default
     pub fn load() -> Result<Self> {
-        let path = Self::config_file()?;
+        Self::load_stored()?.with_overrides(&Self::runtime_overrides()?)
+    }
+    
+    /// Whether the access token comes from `LYNX_FM_TOKEN`, `--token` or `-c auth_token=`
+    pub fn token_overridden() -> Result<bool> {
+        Ok(Self::runtime_overrides()?.contains_key("auth_token"))
+    }
+    
+    /// The config as stored on disk, with the active profile's tokens
+    fn load_stored() -> Result<Self> {
+        let (base, profile) = Self::read_layers()?;
+        let credentials = Self::read_credentials(&Self::profile_name()?)?;
         
+        Self::resolve(base, profile)?.with_overrides(&credentials.unwrap_or_default())
+    }
+    
+    /// Read `config.json` and, for a named profile, its profile file
+    fn read_layers() -> Result<(Option<Layer>, Option<Layer>)> {
+        let config_file = Self::config_file()?;
+        if !config_file.exists() {
+            Self::import_legacy(&Self::legacy_config_file()?, &config_file)?;
+        }
+        
+        let base = Self::read_settings(&config_file, DEFAULT_PROFILE)?;
+        let profile = Self::profile_name()?;
+        
+        if profile == DEFAULT_PROFILE {
+            return Ok((base, None));
+        }
+        
+        let path = Self::profile_file(&profile)?;
         if !path.exists() {
-            return Ok(Self::default());
+            anyhow::bail!("Profile '{}' does not exist. Create it with `lynx-fm profile add {}`", profile, profile);
+        }
+        
+        Ok((base, Some(Self::read_settings(&path, &profile)?.unwrap_or_default())))
+    }
+    
+    /// Read a settings file, moving any tokens it still holds to the profile's credentials file
+    fn read_settings(path: &Path, profile: &str) -> Result<Option<Layer>> {
+        let mut layer = match Self::read_layer(path)? {
+            Some(layer) => layer,
+            None => return Ok(None),
+        };
+        
+        let tokens: Layer = PROFILE_SCOPED_FIELDS
+            .iter()
+            .filter_map(|field| layer.remove(*field).map(|value| (field.to_string(), value)))
+            .collect();
+            
+        if !tokens.is_empty() {
+            // Tokens already in the state directory are newer than any left in the settings
+            if Self::read_credentials(profile)?.is_none() {
+                Self::write_credentials(profile, tokens)?;
+            }
+            write_json(path, &layer)?;
+        }
+        
+        Ok(Some(layer))
+    }
+    
+    fn read_credentials(profile: &str) -> Result<Option<Layer>> {
+        read_json(&Self::credentials_file(profile)?)
+    }
+    
+    /// Store a profile's tokens, removing the file once they are all cleared
+    fn write_credentials(profile: &str, tokens: Layer) -> Result<()> {
+        let path = Self::credentials_file(profile)?;
+        
+        if tokens.values().all(Value::is_null) {
+            if path.exists() {
+                fs::remove_file(&path).context("Failed to remove credentials")?;
+            }
+            return Ok(());
+        }
+        
+        let content = serde_json::to_string_pretty(&tokens)
+            .context("Failed to serialize credentials")?;
+        credentials::write_private(&path, content.as_bytes())
+    }
+    
+    /// Where the old `music-cli` kept its configuration
+    pub fn legacy_config_file() -> Result<PathBuf> {
+        let mut path = home_dir().context("Could not find home directory")?;
+        path.push(".music-cli");
+        path.push("config.json");
+        Ok(path)
+    }
+    
+    /// Copy a `music-cli` config into place; it is migrated when it is next read.
+    /// The original is left alone.
+    pub fn import_legacy(legacy: &Path, target: &Path) -> Result<bool> {
+        if !legacy.exists() || target.exists() {
+            return Ok(false);
+        }
+        
+        fs::copy(legacy, target)
+            .with_context(|| format!("Failed to import {}", legacy.display()))?;
+            
+        eprintln!("Imported music-cli settings from {}", legacy.display());
+        Ok(true)
+    }
+    
+    /// Read a config or profile file, upgrading it in place if an older version wrote it.
+    ///
+    /// The original is copied to `<file>.v<N>.bak` before the upgraded file is written.
+    pub fn read_layer(path: &Path) -> Result<Option<Layer>> {
+        let mut layer = match read_json(path)? {
+            Some(layer) => layer,
+            None => return Ok(None),
+        };
+        
+        let version = Self::layer_version(&layer)?;
+        if version == CONFIG_VERSION {
+            return Ok(Some(layer));
+        }
+        
+        let backup = path.with_extension(format!("json.v{}.bak", version));
+        fs::copy(path, &backup).context("Failed to back up config file before migrating it")?;
+        
+        Self::migrate(&mut layer)
+            .and_then(|_| write_json(path, &layer))
+            .with_context(|| format!(
+                "Failed to migrate {} to version {}; the original is saved as {}",
+                path.display(),
+                CONFIG_VERSION,
+                backup.display()
+            ))?;
+            
+        Ok(Some(layer))
+    }
+    
+    /// Run every migration from the file's version up to `CONFIG_VERSION`
+    pub fn migrate(layer: &mut Layer) -> Result<()> {
+        let version = Self::layer_version(layer)?;
+        
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migration(layer).with_context(|| format!("Migration from version {} failed", from))?;
         }
+        layer.insert("version".to_string(), CONFIG_VERSION.into());
+        
+        // The migrated file has to load, or the next run would fail anyway
+        Self::resolve(Some(layer.clone()), None).map(|_| ())
+    }
+    
+    /// Files from before versioning have no `version` field and count as version 0
+    fn layer_version(layer: &Layer) -> Result<u32> {
+        let version = match layer.get("version") {
+            None => 0,
+            Some(value) => value
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .context("Config field 'version' must be a whole number")?,
+        };
         
-        let content = fs::read_to_string(&path)
-            .context("Failed to read config file")?;
+        if version > CONFIG_VERSION {
+            anyhow::bail!(
+                "Config version {} was written by a newer lynx-fm (this one supports up to {}); please upgrade",
+                version,
+                CONFIG_VERSION
+            );
+        }
+        
+        Ok(version)
+    }
+    
+    /// Record settings given as command-line flags for the rest of this process
+    pub fn set_flag_overrides(overrides: Map<String, Value>) -> Result<()> {
+        FLAG_OVERRIDES
+            .set(overrides)
+            .map_err(|_| anyhow::anyhow!("Flag overrides already set"))
+    }
+    
+    /// Settings from the environment, keyed by field name, with the variable that set each.
+    ///
+    /// Only user-facing settings and the access token are read, so a stray `LYNX_FM_VERSION`
+    /// can't change the file layout version or the stored session.
+    pub fn env_overrides() -> Result<Vec<(String, String, Value)>> {
+        let mut overrides = Vec::new();
+        
+        for field in Self::overridable_fields() {
+            let mut names = vec![format!("{}{}", ENV_PREFIX, field.to_uppercase())];
+            names.extend(
+                ENV_ALIASES
+                    .iter()
+                    .filter(|(_, target)| *target == field)
+                    .map(|(alias, _)| alias.to_string()),
+            );
+            
+            // The full name wins over an alias when both are set
+            for name in names {
+                if let Some(raw) = std::env::var(&name).ok().filter(|raw| !raw.is_empty()) {
+                    let value = Self::parse_field_value(field, &raw)
+                        .with_context(|| format!("Invalid value in {}", name))?;
+                    overrides.push((field.to_string(), name, value));
+                    break;
+                }
+            }
+        }
+        
+        Ok(overrides)
+    }
+    
+    /// Environment and flag overrides merged, flags winning
+    fn runtime_overrides() -> Result<Map<String, Value>> {
+        let mut overrides: Map<String, Value> = Self::env_overrides()?
+            .into_iter()
+            .map(|(field, _, value)| (field, value))
+            .collect();
             
-        let config: Self = serde_json::from_str(&content)
-            .context("Failed to parse config file")?;
+        if let Some(flags) = FLAG_OVERRIDES.get() {
+            overrides.extend(flags.clone());
+        }
+        
+        Ok(overrides)
+    }
+    
+    /// User-facing settings and the access token; the version and token bookkeeping
+    /// fields are never overridden
+    fn overridable_fields() -> impl Iterator<Item = &'static str> {
+        SETTINGS.iter().map(|setting| setting.key).chain(OVERRIDE_ONLY_FIELDS.iter().copied())
+    }
+    
+    /// Parse a `-c field=value` override, refusing fields that can't be overridden
+    pub fn parse_override(field: &str, raw: &str) -> Result<Value> {
+        if !Self::overridable_fields().any(|name| name == field) {
+            let names: Vec<&str> = Self::overridable_fields().collect();
+            anyhow::bail!("Unknown setting '{}'. Available settings: {}", field, names.join(", "));
+        }
+        
+        Self::parse_field_value(field, raw)
+    }
+    
+    /// Convert a raw string into the JSON value `field` expects
+    pub fn parse_field_value(field: &str, raw: &str) -> Result<Value> {
+        let defaults = Self::default().to_map()?;
+        if !defaults.contains_key(field) {
+            anyhow::bail!("Unknown config field '{}'", field);
+        }
+        
+        let fits = |value: &Value| {
+            let mut probe = defaults.clone();
+            probe.insert(field.to_string(), value.clone());
+            serde_json::from_value::<Self>(Value::Object(probe)).is_ok()
+        };
+        
+        // Strings are taken literally; anything else (numbers, booleans) is parsed as JSON
+        let as_string = Value::String(raw.to_string());
+        if fits(&as_string) {
+            return Ok(as_string);
+        }
+        
+        match serde_json::from_str::<Value>(raw) {
+            Ok(value) if fits(&value) => Ok(value),
+            _ => anyhow::bail!("'{}' is not a valid value for {}", raw, field),
+        }
+    }
+    
+    /// Apply env/flag overrides on top of the file-based settings
+    pub fn with_overrides(self, overrides: &Map<String, Value>) -> Result<Self> {
+        if overrides.is_empty() {
+            return Ok(self);
+        }
+        
+        let mut merged = self.to_map()?;
+        merged.extend(overrides.clone());
+        
+        let mut config: Self = serde_json::from_value(Value::Object(merged))
+            .context("Failed to apply config overrides")?;
             
+        // A token given on its own carries its expiry in the `exp` claim, not the stored one
+        if overrides.contains_key("auth_token") && !overrides.contains_key("token_expiry") {
+            config.token_expiry = config
+                .auth_token
+                .as_deref()
+                .and_then(|token| TokenClaims::decode(token).ok())
+                .and_then(|claims| claims.exp);
+        }
+        
         Ok(config)
     }
     
+    /// Report where each effective setting comes from
+    pub fn origins() -> Result<Vec<(String, Value, Origin)>> {
+        let (base, profile) = Self::read_layers()?;
+        let profile_name = Self::profile_name()?;
+        let credentials = Self::read_credentials(&profile_name)?.unwrap_or_default();
+        let env = Self::env_overrides()?;
+        let flags = FLAG_OVERRIDES.get().cloned().unwrap_or_default();
+        let config = Self::load()?.to_map()?;
+        
+        let mut origins = Vec::new();
+        for (field, value) in config {
+            let origin = if flags.contains_key(&field) {
+                Origin::Flag
+            } else if let Some((_, var, _)) = env.iter().find(|(f, _, _)| *f == field) {
+                Origin::Env(var.clone())
+            } else if credentials.contains_key(&field) {
+                Origin::File(Self::credentials_file(&profile_name)?)
+            } else if profile.as_ref().is_some_and(|p| p.contains_key(&field)) {
+                Origin::Profile(profile_name.clone(), Self::profile_file(&profile_name)?)
+            } else if base.as_ref().is_some_and(|b| b.contains_key(&field)) {
+                Origin::File(Self::config_file()?)
+            } else {
+                Origin::Default
+            };
+            
+            origins.push((field, value, origin));
+        }
+        
+        Ok(origins)
+    }
+    
+    /// Layer a profile over `config.json` over the defaults.
+    ///
+    /// Tokens are profile-scoped, so a named profile never sees the base tokens.
+    pub fn resolve(base: Option<Map<String, Value>>, profile: Option<Map<String, Value>>) -> Result<Self> {
+        let mut merged = Self::default().to_map()?;
+        
+        if let Some(mut base) = base {
+            if profile.is_some() {
+                for field in PROFILE_SCOPED_FIELDS {
+                    base.remove(*field);
+                }
+            }
+            merged.extend(base);
+        }
+        
+        if let Some(profile) = profile {
+            merged.extend(profile);
+        }
+        
+        serde_json::from_value(Value::Object(merged))
+            .context("Failed to parse config file")
+    }
+    
+    /// The settings a named profile has to store to reproduce `self` on top of `base`.
+    ///
+    /// Tokens are left out; they live in the profile's credentials file.
+    pub fn profile_overrides(&self, base: Option<Map<String, Value>>) -> Result<Map<String, Value>> {
+        let inherited = Self::resolve(base, Some(Map::new()))?.to_map()?;
+        
+        Ok(self
+            .to_map()?
+            .into_iter()
+            .filter(|(key, value)| {
+                !PROFILE_SCOPED_FIELDS.contains(&key.as_str()) && inherited.get(key) != Some(value)
+            })
+            .collect())
+    }
+    
     pub fn save(&self) -> Result<()> {
-        let path = Self::config_file()?;
-        let content = serde_json::to_string_pretty(self)
+        let profile = Self::profile_name()?;
+        let (base, _) = Self::read_layers()?;
+        
+        // Env and flag overrides are for this run only, so unchanged ones keep the stored value
+        let stored = Self::load_stored()?.to_map()?;
+        let mut values = self.to_map()?;
+        for (field, value) in Self::runtime_overrides()? {
+            if values.get(&field) == Some(&value) {
+                if let Some(stored) = stored.get(&field) {
+                    values.insert(field, stored.clone());
+                }
+            }
+        }
+        let mut config: Self = serde_json::from_value(Value::Object(values))
             .context("Failed to serialize config")?;
+        config.seal_refresh_token()?;
+        
+        let mut settings = config.to_map()?;
+        let tokens: Layer = PROFILE_SCOPED_FIELDS
+            .iter()
+            .filter_map(|field| settings.remove(*field).map(|value| (field.to_string(), value)))
+            .collect();
+        Self::write_credentials(&profile, tokens)?;
+        
+        if profile == DEFAULT_PROFILE {
+            return write_layer(&Self::config_file()?, settings);
+        }
+        
+        let overrides = config.profile_overrides(base)?;
+        
+        fs::create_dir_all(Self::profiles_dir()?).context("Failed to create profiles directory")?;
+        write_layer(&Self::profile_file(&profile)?, overrides)
+    }
+    
+    /// The file `config set` writes to: the profile file, or `config.json` for the default profile
+    pub fn active_file() -> Result<PathBuf> {
+        let profile = Self::profile_name()?;
+        
+        if profile == DEFAULT_PROFILE {
+            Self::config_file()
+        } else {
+            Self::profile_file(&profile)
+        }
+    }
+    
+    /// The effective value of any config field, including tokens
+    pub fn get_field(&self, field: &str) -> Result<Value> {
+        self.to_map()?
+            .remove(field)
+            .with_context(|| format!("Unknown config field '{}'", field))
+    }
+    
+    /// Validate and store a setting in the active profile
+    pub fn set_setting(key: &str, raw: &str) -> Result<()> {
+        let setting = Setting::find(key)?;
+        let value = Self::parse_field_value(key, raw)?;
+        setting.validate(&value)?;
+        
+        Self::update_active_file(|layer| {
+            layer.insert(key.to_string(), value);
+        })
+    }
+    
+    /// Remove a setting from the active profile so it falls back to the inherited value
+    pub fn unset_setting(key: &str) -> Result<()> {
+        Setting::find(key)?;
+        
+        Self::update_active_file(|layer| {
+            layer.remove(key);
+        })
+    }
+    
+    fn update_active_file(update: impl FnOnce(&mut Layer)) -> Result<()> {
+        let path = Self::active_file()?;
+        let mut layer = Self::read_layer(&path)?.unwrap_or_default();
+        
+        update(&mut layer);
+        Self::check_layer(&layer)?;
+        
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create config directory")?;
+        }
+        write_layer(&path, layer)
+    }
+    
+    /// Check every setting against its validator
+    pub fn validate(&self) -> Result<()> {
+        Self::check_layer(&self.to_map()?)
+    }
+    
+    /// Check the contents of a config or profile file before saving it
+    pub fn check_layer(layer: &Layer) -> Result<()> {
+        let fields = Self::default().to_map()?;
+        Self::layer_version(layer)?;
+        
+        for (key, value) in layer {
+            if !fields.contains_key(key) {
+                anyhow::bail!("Unknown config field '{}'", key);
+            }
             
-        fs::write(&path, content)
-            .context("Failed to write config file")?;
-            
-        Ok(())
+            if let Ok(setting) = Setting::find(key) {
+                setting.validate(value)?;
+            }
+        }
+        
+        Self::resolve(Some(layer.clone()), None).map(|_| ())
+    }
+    
+    fn to_map(&self) -> Result<Map<String, Value>> {
+        match serde_json::to_value(self).context("Failed to serialize config")? {
+            Value::Object(map) => Ok(map),
+            _ => unreachable!("Config always serializes to an object"),
+        }
     }
     
     pub fn is_authenticated(&self) -> bool {
@@ -82,6 +1033,101 @@ impl Config {
         self.auth_token = None;
         self.refresh_token = None;
         self.token_expiry = None;
+        self.sealed_refresh_token = None;
         self.save()
     }
-} 
\ No newline at end of file
+    
+    /// Whether a refresh token is stored, in plain or encrypted form
+    pub fn has_refresh_token(&self) -> bool {
+        self.refresh_token.is_some() || self.sealed_refresh_token.is_some()
+    }
+    
+    /// The refresh token, decrypting it (and asking for the passphrase) if it is sealed
+    pub fn current_refresh_token(&self) -> Result<Option<String>> {
+        if let Some(token) = &self.refresh_token {
+            return Ok(Some(token.clone()));
+        }
+        
+        match &self.sealed_refresh_token {
+            Some(sealed) => Ok(Some(credentials::unseal(sealed, &credentials::passphrase(false)?)?)),
+            None => Ok(None),
+        }
+    }
+    
+    /// Encrypt or decrypt the refresh token before saving, to match `encrypt_refresh_token`
+    fn seal_refresh_token(&mut self) -> Result<()> {
+        if self.encrypt_refresh_token {
+            if let Some(token) = self.refresh_token.take() {
+                let passphrase = credentials::passphrase(self.sealed_refresh_token.is_none())?;
+                
+                // Re-sealing with a mistyped passphrase would lock the user out of the new token
+                if let Some(old) = &self.sealed_refresh_token {
+                    credentials::unseal(old, &passphrase)?;
+                }
+                
+                self.sealed_refresh_token = Some(credentials::seal(&token, &passphrase)?);
+            }
+        } else if self.sealed_refresh_token.is_some() {
+            self.refresh_token = self.current_refresh_token()?;
+            self.sealed_refresh_token = None;
+        }
+        
+        Ok(())
+    }
+}
+
+fn read_json(path: &Path) -> Result<Option<Map<String, Value>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    
+    let content = fs::read_to_string(path)
+        .context("Failed to read config file")?;
+        
+    match serde_json::from_str(&content).context("Failed to parse config file")? {
+        Value::Object(map) => Ok(Some(map)),
+        _ => anyhow::bail!("Failed to parse config file: {} is not a JSON object", path.display()),
+    }
+}
+
+/// Move a directory, copying it when a rename isn't possible (e.g. across filesystems)
+fn move_dir(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    
+    copy_dir(from, to)?;
+    fs::remove_dir_all(from)?;
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    
+    Ok(())
+}
+
+/// Write a partial config file, stamped with the current version
+fn write_layer(path: &Path, mut layer: Layer) -> Result<()> {
+    layer.insert("version".to_string(), CONFIG_VERSION.into());
+    write_json(path, &layer)
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let content = serde_json::to_string_pretty(value)
+        .context("Failed to serialize config")?;
+        
+    files::write_atomic(path, content.as_bytes())
+        .context("Failed to write config file")
+}
\ No newline at end of file
@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use dialoguer::Password;
+use fs2::FileExt;
+use rand::RngCore;
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, OnceLock, PoisonError};
+
+use crate::files;
+
+/// Environment variable holding the passphrase for an encrypted refresh token
+pub const PASSPHRASE_ENV: &str = "LYNX_FM_PASSPHRASE";
+
+/// Prefix of a sealed token, so the format can change later
+const SEALED_PREFIX: &str = "v1";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Passphrase entered at a prompt, reused for the rest of this process
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// Credentials directories this process holds the lock on, so other tasks and threads wait too
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Signalled whenever a directory is removed from [`HELD`]
+static RELEASED: Condvar = Condvar::new();
+
+thread_local! {
+    /// Directories whose lock is lent to the code running in [`CredentialsLock::hold`] on this thread
+    static LENT: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Exclusive advisory lock on a credentials directory, released when dropped.
+///
+/// Other processes wait on the lock file and other tasks of this process on [`HELD`].
+/// Code that has to write under a lock it already holds runs inside [`CredentialsLock::hold`].
+pub struct CredentialsLock {
+    dir: PathBuf,
+    file: Option<File>,
+    /// Whether this guard took the lock, rather than borrowing one lent by `hold`
+    owner: bool,
+}
+
+impl CredentialsLock {
+    /// Run `f` with the lock lent to it, so `lock` and `write_private` on the same
+    /// directory go ahead instead of waiting for this guard
+    pub fn hold<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Unlend;
+        
+        impl Drop for Unlend {
+            fn drop(&mut self) {
+                LENT.with(|lent| lent.borrow_mut().pop());
+            }
+        }
+        
+        LENT.with(|lent| lent.borrow_mut().push(self.dir.clone()));
+        let _unlend = Unlend;
+        f()
+    }
+}
+
+impl Drop for CredentialsLock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            let _ = file.unlock();
+        }
+        if self.owner {
+            let mut held = HELD.lock().unwrap_or_else(PoisonError::into_inner);
+            held.retain(|dir| *dir != self.dir);
+            RELEASED.notify_all();
+        }
+    }
+}
+
+/// Take the lock that guards the credentials in `dir`, waiting for other processes and tasks
+pub fn lock(dir: &Path) -> Result<CredentialsLock> {
+    create_private_dir(dir)?;
+    let dir = dir.canonicalize().context("Failed to resolve credentials directory")?;
+    
+    if LENT.with(|lent| lent.borrow().contains(&dir)) {
+        return Ok(CredentialsLock { dir, file: None, owner: false });
+    }
+    
+    let mut held = HELD.lock().unwrap_or_else(PoisonError::into_inner);
+    while held.contains(&dir) {
+        held = RELEASED.wait(held).unwrap_or_else(PoisonError::into_inner);
+    }
+    held.push(dir.clone());
+    drop(held);
+    
+    // From here on dropping the guard hands the directory back, even if locking fails
+    let mut guard = CredentialsLock { dir, file: None, owner: true };
+    let file = File::create(guard.dir.join(".lock")).context("Failed to open credentials lock")?;
+    file.lock_exclusive().context("Failed to lock credentials")?;
+    guard.file = Some(file);
+    
+    Ok(guard)
+}
+
+/// Write a file readable only by the owner, atomically and under an advisory lock.
+///
+/// Concurrent `lynx-fm` processes (e.g. two token refreshes) take turns, and a reader
+/// sees either the old file or the new one, never a truncated one.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path.parent().context("Credentials path has no parent directory")?;
+    let _lock = lock(dir)?;
+    
+    files::write_atomic_private(path, contents)
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        fs::create_dir_all(dir).context("Failed to create credentials directory")?;
+        files::set_mode(dir, 0o700)?;
+    }
+    Ok(())
+}
+
+/// Encrypt a token with a key derived from the passphrase (Argon2id + XChaCha20-Poly1305)
+pub fn seal(token: &str, passphrase: &str) -> Result<String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    
+    let cipher = cipher(passphrase, &salt)?;
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), token.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt refresh token"))?;
+        
+    Ok(format!(
+        "{}:{}:{}:{}",
+        SEALED_PREFIX,
+        STANDARD_NO_PAD.encode(salt),
+        STANDARD_NO_PAD.encode(nonce),
+        STANDARD_NO_PAD.encode(ciphertext)
+    ))
+}
+
+/// Decrypt a token produced by [`seal`]
+pub fn unseal(sealed: &str, passphrase: &str) -> Result<String> {
+    let parts: Vec<&str> = sealed.split(':').collect();
+    let [prefix, salt, nonce, ciphertext] = parts[..] else {
+        anyhow::bail!("Encrypted refresh token is malformed");
+    };
+    
+    if prefix != SEALED_PREFIX {
+        anyhow::bail!("Encrypted refresh token uses an unknown format '{}'", prefix);
+    }
+    
+    let decode = |part: &str| STANDARD_NO_PAD.decode(part).context("Encrypted refresh token is malformed");
+    let salt = decode(salt)?;
+    let nonce = decode(nonce)?;
+    let ciphertext = decode(ciphertext)?;
+    
+    if nonce.len() != NONCE_LEN {
+        anyhow::bail!("Encrypted refresh token is malformed");
+    }
+    
+    let plaintext = cipher(passphrase, &salt)?
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase for the encrypted refresh token"))?;
+        
+    String::from_utf8(plaintext).context("Encrypted refresh token is malformed")
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key from passphrase: {}", e))?;
+        
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// The passphrase from `LYNX_FM_PASSPHRASE`, or asked for once per process.
+///
+/// `confirm` asks twice, for when a new token is being encrypted.
+pub fn passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = std::env::var(PASSPHRASE_ENV).ok().filter(|p| !p.is_empty()) {
+        return Ok(passphrase);
+    }
+    
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase.clone());
+    }
+    
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "The refresh token is encrypted and no terminal is available to ask for the passphrase. \
+             Set {} or disable encryption with `lynx-fm config set encrypt_refresh_token false`",
+            PASSPHRASE_ENV
+        );
+    }
+    
+    let mut prompt = Password::new().with_prompt("Passphrase for the refresh token");
+    if confirm {
+        prompt = prompt.with_confirmation("Confirm passphrase", "Passphrases don't match");
+    }
+    let passphrase = prompt.interact()?;
+    
+    Ok(PASSPHRASE.get_or_init(|| passphrase).clone())
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Extra delay the server asks for with `slow_down` (RFC 8628)
+const SLOW_DOWN_SECS: u64 = 5;
+
+/// A pending device login, as returned by the server
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    #[serde(default)]
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: u64,
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+impl DeviceAuthorization {
+    /// The URL to encode in the QR code, with the user code filled in if the server supports it
+    pub fn qr_target(&self) -> &str {
+        self.verification_uri_complete
+            .as_deref()
+            .unwrap_or(&self.verification_uri)
+    }
+}
+
+/// Tokens issued once the user approves the device
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeviceTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceTokenRequest<'a> {
+    device_code: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceErrorResponse {
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// Device authorization flow against the music server's `/auth/device` endpoints
+pub struct DeviceFlow {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl DeviceFlow {
+    pub fn new(base_url: &str, timeout_secs: u64) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(timeout_secs))
+            .build()
+            .expect("Failed to build HTTP client");
+            
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client,
+        }
+    }
+    
+    /// Ask the server for a device code and a user code to show
+    pub async fn start(&self) -> Result<DeviceAuthorization> {
+        let url = format!("{}/auth/device/code", self.base_url);
+        
+        let response = self.client
+            .post(&url)
+            .send()
+            .await
+            .context("Failed to request a device code")?;
+            
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            anyhow::bail!("Device login is not available ({}): {}", status, error);
+        }
+        
+        response.json().await.context("Failed to parse device code response")
+    }
+    
+    /// Poll until the user approves or denies the login, or the code expires
+    pub async fn poll(&self, authorization: &DeviceAuthorization) -> Result<DeviceTokens> {
+        let url = format!("{}/auth/device/token", self.base_url);
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(authorization.expires_in);
+        let mut interval = authorization.interval;
+        
+        loop {
+            if tokio::time::Instant::now() >= deadline {
+                anyhow::bail!("Device code expired before the login was approved");
+            }
+            
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            
+            let response = self.client
+                .post(&url)
+                .json(&DeviceTokenRequest {
+                    device_code: &authorization.device_code,
+                })
+                .send()
+                .await
+                .context("Failed to poll for device login")?;
+                
+            if response.status().is_success() {
+                return response.json().await.context("Failed to parse device token response");
+            }
+            
+            let status = response.status();
+            let error: DeviceErrorResponse = response.json().await
+                .with_context(|| format!("Unexpected response while polling ({})", status))?;
+                
+            match error.error.as_str() {
+                "authorization_pending" => {}
+                "slow_down" => interval += SLOW_DOWN_SECS,
+                "expired_token" => anyhow::bail!("Device code expired before the login was approved"),
+                "access_denied" => anyhow::bail!("Device login was denied"),
+                other => match error.error_description {
+                    Some(desc) => anyhow::bail!("Device login failed: {} - {}", other, desc),
+                    None => anyhow::bail!("Device login failed: {}", other),
+                },
+            }
+        }
+    }
+}
+
+/// Render text as a QR code made of Unicode half blocks
+pub fn qr_code(data: &str) -> Result<String> {
+    let code = QrCode::new(data.as_bytes()).context("Failed to build QR code")?;
+    
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
\ No newline at end of file
@@ -0,0 +1,109 @@
+use anyhow::{Context, Result};
+
+/// Format a number of seconds as a short human-readable duration
+pub fn format_duration(seconds: i64) -> String {
+    let (days, hours, minutes, secs) = (
+        seconds / 86_400,
+        seconds % 86_400 / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60,
+    );
+    
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Parse a span such as `90s`, `3m`, `24h`, `7d`, `2w` or `1y` into seconds.
+///
+/// A bare number is taken as seconds.
+pub fn parse_span(text: &str) -> Result<i64> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid time span '{}'; try 90s, 3m, 24h, 7d or 2w", text))?;
+        
+    let unit_secs = match unit.trim() {
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3_600.0,
+        "d" => 86_400.0,
+        "w" => 7.0 * 86_400.0,
+        "y" => 365.0 * 86_400.0,
+        other => anyhow::bail!("Unknown time unit '{}' in '{}'; use s, m, h, d, w or y", other, text),
+    };
+    
+    Ok((number * unit_secs).round() as i64)
+}
+
+/// Widest a table cell gets before it is truncated
+const MAX_CELL_WIDTH: usize = 40;
+
+/// How listings are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Format a track length in seconds as `m:ss`, or `h:mm:ss` for long ones
+pub fn format_length(seconds: f64) -> String {
+    let total = seconds.round() as i64;
+    let (hours, minutes, secs) = (total / 3_600, total % 3_600 / 60, total % 60);
+    
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{}:{:02}", minutes, secs)
+    }
+}
+
+/// Render rows as a plain-text table with a header line
+pub fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| row.iter().map(|cell| truncate(cell, MAX_CELL_WIDTH)).collect())
+        .collect();
+        
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    
+    let line = |row: &[String]| {
+        row.iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    
+    let header: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    let mut out = vec![line(&header)];
+    out.extend(cells.iter().map(|row| line(row)));
+    out.join("\n")
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    
+    let mut truncated: String = text.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
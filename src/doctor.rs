@@ -0,0 +1,280 @@
+use anyhow::{Context, Result};
+use rodio::{Decoder, Source};
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use crate::auth::TokenClaims;
+use crate::config::Config;
+use crate::display::format_duration;
+use crate::music::MusicClient;
+
+const TONE_SAMPLE_RATE: u32 = 44_100;
+const TONE_FREQUENCY: f32 = 440.0;
+const TONE_MILLIS: u32 = 250;
+
+/// The outcome of one diagnostic check
+pub struct Check {
+    pub name: &'static str,
+    /// What was found on success, or what went wrong
+    pub result: Result<String>,
+    /// What to try when the check fails
+    pub hint: String,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            result,
+            hint: hint.into(),
+        }
+    }
+    
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+    
+    /// The detail line: what was found, or the error and its root cause
+    pub fn detail(&self) -> String {
+        match &self.result {
+            Ok(detail) => detail.clone(),
+            Err(e) if e.chain().count() > 1 => format!("{}: {}", e, e.root_cause()),
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+/// Run every check in turn. Later checks still run when earlier ones fail, so one
+/// report shows everything that is wrong.
+pub async fn run() -> Vec<Check> {
+    let config = match Config::load().and_then(|config| config.validate().map(|_| config)) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Check::new(
+                "Configuration",
+                Err(e),
+                "Fix the reported setting with `lynx-fm config set` or `lynx-fm config edit`",
+            )];
+        }
+    };
+    
+    let mut checks = vec![Check::new(
+        "Configuration",
+        config_summary(),
+        "Check the active profile with `lynx-fm profile list`",
+    )];
+    
+    checks.push(Check::new(
+        "DNS",
+        resolve_host(&config.music_server_url).await,
+        "Check music_server_url for typos and that this machine can resolve its host name",
+    ));
+    
+    let client = MusicClient::new(config.clone());
+    let health = server_health(&client).await;
+    let hint = network_hint(&health, "Check that the server is running and music_server_url points at it");
+    checks.push(Check::new("Server /health", health, hint));
+    
+    let supabase = supabase_health(&config).await;
+    let hint = network_hint(&supabase, "Check supabase_url and supabase_anon_key (`lynx-fm config list`)");
+    checks.push(Check::new("Supabase", supabase, hint));
+    
+    checks.push(Check::new(
+        "Token",
+        token_status(&config),
+        "Run `lynx-fm login`",
+    ));
+    
+    checks.push(Check::new(
+        "Identity (/me)",
+        identity(&client).await,
+        "Log in again with `lynx-fm login`; if it persists, the server may not accept tokens from this Supabase project",
+    ));
+    
+    checks.push(Check::new(
+        "Audio device",
+        audio_device(&client),
+        "Check that a sound device is connected, or pick another with `lynx-fm config set audio_device <name>`",
+    ));
+    
+    checks.push(Check::new(
+        "Audio decoding",
+        decode_tone(),
+        "The audio decoder is broken in this build; reinstall lynx-fm",
+    ));
+    
+    checks
+}
+
+fn config_summary() -> Result<String> {
+    Ok(format!(
+        "profile '{}', {}",
+        Config::profile_name()?,
+        Config::active_file()?.display()
+    ))
+}
+
+async fn resolve_host(url: &str) -> Result<String> {
+    let url = reqwest::Url::parse(url).context("Invalid server URL")?;
+    let host = url.host_str().context("Server URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    
+    let addresses: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Could not resolve {}", host))?
+        .collect();
+        
+    let first = addresses.first().with_context(|| format!("{} has no addresses", host))?;
+    Ok(format!("{} -> {}", host, first.ip()))
+}
+
+async fn server_health(client: &MusicClient) -> Result<String> {
+    let started = Instant::now();
+    let healthy = client.health_check().await?;
+    let latency = started.elapsed();
+    
+    if !healthy {
+        anyhow::bail!("Server answered /health with an error status");
+    }
+    
+    Ok(format!("healthy, {} ms round trip", latency.as_millis()))
+}
+
+async fn supabase_health(config: &Config) -> Result<String> {
+    if config.supabase_url == Config::default().supabase_url {
+        anyhow::bail!("supabase_url is still the example value; set it with `lynx-fm config set supabase_url <url>`");
+    }
+    
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.request_timeout_secs))
+        .build()?;
+        
+    let url = format!("{}/auth/v1/health", config.supabase_url.trim_end_matches('/'));
+    let started = Instant::now();
+    let response = client
+        .get(&url)
+        .header("apikey", &config.supabase_anon_key)
+        .send()
+        .await
+        .context("Failed to reach Supabase")?;
+        
+    let status = response.status();
+    if status == reqwest::StatusCode::UNAUTHORIZED {
+        anyhow::bail!("Supabase rejected the anonymous key");
+    }
+    if !status.is_success() {
+        anyhow::bail!("Supabase auth answered {}", status);
+    }
+    
+    Ok(format!("reachable, {} ms round trip", started.elapsed().as_millis()))
+}
+
+/// Point at TLS or connection problems specifically when the error says so
+fn network_hint(result: &Result<String>, fallback: &str) -> String {
+    let error = match result {
+        Ok(_) => return String::new(),
+        Err(e) => format!("{:#}", e).to_lowercase(),
+    };
+    
+    if error.contains("certificate") || error.contains("tls") || error.contains("ssl") {
+        "TLS failed: check the system clock and CA certificates, or whether a proxy intercepts HTTPS".to_string()
+    } else if error.contains("timed out") {
+        "The request timed out: check your network, or raise request_timeout_secs".to_string()
+    } else {
+        fallback.to_string()
+    }
+}
+
+fn token_status(config: &Config) -> Result<String> {
+    let token = config.auth_token.as_deref().context("Not logged in")?;
+    let claims = TokenClaims::decode(token)?;
+    let who = claims.email.or(claims.sub).unwrap_or_else(|| "unknown user".to_string());
+    
+    match claims.exp {
+        Some(exp) => {
+            let remaining = exp - chrono::Utc::now().timestamp();
+            if remaining <= 0 {
+                let refresh = if config.has_refresh_token() {
+                    "; it will be refreshed on the next command"
+                } else {
+                    ""
+                };
+                anyhow::bail!("Token for {} expired {} ago{}", who, format_duration(-remaining), refresh);
+            }
+            Ok(format!("{}, expires in {}", who, format_duration(remaining)))
+        }
+        None => Ok(format!("{}, no expiry", who)),
+    }
+}
+
+async fn identity(client: &MusicClient) -> Result<String> {
+    if client.config.auth_token.is_none() {
+        anyhow::bail!("Skipped: not logged in");
+    }
+    
+    let identity = client.me().await?;
+    
+    if let Some(token) = &client.config.auth_token {
+        let claims = TokenClaims::decode(token).unwrap_or_default();
+        let mismatches = identity.mismatches(&claims);
+        if !mismatches.is_empty() {
+            anyhow::bail!("Server and token disagree: {}", mismatches.join("; "));
+        }
+    }
+    
+    Ok(match identity.email {
+        Some(email) => format!("{} ({})", email, identity.user_id),
+        None => identity.user_id,
+    })
+}
+
+fn audio_device(client: &MusicClient) -> Result<String> {
+    let (_stream, _handle) = client.output_stream()?;
+    
+    Ok(client
+        .config
+        .audio_device
+        .clone()
+        .unwrap_or_else(|| "default device opened".to_string()))
+}
+
+fn decode_tone() -> Result<String> {
+    let decoder = Decoder::new(Cursor::new(test_tone())).context("Failed to decode test tone")?;
+    let (rate, channels) = (decoder.sample_rate(), decoder.channels());
+    let samples = decoder.count();
+    
+    let expected = (TONE_SAMPLE_RATE * TONE_MILLIS / 1000) as usize;
+    if samples != expected {
+        anyhow::bail!("Decoded {} samples, expected {}", samples, expected);
+    }
+    
+    Ok(format!("{} ms test tone, {} Hz, {} channel", TONE_MILLIS, rate, channels))
+}
+
+/// A short 16-bit mono sine wave as a WAV file
+pub fn test_tone() -> Vec<u8> {
+    let samples = TONE_SAMPLE_RATE * TONE_MILLIS / 1000;
+    let data_len = samples * 2;
+    
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&TONE_SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(TONE_SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    
+    for i in 0..samples {
+        let t = i as f32 / TONE_SAMPLE_RATE as f32;
+        let sample = (t * TONE_FREQUENCY * std::f32::consts::TAU).sin() * 0.25 * i16::MAX as f32;
+        wav.extend_from_slice(&(sample as i16).to_le_bytes());
+    }
+    
+    wav
+}
@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Replace a file by writing a temporary file next to it and renaming it into place.
+///
+/// A reader sees either the old file or the new one, never a truncated one. The new file
+/// gets the usual permissions for the user's umask.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    replace(path, contents, 0o666)
+}
+
+/// Like [`write_atomic`], but the new file is readable only by the owner (mode 0600)
+pub fn write_atomic_private(path: &Path, contents: &[u8]) -> Result<()> {
+    replace(path, contents, 0o600)
+}
+
+fn replace(path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+    let dir = path.parent().context("File path has no parent directory")?;
+    
+    let mut builder = tempfile::Builder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(mode));
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    
+    let mut temp = builder.tempfile_in(dir).context("Failed to create temporary file")?;
+    temp.write_all(contents).context("Failed to write temporary file")?;
+    temp.as_file().sync_all().context("Failed to flush temporary file")?;
+    
+    temp.persist(path).context("Failed to replace file")?;
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).context("Failed to set file permissions")
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
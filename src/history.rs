@@ -0,0 +1,264 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::display::parse_span;
+use crate::music::{Playback, Track};
+use crate::smart::PlayStats;
+
+/// Kept in `PRAGMA user_version`
+const SCHEMA_VERSION: i32 = 1;
+
+const SCHEMA: &str = "
+    CREATE TABLE plays (
+        id INTEGER PRIMARY KEY,
+        track_id TEXT NOT NULL,
+        title TEXT,
+        artist TEXT,
+        started_at INTEGER NOT NULL,
+        listened REAL NOT NULL,
+        completed INTEGER NOT NULL,
+        source TEXT NOT NULL
+    );
+    CREATE INDEX plays_started_at ON plays (started_at);
+    CREATE INDEX plays_track_id ON plays (track_id);
+";
+
+const CSV_HEADER: &str = "started_at,track_id,artist,title,listened,completed,source";
+
+/// What started a playback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaySource {
+    /// `lynx-fm random`
+    Random,
+    /// A single track picked by ID or query
+    Track,
+    /// An album or artist queued with `play --album` or `--artist`
+    Queue,
+    /// A server, local or smart playlist
+    Playlist,
+}
+
+impl PlaySource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlaySource::Random => "random",
+            PlaySource::Track => "track",
+            PlaySource::Queue => "queue",
+            PlaySource::Playlist => "playlist",
+        }
+    }
+    
+    fn parse(text: &str) -> Option<Self> {
+        [PlaySource::Random, PlaySource::Track, PlaySource::Queue, PlaySource::Playlist]
+            .into_iter()
+            .find(|source| source.as_str() == text)
+    }
+}
+
+impl std::fmt::Display for PlaySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One playback, as recorded in the history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Play {
+    pub track_id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub started_at: DateTime<Utc>,
+    /// Seconds actually listened
+    pub listened: f64,
+    /// Played to the end rather than skipped
+    pub completed: bool,
+    pub source: PlaySource,
+}
+
+impl Play {
+    pub fn new(track: &Track, playback: &Playback, source: PlaySource) -> Self {
+        Self {
+            track_id: track.id.clone(),
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            started_at: playback.started_at,
+            listened: playback.listened,
+            completed: playback.completed,
+            source,
+        }
+    }
+    
+    /// `Artist - Title` as it was when played, or the track ID
+    pub fn label(&self) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => self.track_id.clone(),
+        }
+    }
+    
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let started_at: i64 = row.get("started_at")?;
+        let source: String = row.get("source")?;
+        
+        Ok(Self {
+            track_id: row.get("track_id")?,
+            title: row.get("title")?,
+            artist: row.get("artist")?,
+            started_at: DateTime::from_timestamp(started_at, 0).unwrap_or_default(),
+            listened: row.get("listened")?,
+            completed: row.get("completed")?,
+            // Only ever written by `record`, so anything else is a damaged row
+            source: PlaySource::parse(&source).unwrap_or(PlaySource::Track),
+        })
+    }
+}
+
+/// Every playback on this machine, in a per-profile SQLite database
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    /// The active profile's history
+    pub fn open_default() -> Result<Self> {
+        Self::open(&Config::history_file(&Config::profile_name()?)?)
+    }
+    
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create state directory")?;
+        }
+        
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open history database {}", path.display()))?;
+            
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            anyhow::bail!(
+                "History database {} was created by a newer version of lynx-fm",
+                path.display()
+            );
+        }
+        if version == 0 {
+            conn.execute_batch(SCHEMA).context("Failed to create history database")?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        
+        Ok(Self { conn })
+    }
+    
+    pub fn record(&self, play: &Play) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO plays (track_id, title, artist, started_at, listened, completed, source)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    play.track_id,
+                    play.title,
+                    play.artist,
+                    play.started_at.timestamp(),
+                    play.listened,
+                    play.completed,
+                    play.source.as_str(),
+                ],
+            )
+            .context("Failed to record play")?;
+            
+        Ok(())
+    }
+    
+    /// Plays started at or after `since`, newest first
+    pub fn plays(&self, since: Option<DateTime<Utc>>, limit: Option<usize>) -> Result<Vec<Play>> {
+        let since = since.map_or(i64::MIN, |since| since.timestamp());
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM plays WHERE started_at >= ?1 ORDER BY started_at DESC, id DESC LIMIT ?2",
+        )?;
+        let plays = stmt
+            .query_map(params![since, limit], Play::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+            
+        Ok(plays)
+    }
+    
+    /// How often and how recently each track was played, skips included
+    pub fn stats(&self) -> Result<HashMap<String, PlayStats>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT track_id, count(*), max(started_at) FROM plays GROUP BY track_id")?;
+        let stats = stmt
+            .query_map([], |row| {
+                let count: i64 = row.get(1)?;
+                Ok((row.get(0)?, PlayStats { count: count as usize, last_played: row.get(2)? }))
+            })?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+            
+        Ok(stats)
+    }
+}
+
+/// Play counts for rules and random picks; an unreadable history counts as empty
+pub fn play_stats() -> HashMap<String, PlayStats> {
+    match History::open_default().and_then(|history| history.stats()) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Warning: could not read listening history: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// A date (`2026-10-01`), an RFC 3339 time, or a span back from now such as `24h` or `7d`
+pub fn parse_since(text: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    
+    let span = parse_span(text).with_context(|| format!("Invalid time '{}'; use a date such as 2026-10-01, or a span such as 24h or 7d", text))?;
+    chrono::TimeDelta::try_seconds(span)
+        .and_then(|span| Utc::now().checked_sub_signed(span))
+        .with_context(|| format!("'{}' reaches back too far", text))
+}
+
+/// Plays as CSV with a header row
+pub fn to_csv(plays: &[Play]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    
+    for play in plays {
+        let fields = [
+            play.started_at.to_rfc3339(),
+            play.track_id.clone(),
+            play.artist.clone().unwrap_or_default(),
+            play.title.clone().unwrap_or_default(),
+            format!("{:.1}", play.listened),
+            play.completed.to_string(),
+            play.source.to_string(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    
+    csv
+}
+
+/// Quote a field when it holds a comma, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
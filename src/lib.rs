@@ -1,7 +1,19 @@
 pub mod auth;
+pub mod catalog;
 pub mod commands;
 pub mod config;
+pub mod credentials;
+pub mod device;
+pub mod display;
+pub mod doctor;
+pub mod files;
+pub mod history;
 pub mod music;
+pub mod oauth;
+pub mod playlist;
+pub mod random;
+pub mod smart;
+pub mod stats;
 
 // Re-export the modules for easier access in tests
 pub use auth::AuthClient;
@@ -1,16 +1,48 @@
 mod auth;
+mod catalog;
 mod commands;
 mod config;
+mod credentials;
+mod device;
+mod display;
+mod doctor;
+mod files;
+mod history;
 mod music;
+mod oauth;
+mod playlist;
+mod random;
+mod smart;
+mod stats;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 use colored::Colorize;
+use dialoguer::{Confirm, FuzzySelect};
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
-use crate::auth::AuthClient;
-use crate::commands::{Cli, Commands};
-use crate::config::Config;
-use crate::music::MusicClient;
+use crate::auth::{AuthClient, TokenClaims};
+use crate::catalog::LocalCatalog;
+use crate::commands::{AccountCommands, Cli, Commands, ConfigCommands, MfaCommands, PlaylistCommands, ProfileCommands, SmartCommands, TrackCommands};
+use crate::config::{Config, Origin, DEFAULT_PROFILE, SETTINGS};
+use crate::display::{format_duration, format_length, OutputFormat};
+use crate::history::{History, Play, PlaySource};
+use crate::music::{MusicClient, Playlist, RandomPick, Track, TrackFilter};
+use crate::playlist::{PlaylistFormat, PlaylistStore};
+use crate::random::RandomFilter;
+use crate::smart::{RuleContext, SmartPlaylist, SmartStore};
+use crate::stats::{ReportFormat, Stats};
+
+/// New tracks listed after a sync before pointing at `whats-new`
+const SYNC_REPORT_LIMIT: usize = 20;
+
+/// Most suggestions `complete` prints
+const COMPLETION_LIMIT: usize = 50;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -20,32 +52,110 @@ async fn main() -> Result<()> {
     // Parse command line arguments
     let cli = Cli::parse();
     
+    if let Some(profile) = &cli.profile {
+        Config::set_profile_override(profile)?;
+    }
+    Config::set_flag_overrides(cli.config_overrides()?)?;
+    
+    // A broken config file is reported by the command itself
+    if let Ok(config) = Config::load() {
+        apply_theme(&config.theme);
+    }
+    
     // Execute the appropriate command
     match cli.command {
-        Commands::Config { supabase_url, supabase_key, server_url } => {
-            configure(supabase_url, supabase_key, server_url).await?;
+        Commands::Config { action, email, password_command, show_origin } => {
+            if let Some(action) = action {
+                config_command(action)?;
+            } else if show_origin {
+                show_config_origins()?;
+            } else {
+                configure(cli.supabase_url, cli.supabase_key, cli.server_url, email, password_command).await?;
+            }
+        }
+        Commands::Profile { command } => {
+            profile(command)?;
         }
         Commands::Signup => {
             AuthClient::interactive_signup().await?;
         }
-        Commands::Login => {
-            AuthClient::interactive_login().await?;
+        Commands::Login { email, password_stdin, otp, provider, no_browser, device } => {
+            if device {
+                AuthClient::device_login().await?;
+            } else if let Some(provider) = provider {
+                AuthClient::provider_login(&provider, !no_browser).await?;
+            } else if otp {
+                AuthClient::otp_login(email).await?;
+            } else {
+                AuthClient::login_with(email, password_stdin).await?;
+            }
         }
         Commands::Logout => {
             logout().await?;
         }
+        Commands::Whoami => {
+            whoami().await?;
+        }
+        Commands::Account { command } => {
+            account(command).await?;
+        }
         Commands::Health => {
             health_check().await?;
         }
-        Commands::Random => {
-            play_random().await?;
+        Commands::Doctor => {
+            doctor().await?;
+        }
+        Commands::Random { genre, artist, min_duration, exclude_played, weighted } => {
+            let filter = RandomFilter { genre, artist, min_duration, exclude_played };
+            play_random(&filter, weighted).await?;
+        }
+        Commands::Play { target, id, album, artist } => match target {
+            Some(target) if id => play_track(&target).await?,
+            Some(target) => play_query(&target).await?,
+            None => play_collection(artist.as_deref(), album.as_deref()).await?,
+        },
+        Commands::Search { query, limit, format } => {
+            search(&query, limit, format).await?;
         }
-        Commands::Play { track_id } => {
-            play_track(&track_id).await?;
+        Commands::Artists { format } => {
+            artists(format).await?;
+        }
+        Commands::Albums { artist, format } => {
+            albums(artist.as_deref(), format).await?;
+        }
+        Commands::Genres { format } => {
+            genres(format).await?;
+        }
+        Commands::Playlist { local, command } => {
+            playlist_command(local, command).await?;
+        }
+        Commands::Smart { command } => {
+            smart_command(command).await?;
+        }
+        Commands::Sync { full } => {
+            sync(full).await?;
+        }
+        Commands::WhatsNew { days, format } => {
+            whats_new(days, format)?;
+        }
+        Commands::History { since, limit, json, export } => {
+            history(since, limit, json, export.as_deref())?;
+        }
+        Commands::Stats { since, until, year, top, report, output } => {
+            stats(since, until, year, top, report, output)?;
+        }
+        Commands::Complete { prefix } => {
+            complete(&prefix)?;
+        }
+        Commands::Tracks { command } => {
+            tracks(command).await?;
         }
         Commands::Prefetch { track_ids } => {
             prefetch_tracks(track_ids).await?;
         }
+        Commands::Api { method, path, data, headers } => {
+            api_request(&method, &path, data, headers).await?;
+        }
     }
     
     Ok(())
@@ -55,6 +165,8 @@ async fn configure(
     supabase_url: Option<String>,
     supabase_key: Option<String>,
     server_url: Option<String>,
+    email: Option<String>,
+    password_command: Option<String>,
 ) -> Result<()> {
     let mut config = Config::load()?;
     let mut updated = false;
@@ -74,13 +186,30 @@ async fn configure(
         updated = true;
     }
     
+    if let Some(email) = email {
+        config.email = Some(email);
+        updated = true;
+    }
+    
+    if let Some(command) = password_command {
+        config.password_command = Some(command);
+        updated = true;
+    }
+    
     if updated {
+        config.validate()?;
         config.save()?;
         println!("{}", "Configuration updated successfully.".green());
     } else {
-        println!("Current configuration:");
+        println!("Current configuration (profile '{}'):", Config::profile_name()?);
         println!("  Supabase URL: {}", config.supabase_url);
         println!("  Music Server URL: {}", config.music_server_url);
+        if let Some(email) = &config.email {
+            println!("  Email: {}", email);
+        }
+        if let Some(command) = &config.password_command {
+            println!("  Password command: {}", command);
+        }
         println!("  Authentication: {}", 
             if config.is_authenticated() { 
                 "Authenticated".green() 
@@ -93,6 +222,250 @@ async fn configure(
     Ok(())
 }
 
+fn show_config_origins() -> Result<()> {
+    println!("Configuration (profile '{}'):", Config::profile_name()?);
+    
+    for (field, value, origin) in Config::origins()? {
+        let shown = match value {
+            serde_json::Value::Null => "-".to_string(),
+            serde_json::Value::String(s) if is_secret(&field) => mask_secret(&s),
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        };
+        
+        println!("  {} = {}  {}", field, shown, format!("({})", origin).dimmed());
+    }
+    
+    Ok(())
+}
+
+fn config_command(action: ConfigCommands) -> Result<()> {
+    match action {
+        ConfigCommands::Get { key } => {
+            match Config::load()?.get_field(&key)? {
+                serde_json::Value::Null => anyhow::bail!("{} is not set", key),
+                serde_json::Value::String(s) => println!("{}", s),
+                other => println!("{}", other),
+            }
+        }
+        ConfigCommands::Set { key, value } => {
+            Config::set_setting(&key, &value)?;
+            reseal_if_needed(&key)?;
+            println!("{}", format!("Set {} in profile '{}'.", key, Config::profile_name()?).green());
+            warn_if_overridden(&key)?;
+        }
+        ConfigCommands::Unset { key } => {
+            Config::unset_setting(&key)?;
+            reseal_if_needed(&key)?;
+            println!("{}", format!("Unset {} in profile '{}'.", key, Config::profile_name()?).green());
+            warn_if_overridden(&key)?;
+        }
+        ConfigCommands::List => {
+            let config = Config::load()?;
+            println!("Settings (profile '{}'):", Config::profile_name()?);
+            
+            for setting in SETTINGS {
+                let shown = match config.get_field(setting.key)? {
+                    serde_json::Value::Null => "-".to_string(),
+                    serde_json::Value::String(s) if is_secret(setting.key) => mask_secret(&s),
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                
+                println!("  {} = {}", setting.key, shown);
+                println!("      {}", setting.description.dimmed());
+            }
+        }
+        ConfigCommands::Edit => {
+            edit_config()?;
+        }
+        ConfigCommands::Path => {
+            println!("{}", Config::active_file()?.display());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Re-save the tokens right away so turning encryption on or off takes effect now
+fn reseal_if_needed(key: &str) -> Result<()> {
+    if key == "encrypt_refresh_token" {
+        Config::load()?.save()?;
+    }
+    Ok(())
+}
+
+/// Point out when an env var or flag will hide the value just written
+fn warn_if_overridden(key: &str) -> Result<()> {
+    let origins = Config::origins()?;
+    
+    if let Some((_, _, origin)) = origins.iter().find(|(field, _, _)| field == key) {
+        if matches!(origin, Origin::Env(_) | Origin::Flag) {
+            println!("{}", format!("Note: {} is currently overridden by {}.", key, origin).yellow());
+        }
+    }
+    
+    Ok(())
+}
+
+/// Edit the active config file in an editor, re-validating before it is saved
+fn edit_config() -> Result<()> {
+    let path = Config::active_file()?;
+    let original = if path.exists() {
+        fs::read_to_string(&path).context("Failed to read config file")?
+    } else {
+        "{}\n".to_string()
+    };
+    
+    let mut contents = original.clone();
+    loop {
+        contents = open_in_editor(&contents)?;
+        
+        let checked = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&contents)
+            .context("Config file must be a JSON object")
+            .and_then(|layer| Config::check_layer(&layer));
+            
+        match checked {
+            Ok(()) => break,
+            Err(e) => {
+                eprintln!("{} {:#}", "Invalid configuration:".red(), e);
+                
+                let again = Confirm::new()
+                    .with_prompt("Edit again?")
+                    .default(true)
+                    .interact()?;
+                    
+                if !again {
+                    println!("Changes discarded.");
+                    return Ok(());
+                }
+            }
+        }
+    }
+    
+    if contents == original {
+        println!("No changes.");
+        return Ok(());
+    }
+    
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+    files::write_atomic(&path, contents.as_bytes()).context("Failed to write config file")?;
+    
+    println!("{}", format!("Saved {}", path.display()).green());
+    Ok(())
+}
+
+/// Run $VISUAL or $EDITOR (falling back to vi) on a scratch copy and return the result
+fn open_in_editor(contents: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+        
+    // The config can hold tokens, so keep the scratch file private (tempfile creates it 0600)
+    let scratch = tempfile::Builder::new()
+        .prefix("lynx-fm-config-")
+        .suffix(".json")
+        .tempfile()
+        .context("Failed to create temporary file")?;
+    fs::write(scratch.path(), contents).context("Failed to write temporary file")?;
+    
+    // Editors are often configured with arguments, e.g. "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().context("Editor command is empty")?;
+    
+    let status = Command::new(program)
+        .args(parts)
+        .arg(scratch.path())
+        .status()
+        .with_context(|| format!("Failed to run editor '{}'", editor));
+        
+    // Read by path: some editors save by replacing the file
+    let edited = fs::read_to_string(scratch.path()).context("Failed to read edited file");
+    
+    if !status?.success() {
+        anyhow::bail!("Editor exited with an error; changes discarded");
+    }
+    edited
+}
+
+fn apply_theme(theme: &str) {
+    match theme {
+        "color" => colored::control::set_override(true),
+        "plain" => colored::control::set_override(false),
+        _ => {}
+    }
+}
+
+fn is_secret(field: &str) -> bool {
+    matches!(field, "auth_token" | "refresh_token" | "sealed_refresh_token" | "supabase_anon_key")
+}
+
+/// Show enough of a secret to recognise it without printing it
+fn mask_secret(secret: &str) -> String {
+    let prefix: String = secret.chars().take(8).collect();
+    if prefix.len() < secret.len() {
+        format!("{}...", prefix)
+    } else {
+        "********".to_string()
+    }
+}
+
+fn profile(command: ProfileCommands) -> Result<()> {
+    match command {
+        ProfileCommands::Add { name, supabase_url, supabase_key, server_url, activate } => {
+            let mut settings = serde_json::Map::new();
+            
+            if let Some(url) = supabase_url {
+                settings.insert("supabase_url".to_string(), url.into());
+            }
+            
+            if let Some(key) = supabase_key {
+                settings.insert("supabase_anon_key".to_string(), key.into());
+            }
+            
+            if let Some(url) = server_url {
+                settings.insert("music_server_url".to_string(), url.into());
+            }
+            
+            Config::create_profile(&name, settings)?;
+            println!("{}", format!("Profile '{}' created.", name).green());
+            
+            if activate {
+                Config::set_active_profile(&name)?;
+                println!("Switched to profile '{}'.", name);
+            }
+        }
+        ProfileCommands::List => {
+            let current = Config::profile_name()?;
+            
+            for name in Config::list_profiles()? {
+                if name == current {
+                    println!("* {}", name.green());
+                } else {
+                    println!("  {}", name);
+                }
+            }
+        }
+        ProfileCommands::Use { name } => {
+            Config::set_active_profile(&name)?;
+            println!("Switched to profile '{}'.", name);
+        }
+        ProfileCommands::Remove { name } => {
+            let was_active = Config::active_profile()? == name;
+            Config::remove_profile(&name)?;
+            println!("Profile '{}' removed.", name);
+            
+            if was_active {
+                println!("Active profile is now '{}'.", DEFAULT_PROFILE);
+            }
+        }
+    }
+    
+    Ok(())
+}
+
 async fn logout() -> Result<()> {
     let config = Config::load()?;
     let client = AuthClient::new(config);
@@ -100,6 +473,137 @@ async fn logout() -> Result<()> {
     Ok(())
 }
 
+async fn whoami() -> Result<()> {
+    let mut config = Config::load()?;
+    
+    let Some(token) = config.auth_token.clone() else {
+        println!("{}", "Not logged in.".yellow());
+        println!("Run `lynx-fm login` to sign in.");
+        return Ok(());
+    };
+    
+    println!("Token:");
+    let claims = match TokenClaims::decode(&token) {
+        Ok(claims) => {
+            print_field("Subject", claims.sub.as_deref());
+            print_field("Email", claims.email.as_deref());
+            print_field("Role", claims.role.as_deref());
+            print_field("Issuer", claims.iss.as_deref());
+            print_field("Assurance", claims.aal.as_deref());
+            
+            let expiry = claims.exp.or(config.token_expiry);
+            match expiry {
+                Some(exp) => {
+                    let remaining = exp - chrono::Utc::now().timestamp();
+                    if remaining > 0 {
+                        println!("  Expires: in {}", format_duration(remaining).green());
+                    } else {
+                        println!("  Expires: {}", format!("expired {} ago", format_duration(-remaining)).red());
+                    }
+                }
+                None => print_field("Expires", None),
+            }
+            
+            Some(claims)
+        }
+        Err(e) => {
+            println!("  {} {}", "Could not decode token:".red(), e);
+            None
+        }
+    };
+    
+    // Refresh an expired token so the server sees current credentials
+    if !config.is_authenticated() && config.has_refresh_token() {
+        if let Ok(new_config) = AuthClient::new(config.clone()).refresh_token().await {
+            config = new_config;
+        }
+    }
+    
+    println!();
+    println!("Server (/me):");
+    let client = MusicClient::new(config);
+    match client.me().await {
+        Ok(identity) => {
+            print_field("User ID", Some(&identity.user_id));
+            print_field("Email", identity.email.as_deref());
+            print_field("Role", identity.role.as_deref());
+            if let Some(metadata) = identity.metadata.as_ref().filter(|m| !m.is_null()) {
+                println!("  Metadata: {}", metadata);
+            }
+            
+            if let Some(claims) = claims {
+                let mismatches = identity.mismatches(&claims);
+                if !mismatches.is_empty() {
+                    println!();
+                    for mismatch in mismatches {
+                        println!("{} {}", "Mismatch:".red(), mismatch);
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            println!("  {} {}", "Request failed:".red(), e);
+        }
+    }
+    
+    Ok(())
+}
+
+fn print_field(label: &str, value: Option<&str>) {
+    println!("  {}: {}", label, value.unwrap_or("-"));
+}
+
+async fn account(command: AccountCommands) -> Result<()> {
+    match command {
+        AccountCommands::ResetPassword { email } => {
+            AuthClient::interactive_reset_password(email).await?;
+        }
+        AccountCommands::ChangePassword => {
+            AuthClient::interactive_change_password().await?;
+        }
+        AccountCommands::ChangeEmail { new_email } => {
+            AuthClient::interactive_change_email(&new_email).await?;
+        }
+        AccountCommands::ResendVerification { email } => {
+            AuthClient::interactive_resend_verification(email).await?;
+        }
+        AccountCommands::Mfa { command } => match command {
+            MfaCommands::Enroll { name } => {
+                AuthClient::interactive_mfa_enroll(name).await?;
+            }
+            MfaCommands::List => {
+                AuthClient::interactive_mfa_list().await?;
+            }
+            MfaCommands::Unenroll { factor_id } => {
+                AuthClient::interactive_mfa_unenroll(&factor_id).await?;
+            }
+        },
+    }
+    
+    Ok(())
+}
+
+async fn doctor() -> Result<()> {
+    let checks = doctor::run().await;
+    
+    for check in &checks {
+        if check.passed() {
+            println!("{} {}: {}", "✓".green(), check.name, check.detail());
+        } else {
+            println!("{} {}: {}", "✗".red(), check.name, check.detail());
+            println!("    {}", check.hint.dimmed());
+        }
+    }
+    
+    let failed = checks.iter().filter(|check| !check.passed()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, checks.len());
+    }
+    
+    println!("{}", "All checks passed.".green());
+    Ok(())
+}
+
 async fn health_check() -> Result<()> {
     let config = Config::load()?;
     let client = MusicClient::new(config);
@@ -120,15 +624,45 @@ async fn health_check() -> Result<()> {
     }
 }
 
-async fn play_random() -> Result<()> {
+async fn play_random(filter: &RandomFilter, weighted: bool) -> Result<()> {
     // Load config without requiring authentication
     let config = Config::load()?;
     let client = MusicClient::new(config);
     
-    let track_id = client.get_random_track().await?;
-    client.stream_track(&track_id).await?;
+    if filter.is_empty() && !weighted {
+        let pick = client.get_random_track().await?;
+        let track = pick.track.unwrap_or_else(|| catalog_track(&pick.id));
+        return play_logged(&client, &track, PlaySource::Random).await;
+    }
     
-    Ok(())
+    let plays = history::play_stats();
+    let context = RuleContext { now: chrono::Utc::now().timestamp(), plays: &plays };
+    
+    // The server can only help when history and weights don't matter
+    let mut server_pick = None;
+    if !weighted && filter.exclude_played.is_none() {
+        match client.random_track_matching(&filter.server_query()).await? {
+            Some(RandomPick { track: Some(track), .. }) if filter.matches(&track, &context) => {
+                println!("{} {}", "▶".green(), track.label().bold());
+                return play_logged(&client, &track, PlaySource::Random).await;
+            }
+            // Without metadata the pick is checked against the catalog below
+            Some(RandomPick { id, track: None }) => server_pick = Some(id),
+            // The server ignored the filters or couldn't apply them
+            _ => {}
+        }
+    }
+    
+    let tracks = catalog::all_tracks(&client).await?;
+    let verified = server_pick.and_then(|id| tracks.iter().find(|t| t.id == id && filter.matches(t, &context)));
+    let track = match verified {
+        Some(track) => track,
+        None => random::pick(&tracks, filter, &context, weighted, &mut rand::thread_rng())
+            .context("No tracks match those filters")?,
+    };
+    
+    println!("{} {}", "▶".green(), track.label().bold());
+    play_logged(&client, track, PlaySource::Random).await
 }
 
 async fn play_track(track_id: &str) -> Result<()> {
@@ -136,7 +670,742 @@ async fn play_track(track_id: &str) -> Result<()> {
     let config = Config::load()?;
     let client = MusicClient::new(config);
     
-    client.stream_track(track_id).await?;
+    play_logged(&client, &catalog_track(track_id), PlaySource::Track).await
+}
+
+/// A track with its metadata from the synced catalog, or with just its ID if it isn't there
+fn catalog_track(id: &str) -> Track {
+    let known = LocalCatalog::open_default().and_then(|catalog| catalog.track(id));
+    known.ok().flatten().unwrap_or_else(|| Track { id: id.to_string(), ..Track::default() })
+}
+
+/// Play the track a query names, asking which one when it's ambiguous
+async fn play_query(query: &str) -> Result<()> {
+    // Load config without requiring authentication
+    let config = Config::load()?;
+    let client = MusicClient::new(config);
+    
+    let results = match catalog::search_without_sync(&client, query).await {
+        Ok(Some(results)) => results,
+        Ok(None) => catalog::sync_and_search(&client, query).await?,
+        Err(e) => {
+            eprintln!("Warning: search failed ({:#}); trying '{}' as a track ID", e, query);
+            return play_track(query).await;
+        }
+    };
+    
+    let track = match catalog::resolve_exact(&results, query) {
+        Some(track) => track.clone(),
+        // Nothing matched, so it's most likely a track ID
+        None if results.is_empty() => return play_track(query).await,
+        None => pick_track(query, &results)?,
+    };
+    
+    println!("{} {}", "▶".green(), track.label().bold());
+    play_logged(&client, &track, PlaySource::Track).await
+}
+
+/// Queue an album, or an artist's albums, and play them in order
+async fn play_collection(artist: Option<&str>, album: Option<&str>) -> Result<()> {
+    // Load config without requiring authentication
+    let config = Config::load()?;
+    let client = MusicClient::new(config);
+    
+    let tracks = catalog::tracks_for(catalog::all_tracks(&client).await?, artist, album);
+    if tracks.is_empty() {
+        let wanted: Vec<String> = [("artist", artist), ("album", album)]
+            .iter()
+            .filter_map(|(kind, name)| name.map(|name| format!("{} \"{}\"", kind, name)))
+            .collect();
+        anyhow::bail!("No tracks found for {}", wanted.join(" and "));
+    }
+    
+    play_queue(&client, &tracks, PlaySource::Queue).await
+}
+
+/// Play tracks one after another; Ctrl-C skips to the next one
+async fn play_queue(client: &MusicClient, tracks: &[Track], source: PlaySource) -> Result<()> {
+    for (i, track) in tracks.iter().enumerate() {
+        println!("{} {}/{} {}", "▶".green(), i + 1, tracks.len(), track.label().bold());
+        play_logged(client, track, source).await?;
+    }
+    
+    Ok(())
+}
+
+/// Play a track and add it to the listening history
+async fn play_logged(client: &MusicClient, track: &Track, source: PlaySource) -> Result<()> {
+    let playback = client.stream_track(&track.id).await?;
+    
+    // A history that can't be written shouldn't stop the music
+    let play = Play::new(track, &playback, source);
+    if let Err(e) = History::open_default().and_then(|history| history.record(&play)) {
+        eprintln!("Warning: could not record play: {}", e);
+    }
+    
+    Ok(())
+}
+
+fn pick_track(query: &str, results: &[Track]) -> Result<Track> {
+    let labels: Vec<String> = results
+        .iter()
+        .map(|track| match &track.album {
+            Some(album) => format!("{} ({})", track.label(), album),
+            None => track.label(),
+        })
+        .collect();
+        
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "\"{}\" matches {} tracks; be more specific or use --id:\n  {}",
+            query,
+            results.len(),
+            labels.join("\n  ")
+        );
+    }
+    
+    let choice = FuzzySelect::new()
+        .with_prompt(format!("\"{}\" matches {} tracks", query, results.len()))
+        .items(&labels)
+        .default(0)
+        .interact_opt()?
+        .context("No track selected")?;
+        
+    Ok(results[choice].clone())
+}
+
+async fn search(query: &str, limit: Option<usize>, format: OutputFormat) -> Result<()> {
+    // Load config without requiring authentication
+    let config = Config::load()?;
+    let client = MusicClient::new(config);
+    
+    let mut tracks = catalog::search(&client, query).await?;
+    if let Some(limit) = limit {
+        tracks.truncate(limit);
+    }
+    
+    print_tracks(&tracks, format)
+}
+
+async fn artists(format: OutputFormat) -> Result<()> {
+    // Load config without requiring authentication
+    let config = Config::load()?;
+    let artists = catalog::artists(&MusicClient::new(config)).await?;
+    
+    let rows = artists
+        .iter()
+        .map(|artist| vec![artist.name.clone(), count_cell(artist.albums), count_cell(artist.tracks)])
+        .collect();
+    print_listing(&artists, &["ARTIST", "ALBUMS", "TRACKS"], rows, "artists", format)
+}
+
+async fn albums(artist: Option<&str>, format: OutputFormat) -> Result<()> {
+    // Load config without requiring authentication
+    let config = Config::load()?;
+    let albums = catalog::albums(&MusicClient::new(config), artist).await?;
+    
+    let rows = albums
+        .iter()
+        .map(|album| {
+            vec![
+                album.title.clone(),
+                album.artist.clone().unwrap_or_default(),
+                album.year.map(|year| year.to_string()).unwrap_or_default(),
+                count_cell(album.tracks),
+            ]
+        })
+        .collect();
+    print_listing(&albums, &["ALBUM", "ARTIST", "YEAR", "TRACKS"], rows, "albums", format)
+}
+
+async fn genres(format: OutputFormat) -> Result<()> {
+    // Load config without requiring authentication
+    let config = Config::load()?;
+    let genres = catalog::genres(&MusicClient::new(config)).await?;
+    
+    let rows = genres
+        .iter()
+        .map(|genre| vec![genre.name.clone(), count_cell(genre.tracks)])
+        .collect();
+    print_listing(&genres, &["GENRE", "TRACKS"], rows, "genres", format)
+}
+
+fn count_cell(count: Option<usize>) -> String {
+    count.map(|count| count.to_string()).unwrap_or_default()
+}
+
+/// Print a browse listing as a table with a count, or as JSON
+fn print_listing<T: serde::Serialize>(
+    items: &[T],
+    headers: &[&str],
+    rows: Vec<Vec<String>>,
+    noun: &str,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(items)?);
+        }
+        OutputFormat::Table => {
+            if items.is_empty() {
+                println!("No {} found.", noun);
+                return Ok(());
+            }
+            
+            println!("{}", display::table(headers, &rows));
+            println!("{}", format!("{} {}", items.len(), noun).dimmed());
+        }
+    }
+    
+    Ok(())
+}
+
+async fn playlist_command(local: bool, command: PlaylistCommands) -> Result<()> {
+    if local {
+        return local_playlist_command(command).await;
+    }
+    
+    let config = AuthClient::ensure_authenticated().await?;
+    let client = MusicClient::new(config);
+    
+    match command {
+        PlaylistCommands::List { format } => {
+            print_playlists(&client.list_playlists().await?, format)?;
+        }
+        PlaylistCommands::Show { playlist, format } => {
+            let playlist = client.find_playlist(&playlist).await?;
+            print_playlist(&client.get_playlist(&playlist.id).await?, format)?;
+        }
+        PlaylistCommands::Create { name, description } => {
+            let playlist = client.create_playlist(&name, description.as_deref()).await?;
+            println!("Created playlist '{}' ({}).", playlist.name, playlist.id);
+        }
+        PlaylistCommands::Rename { playlist, name } => {
+            let playlist = client.find_playlist(&playlist).await?;
+            client.rename_playlist(&playlist.id, &name).await?;
+            println!("Renamed '{}' to '{}'.", playlist.name, name);
+        }
+        PlaylistCommands::Delete { playlist } => {
+            let playlist = client.find_playlist(&playlist).await?;
+            client.delete_playlist(&playlist.id).await?;
+            println!("Playlist '{}' deleted.", playlist.name);
+        }
+        PlaylistCommands::Add { playlist, track_ids } => {
+            let playlist = client.find_playlist(&playlist).await?;
+            client.add_to_playlist(&playlist.id, &track_ids).await?;
+            println!("Added {} tracks to '{}'.", track_ids.len(), playlist.name);
+        }
+        PlaylistCommands::Remove { playlist, track_ids } => {
+            let playlist = client.find_playlist(&playlist).await?;
+            for track_id in &track_ids {
+                client.remove_from_playlist(&playlist.id, track_id).await?;
+            }
+            println!("Removed {} tracks from '{}'.", track_ids.len(), playlist.name);
+        }
+        PlaylistCommands::Reorder { playlist, from, to } => {
+            let playlist = client.find_playlist(&playlist).await?;
+            let playlist = client.get_playlist(&playlist.id).await?;
+            
+            let mut track_ids: Vec<String> = playlist.tracks.iter().map(|track| track.id.clone()).collect();
+            move_item(&mut track_ids, from, to)?;
+            client.reorder_playlist(&playlist.id, &track_ids).await?;
+            println!("Moved track {} to position {} in '{}'.", from, to, playlist.name);
+        }
+        PlaylistCommands::Play { playlist, shuffle } => {
+            let playlist = client.find_playlist(&playlist).await?;
+            let playlist = client.get_playlist(&playlist.id).await?;
+            play_playlist(&client, playlist, shuffle).await?;
+        }
+        PlaylistCommands::Import { file, name, format } => {
+            let (name, tracks) = import_playlist(&client, &file, name, format).await?;
+            
+            let playlist = client.create_playlist(&name, None).await?;
+            let track_ids: Vec<String> = tracks.iter().map(|track| track.id.clone()).collect();
+            if !track_ids.is_empty() {
+                client.add_to_playlist(&playlist.id, &track_ids).await?;
+            }
+            println!("Imported {} tracks into playlist '{}'.", track_ids.len(), playlist.name);
+        }
+        PlaylistCommands::Export { playlist, file, format } => {
+            let playlist = client.find_playlist(&playlist).await?;
+            let playlist = client.get_playlist(&playlist.id).await?;
+            export_playlist(&client, &playlist, &file, format)?;
+        }
+    }
+    
+    Ok(())
+}
+
+/// The same operations on playlists kept in the data directory
+async fn local_playlist_command(command: PlaylistCommands) -> Result<()> {
+    // Local playlists work without logging in; only resolving tracks may need the server
+    let config = Config::load()?;
+    let client = MusicClient::new(config);
+    let store = PlaylistStore::open_default()?;
+    
+    match command {
+        PlaylistCommands::List { format } => {
+            print_playlists(&store.list()?, format)?;
+        }
+        PlaylistCommands::Show { playlist, format } => {
+            print_playlist(&store.find(&playlist)?, format)?;
+        }
+        PlaylistCommands::Create { name, description } => {
+            let playlist = store.create(&name, description.as_deref(), Vec::new())?;
+            println!("Created local playlist '{}' ({}).", playlist.name, playlist.id);
+        }
+        PlaylistCommands::Rename { playlist, name } => {
+            let mut playlist = store.find(&playlist)?;
+            let old = playlist.name.clone();
+            store.rename(&mut playlist, &name)?;
+            println!("Renamed '{}' to '{}'.", old, playlist.name);
+        }
+        PlaylistCommands::Delete { playlist } => {
+            let playlist = store.find(&playlist)?;
+            store.delete(&playlist.id)?;
+            println!("Local playlist '{}' deleted.", playlist.name);
+        }
+        PlaylistCommands::Add { playlist, track_ids } => {
+            let mut playlist = store.find(&playlist)?;
+            let catalog = catalog::all_tracks(&client).await?;
+            
+            for track_id in &track_ids {
+                let track = catalog
+                    .iter()
+                    .find(|track| &track.id == track_id)
+                    .with_context(|| format!("Track '{}' is not in the catalog", track_id))?;
+                playlist.tracks.push(track.clone());
+            }
+            store.save(&playlist)?;
+            println!("Added {} tracks to '{}'.", track_ids.len(), playlist.name);
+        }
+        PlaylistCommands::Remove { playlist, track_ids } => {
+            let mut playlist = store.find(&playlist)?;
+            let before = playlist.tracks.len();
+            playlist.tracks.retain(|track| !track_ids.contains(&track.id));
+            store.save(&playlist)?;
+            println!("Removed {} tracks from '{}'.", before - playlist.tracks.len(), playlist.name);
+        }
+        PlaylistCommands::Reorder { playlist, from, to } => {
+            let mut playlist = store.find(&playlist)?;
+            move_item(&mut playlist.tracks, from, to)?;
+            store.save(&playlist)?;
+            println!("Moved track {} to position {} in '{}'.", from, to, playlist.name);
+        }
+        PlaylistCommands::Play { playlist, shuffle } => {
+            play_playlist(&client, store.find(&playlist)?, shuffle).await?;
+        }
+        PlaylistCommands::Import { file, name, format } => {
+            let (name, tracks) = import_playlist(&client, &file, name, format).await?;
+            let playlist = store.create(&name, None, tracks)?;
+            println!("Imported {} tracks into local playlist '{}'.", playlist.tracks.len(), playlist.name);
+        }
+        PlaylistCommands::Export { playlist, file, format } => {
+            export_playlist(&client, &store.find(&playlist)?, &file, format)?;
+        }
+    }
+    
+    Ok(())
+}
+
+fn print_playlists(playlists: &[Playlist], format: OutputFormat) -> Result<()> {
+    let rows = playlists
+        .iter()
+        .map(|playlist| {
+            vec![
+                playlist.id.clone(),
+                playlist.name.clone(),
+                playlist.track_total().to_string(),
+                playlist.description.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    print_listing(playlists, &["ID", "NAME", "TRACKS", "DESCRIPTION"], rows, "playlists", format)
+}
+
+fn print_playlist(playlist: &Playlist, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Table {
+        println!("{}", playlist.name.bold());
+        if let Some(description) = &playlist.description {
+            println!("{}", description.dimmed());
+        }
+    }
+    print_tracks(&playlist.tracks, format)
+}
+
+async fn play_playlist(client: &MusicClient, playlist: Playlist, shuffle: bool) -> Result<()> {
+    let mut tracks = playlist.tracks;
+    if tracks.is_empty() {
+        anyhow::bail!("Playlist '{}' is empty", playlist.name);
+    }
+    
+    if shuffle {
+        tracks.shuffle(&mut rand::thread_rng());
+    }
+    play_queue(client, &tracks, PlaySource::Playlist).await
+}
+
+/// Read a playlist file and match its entries against the catalog, reporting the misses.
+///
+/// Returns the name to give the playlist and the tracks that matched.
+async fn import_playlist(
+    client: &MusicClient,
+    file: &Path,
+    name: Option<String>,
+    format: Option<PlaylistFormat>,
+) -> Result<(String, Vec<Track>)> {
+    let format = match format {
+        Some(format) => format,
+        None => PlaylistFormat::from_path(file)?,
+    };
+    
+    let content = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read {}", file.display()))?;
+    let parsed = playlist::parse(&content, format)?;
+    let total = parsed.entries.len();
+    
+    let resolution = playlist::resolve(parsed.entries, &catalog::all_tracks(client).await?);
+    
+    if !resolution.unmatched.is_empty() {
+        eprintln!("{} {} of {} entries didn't match any track:", "!".yellow(), resolution.unmatched.len(), total);
+        for entry in &resolution.unmatched {
+            eprintln!("  {} {}", "✗".red(), entry);
+        }
+    }
+    
+    let name = name
+        .or(parsed.title)
+        .or_else(|| file.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .context("Playlist has no name; use --name")?;
+        
+    Ok((name, resolution.tracks))
+}
+
+fn export_playlist(
+    client: &MusicClient,
+    playlist: &Playlist,
+    file: &Path,
+    format: Option<PlaylistFormat>,
+) -> Result<()> {
+    let format = match format {
+        Some(format) => format,
+        None => PlaylistFormat::from_path(file)?,
+    };
+    
+    let urls: Vec<String> = playlist
+        .tracks
+        .iter()
+        .map(|track| playlist::stream_url(track, &client.config.music_server_url))
+        .collect::<Result<_>>()?;
+        
+    fs::write(file, playlist::render(playlist, &urls, format))
+        .with_context(|| format!("Failed to write {}", file.display()))?;
+        
+    println!("Exported {} tracks from '{}' to {}.", playlist.tracks.len(), playlist.name, file.display());
+    Ok(())
+}
+
+async fn smart_command(command: SmartCommands) -> Result<()> {
+    let store = SmartStore::open_default()?;
+    
+    match command {
+        SmartCommands::Create { name, rule, sort, limit } => {
+            let playlist = SmartPlaylist { name, rule, sort, limit };
+            store.add(playlist.clone())?;
+            
+            match evaluate_smart(&playlist) {
+                Ok(tracks) => println!("Created smart playlist '{}', which matches {} tracks now.", playlist.name, tracks.len()),
+                Err(_) => println!("Created smart playlist '{}'. Run `lynx-fm sync` to see what it matches.", playlist.name),
+            }
+        }
+        SmartCommands::List => {
+            let playlists = store.list()?;
+            let mut rows = Vec::new();
+            for playlist in &playlists {
+                // Definitions are still listed before the first sync
+                let matching = evaluate_smart(playlist).map_or_else(|_| "-".to_string(), |tracks| tracks.len().to_string());
+                rows.push(vec![playlist.name.clone(), playlist.rule.clone(), matching]);
+            }
+            print_listing(&playlists, &["NAME", "RULE", "TRACKS"], rows, "smart playlists", OutputFormat::Table)?;
+        }
+        SmartCommands::Show { name, format } => {
+            let playlist = store.find(&name)?;
+            if format == OutputFormat::Table {
+                println!("{} {}", playlist.name.bold(), playlist.rule.dimmed());
+            }
+            print_tracks(&evaluate_smart(&playlist)?, format)?;
+        }
+        SmartCommands::Play { name, shuffle } => {
+            let playlist = store.find(&name)?;
+            
+            // Load config without requiring authentication
+            let config = Config::load()?;
+            let client = MusicClient::new(config);
+            
+            let tracks = evaluate_smart(&playlist)?;
+            play_playlist(&client, Playlist { name: playlist.name, tracks, ..Playlist::default() }, shuffle).await?;
+        }
+        SmartCommands::Delete { name } => {
+            store.remove(&name)?;
+            println!("Smart playlist '{}' deleted.", name);
+        }
+    }
+    
+    Ok(())
+}
+
+/// The tracks a smart playlist matches in the synced catalog right now
+fn evaluate_smart(playlist: &SmartPlaylist) -> Result<Vec<Track>> {
+    let catalog = LocalCatalog::open_default()?;
+    if catalog.last_sync()?.is_none() {
+        anyhow::bail!("Smart playlists use the synced catalog; run `lynx-fm sync` first");
+    }
+    
+    let plays = history::play_stats();
+    let context = RuleContext { now: chrono::Utc::now().timestamp(), plays: &plays };
+    
+    playlist.evaluate(catalog.tracks()?, &context)
+}
+
+/// Move the item at 1-based position `from` to position `to`
+fn move_item<T>(items: &mut Vec<T>, from: usize, to: usize) -> Result<()> {
+    let len = items.len();
+    if from == 0 || to == 0 || from > len || to > len {
+        anyhow::bail!("Positions must be between 1 and {}", len);
+    }
+    
+    let item = items.remove(from - 1);
+    items.insert(to - 1, item);
+    Ok(())
+}
+
+async fn sync(full: bool) -> Result<()> {
+    let config = AuthClient::ensure_authenticated().await?;
+    let client = MusicClient::new(config);
+    let mut catalog = LocalCatalog::open_default()?;
+    
+    let report = catalog::sync(&client, &mut catalog, full).await?;
+    
+    if report.unchanged {
+        println!("Catalog is up to date ({} tracks).", report.total);
+        return Ok(());
+    }
+    
+    if report.initial {
+        println!("{} Synced {} tracks.", "✓".green(), report.total);
+        return Ok(());
+    }
+    
+    println!(
+        "{} {} added, {} updated, {} removed ({} tracks)",
+        "✓".green(),
+        report.added.len(),
+        report.updated,
+        report.removed,
+        report.total
+    );
+    for track in report.added.iter().take(SYNC_REPORT_LIMIT) {
+        println!("  {} {}", "+".green(), track.label());
+    }
+    if report.added.len() > SYNC_REPORT_LIMIT {
+        println!("  {}", format!("…and {} more; see `lynx-fm whats-new`", report.added.len() - SYNC_REPORT_LIMIT).dimmed());
+    }
+    
+    Ok(())
+}
+
+fn whats_new(days: u32, format: OutputFormat) -> Result<()> {
+    let catalog = LocalCatalog::open_default()?;
+    
+    let Some(last_sync) = catalog.last_sync()? else {
+        anyhow::bail!("The catalog hasn't been synced yet; run `lynx-fm sync` first");
+    };
+    
+    let since = chrono::Utc::now().timestamp() - i64::from(days) * 24 * 60 * 60;
+    let tracks = catalog.added_since(since)?;
+    
+    if format == OutputFormat::Table {
+        println!(
+            "{}",
+            format!("Added in the last {} days; last synced {} ago", days, format_duration(chrono::Utc::now().timestamp() - last_sync)).dimmed()
+        );
+    }
+    
+    print_tracks(&tracks, format)
+}
+
+fn history(since: Option<chrono::DateTime<chrono::Utc>>, limit: Option<usize>, json: bool, export: Option<&Path>) -> Result<()> {
+    let plays = History::open_default()?.plays(since, limit)?;
+    
+    if let Some(path) = export {
+        let content = match path.extension().and_then(|ext| ext.to_str()).map(str::to_lowercase).as_deref() {
+            Some("csv") => history::to_csv(&plays),
+            Some("json") => serde_json::to_string_pretty(&plays)?,
+            _ => anyhow::bail!("Can't tell the export format of {}; use a .csv or .json file", path.display()),
+        };
+        fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Exported {} plays to {}", plays.len(), path.display());
+        return Ok(());
+    }
+    
+    let rows = plays
+        .iter()
+        .map(|play| {
+            vec![
+                play.started_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string(),
+                play.label(),
+                format_length(play.listened),
+                if play.completed { "played" } else { "skipped" }.to_string(),
+                play.source.to_string(),
+            ]
+        })
+        .collect();
+        
+    let format = if json { OutputFormat::Json } else { OutputFormat::Table };
+    print_listing(&plays, &["STARTED", "TRACK", "LISTENED", "RESULT", "SOURCE"], rows, "plays", format)
+}
+
+/// Summarise the listening history for a period, or write it up as a report
+fn stats(
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    until: Option<chrono::DateTime<chrono::Utc>>,
+    year: Option<i32>,
+    top: usize,
+    report: Option<ReportFormat>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    use chrono::TimeZone;
+    
+    let day = |time: chrono::DateTime<chrono::Utc>| time.with_timezone(&chrono::Local).format("%Y-%m-%d").to_string();
+    let (since, until, period) = match year {
+        Some(year) => {
+            let start = |year| chrono::Local.with_ymd_and_hms(year, 1, 1, 0, 0, 0).earliest().map(|t| t.to_utc());
+            let since = start(year).with_context(|| format!("Invalid year {}", year))?;
+            (Some(since), start(year + 1), year.to_string())
+        }
+        None => {
+            let period = match (since, until) {
+                (None, None) => "All time".to_string(),
+                (Some(since), None) => format!("Since {}", day(since)),
+                (None, Some(until)) => format!("Until {}", day(until)),
+                (Some(since), Some(until)) => format!("{} to {}", day(since), day(until)),
+            };
+            (since, until, period)
+        }
+    };
+    
+    let mut plays = History::open_default()?.plays(since, None)?;
+    if let Some(until) = until {
+        plays.retain(|play| play.started_at < until);
+    }
+    
+    // Genres, and artists the history didn't record, come from the synced catalog
+    let catalog: HashMap<String, Track> = LocalCatalog::open_default()?
+        .tracks()?
+        .into_iter()
+        .map(|track| (track.id.clone(), track))
+        .collect();
+        
+    let stats = Stats::compute(&period, &plays, &catalog, top, &chrono::Local);
+    
+    if let Some(format) = report {
+        let path = output.unwrap_or_else(|| {
+            let name = if year.is_some() { period.clone() } else { "report".to_string() };
+            PathBuf::from(format!("lynx-fm-{}.{}", name, format.extension()))
+        });
+        fs::write(&path, stats.render(format)).with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Wrote the report ({}) to {}", period, path.display());
+        return Ok(());
+    }
+    
+    if stats.plays == 0 {
+        println!("No plays found ({}).", period);
+        return Ok(());
+    }
+    
+    println!("{}", format!("Listening: {}", period).bold());
+    println!(
+        "{} plays, {} listened, {:.0}% skipped",
+        stats.plays,
+        format_duration(stats.listened.round() as i64),
+        stats.skip_rate() * 100.0
+    );
+    
+    for (heading, ranking) in [("TRACK", &stats.tracks), ("ARTIST", &stats.artists), ("GENRE", &stats.genres)] {
+        if ranking.is_empty() {
+            continue;
+        }
+        let rows: Vec<Vec<String>> = ranking
+            .iter()
+            .enumerate()
+            .map(|(i, item)| vec![(i + 1).to_string(), item.name.clone(), item.plays.to_string(), format_length(item.listened)])
+            .collect();
+        println!("\n{}", display::table(&["#", heading, "PLAYS", "TIME"], &rows));
+    }
+    if catalog.is_empty() {
+        println!("\n{}", "Run `lynx-fm sync` to rank genres too.".dimmed());
+    }
+    
+    println!("\n{}\n{}", "Listening by hour".bold(), stats.heatmap_text());
+    
+    Ok(())
+}
+
+/// Print catalog values starting with a prefix, one per line, for shell completion
+fn complete(prefix: &str) -> Result<()> {
+    let catalog = LocalCatalog::open_default()?;
+    
+    for value in catalog.complete(prefix, COMPLETION_LIMIT)? {
+        println!("{}", value);
+    }
+    
+    Ok(())
+}
+
+async fn tracks(command: TrackCommands) -> Result<()> {
+    match command {
+        TrackCommands::List { sort, artist, album, limit, format } => {
+            let config = AuthClient::ensure_authenticated().await?;
+            let client = MusicClient::new(config);
+            
+            let filter = TrackFilter { artist, album, sort, limit };
+            let tracks = client.list_filtered_tracks(&filter).await?;
+            
+            print_tracks(&tracks, format)?;
+        }
+    }
+    
+    Ok(())
+}
+
+fn print_tracks(tracks: &[Track], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(tracks)?);
+        }
+        OutputFormat::Table => {
+            if tracks.is_empty() {
+                println!("No tracks found.");
+                return Ok(());
+            }
+            
+            let rows: Vec<Vec<String>> = tracks
+                .iter()
+                .map(|track| {
+                    vec![
+                        track.id.clone(),
+                        track.display_title().to_string(),
+                        track.artist.clone().unwrap_or_default(),
+                        track.album.clone().unwrap_or_default(),
+                        track.duration.map(format_length).unwrap_or_default(),
+                    ]
+                })
+                .collect();
+                
+            println!("{}", display::table(&["ID", "TITLE", "ARTIST", "ALBUM", "LENGTH"], &rows));
+            println!("{}", format!("{} tracks", tracks.len()).dimmed());
+        }
+    }
     
     Ok(())
 }
@@ -149,3 +1418,39 @@ async fn prefetch_tracks(track_ids: Vec<String>) -> Result<()> {
     
     Ok(())
 }
+
+async fn api_request(
+    method: &str,
+    path: &str,
+    data: Option<serde_json::Value>,
+    headers: Vec<(String, String)>,
+) -> Result<()> {
+    let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes())
+        .with_context(|| format!("Invalid HTTP method: {}", method))?;
+        
+    let config = AuthClient::ensure_authenticated().await?;
+    let client = MusicClient::new(config);
+    
+    let response = client.api_request(method, path, data.as_ref(), &headers).await?;
+    let status = response.status();
+    let body = response.text().await.context("Failed to read response body")?;
+    
+    if status.is_success() {
+        eprintln!("{}", status.to_string().green());
+    } else {
+        eprintln!("{}", status.to_string().red());
+    }
+    
+    // Pretty-print JSON bodies, pass anything else through untouched
+    match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(json) => println!("{}", serde_json::to_string_pretty(&json)?),
+        Err(_) if !body.is_empty() => println!("{}", body),
+        Err(_) => {}
+    }
+    
+    if !status.is_success() {
+        anyhow::bail!("Request failed with status {}", status);
+    }
+    
+    Ok(())
+}
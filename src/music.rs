@@ -1,12 +1,385 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
-use rodio::{Decoder, OutputStream, Sink};
+use rodio::cpal::traits::HostTrait;
+use rodio::{Decoder, DeviceTrait, OutputStream, OutputStreamHandle, Sink};
+use reqwest::header::{HeaderName, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::io::Cursor;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
 
+use crate::auth::TokenClaims;
 use crate::config::Config;
 
+/// How often playback checks whether Ctrl-C asked to skip the track
+const SKIP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Set while audio plays, so Ctrl-C skips the track instead of quitting
+static PLAYING: AtomicBool = AtomicBool::new(false);
+
+/// Set by Ctrl-C during playback
+static SKIP: AtomicBool = AtomicBool::new(false);
+
+/// Make Ctrl-C skip the playing track. At any other time it still quits.
+fn install_skip_handler() {
+    static INSTALLED: Once = Once::new();
+    
+    INSTALLED.call_once(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if PLAYING.load(Ordering::SeqCst) && !SKIP.swap(true, Ordering::SeqCst) {
+                    eprintln!("\nSkipped");
+                    continue;
+                }
+                std::process::exit(130);
+            }
+        });
+    });
+}
+
+/// How a playback went
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Playback {
+    pub started_at: DateTime<Utc>,
+    /// Seconds of audio actually played
+    pub listened: f64,
+    /// Played to the end rather than skipped
+    pub completed: bool,
+}
+
+/// The identity the server associates with the current credentials
+#[derive(Debug, Deserialize, Clone)]
+pub struct Identity {
+    pub user_id: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+impl Identity {
+    /// Describe every field where the server disagrees with the local token
+    pub fn mismatches(&self, claims: &TokenClaims) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        
+        let pairs = [
+            ("user ID", claims.sub.as_deref(), Some(self.user_id.as_str())),
+            ("email", claims.email.as_deref(), self.email.as_deref()),
+            ("role", claims.role.as_deref(), self.role.as_deref()),
+        ];
+        
+        for (field, local, server) in pairs {
+            if let (Some(local), Some(server)) = (local, server) {
+                if !local.eq_ignore_ascii_case(server) {
+                    mismatches.push(format!(
+                        "{} differs: token has '{}', server reports '{}'",
+                        field, local, server
+                    ));
+                }
+            }
+        }
+        
+        mismatches
+    }
+}
+
+/// How many tracks to ask for per request when listing the catalog
+pub const TRACK_PAGE_SIZE: usize = 200;
+
+/// A track in the server's catalog. Only `id` is guaranteed; the rest is shown when present.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Track {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    /// Length in seconds
+    #[serde(default)]
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub track_number: Option<u32>,
+    #[serde(default)]
+    pub year: Option<i32>,
+    /// When the track was added to the catalog (RFC 3339)
+    #[serde(default, alias = "created_at")]
+    pub added_at: Option<String>,
+    /// When the track's metadata last changed (RFC 3339)
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    /// Tempo in beats per minute
+    #[serde(default)]
+    pub bpm: Option<f64>,
+    /// Rating out of 5
+    #[serde(default)]
+    pub rating: Option<f64>,
+}
+
+impl Track {
+    /// The title, or the ID for tracks without one
+    pub fn display_title(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.id)
+    }
+    
+    /// `Artist - Title`, or just the title when the artist is unknown
+    pub fn label(&self) -> String {
+        match &self.artist {
+            Some(artist) => format!("{} - {}", artist, self.display_title()),
+            None => self.display_title().to_string(),
+        }
+    }
+}
+
+/// Track IDs may be numbers or strings depending on the server
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => Ok(s),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        other => Err(serde::de::Error::custom(format!("invalid track id: {}", other))),
+    }
+}
+
+/// A page of `/tracks`, which is either a bare array or wrapped in an object
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TrackPage {
+    List(Vec<Track>),
+    Wrapped { tracks: Vec<Track> },
+}
+
+/// A track picked by `/random`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RandomPick {
+    pub id: String,
+    /// Metadata, when the server sent any along with the ID
+    pub track: Option<Track>,
+}
+
+/// `/random` answers with `{"track_id": ..}`, a track object, or the bare ID as text
+fn parse_random_pick(text: &str) -> Option<RandomPick> {
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(text) else {
+        let id = text.trim();
+        return (!id.is_empty()).then(|| RandomPick { id: id.to_string(), track: None });
+    };
+    
+    let id = match json.get("track_id").or_else(|| json.get("id"))? {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => return None,
+    };
+    
+    let has_metadata = ["title", "artist", "genre", "duration"].iter().any(|key| json.get(key).is_some());
+    let track = has_metadata
+        .then(|| {
+            let mut json = json.clone();
+            json["id"] = serde_json::Value::String(id.clone());
+            serde_json::from_value::<Track>(json).ok()
+        })
+        .flatten();
+        
+    Some(RandomPick { id, track })
+}
+
+/// An artist, as listed by `/artists` or derived from track metadata
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Artist {
+    pub name: String,
+    #[serde(default, alias = "album_count")]
+    pub albums: Option<usize>,
+    #[serde(default, alias = "track_count")]
+    pub tracks: Option<usize>,
+}
+
+/// An album, as listed by `/albums` or derived from track metadata
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Album {
+    #[serde(alias = "name")]
+    pub title: String,
+    #[serde(default)]
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub year: Option<i32>,
+    #[serde(default, alias = "track_count")]
+    pub tracks: Option<usize>,
+}
+
+/// A genre, as listed by `/genres` or derived from track metadata
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Genre {
+    pub name: String,
+    #[serde(default, alias = "track_count")]
+    pub tracks: Option<usize>,
+}
+
+/// A browse listing, which is either a bare array or wrapped in an object
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Listing<T> {
+    List(Vec<T>),
+    Wrapped {
+        #[serde(alias = "artists", alias = "albums", alias = "genres")]
+        items: Vec<T>,
+    },
+}
+
+/// A playlist stored on the server
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Playlist {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Only filled in when fetching a single playlist
+    #[serde(default)]
+    pub tracks: Vec<Track>,
+    #[serde(default)]
+    pub track_count: Option<usize>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+impl Playlist {
+    /// How many tracks it holds, whether or not they were fetched
+    pub fn track_total(&self) -> usize {
+        self.track_count.unwrap_or(self.tracks.len())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PlaylistRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct PlaylistTracksRequest<'a> {
+    track_ids: &'a [String],
+}
+
+/// `/api/playlists` listing, which is either a bare array or wrapped in an object
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PlaylistPage {
+    List(Vec<Playlist>),
+    Wrapped { playlists: Vec<Playlist> },
+}
+
+/// What the previous sync saw, sent back so the server can skip unchanged data
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncToken {
+    /// `ETag` of the last listing
+    pub etag: Option<String>,
+    /// `Last-Modified` of the last listing
+    pub last_modified: Option<String>,
+    /// Newest `updated_at` seen, sent as `updated_since`
+    pub cursor: Option<String>,
+}
+
+/// The server's answer to "what changed since the last sync"
+#[derive(Debug)]
+pub enum CatalogChanges {
+    /// 304 Not Modified
+    Unchanged,
+    /// Changed tracks, or the whole catalog when `complete`
+    Changed {
+        tracks: Vec<Track>,
+        complete: bool,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+/// Sort orders for track listings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackSort {
+    Title,
+    Artist,
+    Album,
+    Duration,
+    /// Newest first
+    Added,
+}
+
+/// Client-side filtering and sorting of a track listing
+#[derive(Debug, Clone, Default)]
+pub struct TrackFilter {
+    /// Case-insensitive substring of the artist
+    pub artist: Option<String>,
+    /// Case-insensitive substring of the album
+    pub album: Option<String>,
+    pub sort: Option<TrackSort>,
+    pub limit: Option<usize>,
+}
+
+impl TrackFilter {
+    pub fn apply(&self, tracks: Vec<Track>) -> Vec<Track> {
+        let matches = |field: &Option<String>, wanted: &Option<String>| match wanted {
+            Some(wanted) => field
+                .as_deref()
+                .is_some_and(|value| value.to_lowercase().contains(&wanted.to_lowercase())),
+            None => true,
+        };
+        
+        let mut tracks: Vec<Track> = tracks
+            .into_iter()
+            .filter(|track| matches(&track.artist, &self.artist) && matches(&track.album, &self.album))
+            .collect();
+            
+        let key = |value: &Option<String>| value.as_deref().unwrap_or("").to_lowercase();
+        match self.sort {
+            Some(TrackSort::Title) => tracks.sort_by_key(|t| t.display_title().to_lowercase()),
+            Some(TrackSort::Artist) => tracks.sort_by_key(|t| (key(&t.artist), key(&t.album), t.track_number)),
+            Some(TrackSort::Album) => tracks.sort_by_key(|t| (key(&t.album), t.track_number)),
+            Some(TrackSort::Duration) => tracks.sort_by(|a, b| {
+                a.duration.unwrap_or(0.0).total_cmp(&b.duration.unwrap_or(0.0))
+            }),
+            Some(TrackSort::Added) => tracks.sort_by(|a, b| b.added_at.cmp(&a.added_at)),
+            None => {}
+        }
+        
+        if let Some(limit) = self.limit {
+            tracks.truncate(limit);
+        }
+        tracks
+    }
+    
+    /// Whether `tracks` already hold everything `apply` would keep, so no more pages are needed.
+    ///
+    /// Only an unsorted listing can stop early; a sort needs every track.
+    pub fn is_satisfied_by(&self, tracks: &[Track]) -> bool {
+        match (self.limit, self.sort) {
+            (Some(limit), None) => self.apply(tracks.to_vec()).len() >= limit,
+            _ => false,
+        }
+    }
+}
+
+/// `base` with `segments` appended to its path, each percent-encoded so an ID can't change the route
+pub fn segment_url(base: &str, segments: &[&str]) -> Result<reqwest::Url> {
+    let mut url = reqwest::Url::parse(base).with_context(|| format!("Invalid music server URL '{}'", base))?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("Invalid music server URL '{}'", base))?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
+
 pub struct MusicClient {
     pub config: Config,
     client: reqwest::Client,
@@ -15,7 +388,7 @@ pub struct MusicClient {
 impl MusicClient {
     pub fn new(config: Config) -> Self {
         let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
+            .timeout(Duration::from_secs(config.request_timeout_secs))
             .build()
             .expect("Failed to build HTTP client");
             
@@ -34,9 +407,362 @@ impl MusicClient {
         Ok(response.status().is_success())
     }
     
-    pub async fn get_random_track(&self) -> Result<String> {
+    pub async fn me(&self) -> Result<Identity> {
+        let url = format!("{}/me", self.config.music_server_url);
+        
+        let mut request = self.client.get(&url);
+        
+        if let Some(token) = &self.config.auth_token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        
+        let response = request
+            .send()
+            .await
+            .context("Failed to send /me request")?;
+            
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            anyhow::bail!("Failed to get identity ({}): {}", status, error);
+        }
+        
+        response.json().await.context("Failed to parse /me response")
+    }
+    
+    /// Fetch one page of the catalog
+    pub async fn list_tracks_page(&self, offset: usize, limit: usize) -> Result<Vec<Track>> {
+        let response = self.tracks_request(offset, limit, None)
+            .send()
+            .await
+            .context("Failed to request track list")?;
+            
+        Self::parse_track_page(response).await
+    }
+    
+    /// `GET /tracks`, optionally only for tracks changed since a sync cursor
+    fn tracks_request(&self, offset: usize, limit: usize, updated_since: Option<&str>) -> reqwest::RequestBuilder {
+        let url = format!("{}/tracks", self.config.music_server_url);
+        
+        let mut request = self.client
+            .get(&url)
+            .query(&[("offset", offset), ("limit", limit)]);
+            
+        if let Some(cursor) = updated_since {
+            request = request.query(&[("updated_since", cursor)]);
+        }
+        
+        if let Some(token) = &self.config.auth_token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        
+        request
+    }
+    
+    async fn parse_track_page(response: reqwest::Response) -> Result<Vec<Track>> {
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            anyhow::bail!("Failed to list tracks ({}): {}", status, error);
+        }
+        
+        let page: TrackPage = response.json().await.context("Failed to parse track list")?;
+        Ok(match page {
+            TrackPage::List(tracks) | TrackPage::Wrapped { tracks } => tracks,
+        })
+    }
+    
+    /// Ask the server to search titles, artists and albums.
+    ///
+    /// Returns `None` when the server has no search endpoint.
+    pub async fn search_tracks(&self, query: &str) -> Result<Option<Vec<Track>>> {
+        let url = format!("{}/search", self.config.music_server_url);
+        
+        let mut request = self.client.get(&url).query(&[("q", query)]);
+        
+        if let Some(token) = &self.config.auth_token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        
+        let response = request
+            .send()
+            .await
+            .context("Failed to send search request")?;
+            
+        let status = response.status();
+        if matches!(status.as_u16(), 404 | 405 | 501) {
+            return Ok(None);
+        }
+        
+        if !status.is_success() {
+            let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            anyhow::bail!("Search failed ({}): {}", status, error);
+        }
+        
+        let page: TrackPage = response.json().await.context("Failed to parse search results")?;
+        Ok(Some(match page {
+            TrackPage::List(tracks) | TrackPage::Wrapped { tracks } => tracks,
+        }))
+    }
+    
+    /// Artists from the server's `/artists` endpoint, or `None` if it has none
+    pub async fn list_artists(&self) -> Result<Option<Vec<Artist>>> {
+        self.get_listing("artists", &[]).await
+    }
+    
+    /// Albums from the server's `/albums` endpoint, or `None` if it has none
+    pub async fn list_albums(&self, artist: Option<&str>) -> Result<Option<Vec<Album>>> {
+        let query: Vec<(&str, &str)> = artist.map(|artist| ("artist", artist)).into_iter().collect();
+        self.get_listing("albums", &query).await
+    }
+    
+    /// Genres from the server's `/genres` endpoint, or `None` if it has none
+    pub async fn list_genres(&self) -> Result<Option<Vec<Genre>>> {
+        self.get_listing("genres", &[]).await
+    }
+    
+    async fn get_listing<T: DeserializeOwned>(&self, kind: &str, query: &[(&str, &str)]) -> Result<Option<Vec<T>>> {
+        let url = format!("{}/{}", self.config.music_server_url, kind);
+        
+        let mut request = self.client.get(&url).query(query);
+        
+        if let Some(token) = &self.config.auth_token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to request {}", kind))?;
+            
+        let status = response.status();
+        if matches!(status.as_u16(), 404 | 405 | 501) {
+            return Ok(None);
+        }
+        
+        if !status.is_success() {
+            let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            anyhow::bail!("Failed to list {} ({}): {}", kind, status, error);
+        }
+        
+        let listing: Listing<T> = response.json().await
+            .with_context(|| format!("Failed to parse {} list", kind))?;
+        Ok(Some(match listing {
+            Listing::List(items) | Listing::Wrapped { items } => items,
+        }))
+    }
+    
+    pub async fn list_playlists(&self) -> Result<Vec<Playlist>> {
+        let response = self.send_playlist_request(Method::GET, &[], "list playlists", None::<&()>).await?;
+        
+        let page: PlaylistPage = response.json().await.context("Failed to parse playlists")?;
+        Ok(match page {
+            PlaylistPage::List(playlists) | PlaylistPage::Wrapped { playlists } => playlists,
+        })
+    }
+    
+    /// Find a playlist by ID or by name (case-insensitive)
+    pub async fn find_playlist(&self, reference: &str) -> Result<Playlist> {
+        let playlists = self.list_playlists().await?;
+        
+        if let Some(playlist) = playlists.iter().find(|p| p.id == reference) {
+            return Ok(playlist.clone());
+        }
+        
+        let named: Vec<&Playlist> = playlists
+            .iter()
+            .filter(|p| p.name.eq_ignore_ascii_case(reference))
+            .collect();
+            
+        match named.as_slice() {
+            [playlist] => Ok((*playlist).clone()),
+            [] => anyhow::bail!("No playlist named '{}'", reference),
+            _ => anyhow::bail!("Several playlists are named '{}'; use its ID instead", reference),
+        }
+    }
+    
+    /// A playlist with its tracks, in order
+    pub async fn get_playlist(&self, id: &str) -> Result<Playlist> {
+        let response = self.send_playlist_request(Method::GET, &[id], "show playlists", None::<&()>).await?;
+        response.json().await.context("Failed to parse playlist")
+    }
+    
+    pub async fn create_playlist(&self, name: &str, description: Option<&str>) -> Result<Playlist> {
+        let body = PlaylistRequest { name: Some(name), description };
+        let response = self.send_playlist_request(Method::POST, &[], "create playlists", Some(&body)).await?;
+        response.json().await.context("Failed to parse created playlist")
+    }
+    
+    pub async fn rename_playlist(&self, id: &str, name: &str) -> Result<()> {
+        let body = PlaylistRequest { name: Some(name), description: None };
+        self.send_playlist_request(Method::PATCH, &[id], "rename playlists", Some(&body)).await?;
+        Ok(())
+    }
+    
+    pub async fn delete_playlist(&self, id: &str) -> Result<()> {
+        self.send_playlist_request(Method::DELETE, &[id], "delete playlists", None::<&()>).await?;
+        Ok(())
+    }
+    
+    /// Append tracks to the end of a playlist
+    pub async fn add_to_playlist(&self, id: &str, track_ids: &[String]) -> Result<()> {
+        let body = PlaylistTracksRequest { track_ids };
+        self.send_playlist_request(Method::POST, &[id, "tracks"], "add tracks to playlists", Some(&body)).await?;
+        Ok(())
+    }
+    
+    pub async fn remove_from_playlist(&self, id: &str, track_id: &str) -> Result<()> {
+        let segments = [id, "tracks", track_id];
+        self.send_playlist_request(Method::DELETE, &segments, "remove tracks from playlists", None::<&()>).await?;
+        Ok(())
+    }
+    
+    /// Replace a playlist's order with `track_ids`, which must hold the same tracks
+    pub async fn reorder_playlist(&self, id: &str, track_ids: &[String]) -> Result<()> {
+        let body = PlaylistTracksRequest { track_ids };
+        self.send_playlist_request(Method::PUT, &[id, "tracks"], "reorder playlists", Some(&body)).await?;
+        Ok(())
+    }
+    
+    /// Send an authenticated request under `/api/playlists`, turning failures into
+    /// errors that say whether the server lacks the operation or the playlist is missing.
+    ///
+    /// `segments` are appended to the path percent-encoded, so IDs can't change the route.
+    async fn send_playlist_request<B: Serialize>(
+        &self,
+        method: Method,
+        segments: &[&str],
+        operation: &str,
+        body: Option<&B>,
+    ) -> Result<reqwest::Response> {
+        let path: Vec<&str> = ["api", "playlists"].iter().chain(segments).copied().collect();
+        let url = segment_url(&self.config.music_server_url, &path)?;
+        
+        let mut request = self.client.request(method, url);
+        
+        if let Some(token) = &self.config.auth_token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to {}", operation))?;
+            
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        
+        let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        match status.as_u16() {
+            405 | 501 => anyhow::bail!("This server doesn't support playlists ({}): it can't {}", status, operation),
+            404 if segments.is_empty() => anyhow::bail!("This server doesn't support playlists ({})", status),
+            404 => anyhow::bail!("Playlist or track not found ({}): {}", status, error),
+            401 | 403 => anyhow::bail!("Not allowed to {} ({}): {}", operation, status, error),
+            _ => anyhow::bail!("Failed to {} ({}): {}", operation, status, error),
+        }
+    }
+    
+    /// Fetch the whole catalog, a page at a time
+    pub async fn list_tracks(&self) -> Result<Vec<Track>> {
+        let first = self.list_tracks_page(0, TRACK_PAGE_SIZE).await?;
+        self.collect_pages(first, None, |_| false).await
+    }
+    
+    /// The tracks `filter` keeps, fetching no more pages than it needs
+    pub async fn list_filtered_tracks(&self, filter: &TrackFilter) -> Result<Vec<Track>> {
+        let first = self.list_tracks_page(0, TRACK_PAGE_SIZE).await?;
+        let tracks = self.collect_pages(first, None, |tracks| filter.is_satisfied_by(tracks)).await?;
+        Ok(filter.apply(tracks))
+    }
+    
+    /// Ask what changed since the last sync, or for everything when there wasn't one.
+    ///
+    /// The previous `ETag` and `Last-Modified` make the request conditional, and the
+    /// cursor asks for tracks updated since then. Servers may ignore any of these.
+    pub async fn catalog_changes(&self, since: Option<&SyncToken>) -> Result<CatalogChanges> {
+        let updated_since = since.and_then(|token| token.cursor.as_deref());
+        let mut request = self.tracks_request(0, TRACK_PAGE_SIZE, updated_since);
+        
+        if let Some(token) = since {
+            if let Some(etag) = &token.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &token.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        
+        let response = request
+            .send()
+            .await
+            .context("Failed to request track list")?;
+            
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(CatalogChanges::Unchanged);
+        }
+        
+        let header = |name: HeaderName| {
+            response.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        
+        let first = Self::parse_track_page(response).await?;
+        let tracks = self.collect_pages(first, updated_since, |_| false).await?;
+        
+        Ok(CatalogChanges::Changed {
+            tracks,
+            complete: updated_since.is_none(),
+            etag,
+            last_modified,
+        })
+    }
+    
+    /// Follow pages after `first` until a short page, or until `done` says there are enough
+    async fn collect_pages(
+        &self,
+        first: Vec<Track>,
+        updated_since: Option<&str>,
+        done: impl Fn(&[Track]) -> bool,
+    ) -> Result<Vec<Track>> {
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut page = first;
+        
+        loop {
+            let count = page.len();
+            
+            // A server that ignores paging keeps sending the first page
+            let repeated = count > 0 && tracks.first().map(|t| &t.id) == page.first().map(|t| &t.id);
+            if repeated {
+                break;
+            }
+            tracks.extend(page);
+            
+            // A short page is the last one; a long one means the server ignores paging
+            if count != TRACK_PAGE_SIZE || done(&tracks) {
+                break;
+            }
+            
+            let response = self.tracks_request(tracks.len(), TRACK_PAGE_SIZE, updated_since)
+                .send()
+                .await
+                .context("Failed to request track list")?;
+            page = Self::parse_track_page(response).await?;
+        }
+        
+        Ok(tracks)
+    }
+    
+    pub async fn get_random_track(&self) -> Result<RandomPick> {
         let url = format!("{}/random", self.config.music_server_url);
-        println!("Requesting random track from: {}", url);
         
         // The random endpoint is now public, no authentication required
         let response = self.client
@@ -45,11 +771,8 @@ impl MusicClient {
             .await
             .context("Failed to get random track")?;
             
-        println!("Response status: {}", response.status());
-        
         if !response.status().is_success() {
             let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            println!("Error response body: {}", error);
             anyhow::bail!("Failed to get random track: {}", error);
         }
         
@@ -57,33 +780,63 @@ impl MusicClient {
         self.extract_track_id_from_response(response).await
     }
     
-    async fn extract_track_id_from_response(&self, response: reqwest::Response) -> Result<String> {
+    /// Ask `/random` for a track matching `query`.
+    ///
+    /// Returns `None` when the server rejects the filters or has nothing that matches.
+    /// Servers that don't know the filters may ignore them, so callers should check
+    /// the metadata that comes back.
+    pub async fn random_track_matching(&self, query: &[(&str, String)]) -> Result<Option<RandomPick>> {
+        let url = format!("{}/random", self.config.music_server_url);
+        
+        let response = self.client
+            .get(&url)
+            .query(query)
+            .send()
+            .await
+            .context("Failed to get random track")?;
+            
+        let status = response.status();
+        if matches!(status.as_u16(), 400 | 404 | 405 | 422 | 501) {
+            return Ok(None);
+        }
+        
+        if !status.is_success() {
+            let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            anyhow::bail!("Failed to get random track ({}): {}", status, error);
+        }
+        
+        let text = response.text().await?;
+        Ok(parse_random_pick(&text))
+    }
+    
+    async fn extract_track_id_from_response(&self, response: reqwest::Response) -> Result<RandomPick> {
         // First, try to parse as JSON (new format)
         let text = response.text().await?;
-        println!("Response body: {}", text);
         
+        let id = Self::extract_track_id(&text)?;
+        
+        // Keep whatever metadata came along, for the listening history
+        let track = parse_random_pick(&text).and_then(|pick| pick.track);
+        Ok(RandomPick { id, track })
+    }
+    
+    fn extract_track_id(text: &str) -> Result<String> {
         // Try to parse the JSON response
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) {
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(text) {
             // Check if there's a track_id field
             if let Some(track_id) = json.get("track_id").and_then(|v| v.as_str()) {
-                println!("Extracted track ID from JSON: {}", track_id);
                 return Ok(track_id.to_string());
             }
             
             // Check if there's an id field
             if let Some(track_id) = json.get("id").and_then(|v| v.as_str()) {
-                println!("Extracted track ID from JSON: {}", track_id);
                 return Ok(track_id.to_string());
             }
-            
-            println!("No track ID found in JSON response: {:?}", json);
         } else {
-            println!("Response is not valid JSON, trying to extract track ID from text");
             // If it's not JSON, try to extract the track ID from the text
             // This is a fallback in case the server returns just the ID as plain text
             let track_id = text.trim();
             if !track_id.is_empty() {
-                println!("Using response text as track ID: {}", track_id);
                 return Ok(track_id.to_string());
             }
         }
@@ -91,13 +844,18 @@ impl MusicClient {
         anyhow::bail!("No track ID found in response")
     }
     
-    pub async fn stream_track(&self, track_id: &str) -> Result<()> {
-        let url = format!("{}/tracks/{}", self.config.music_server_url, track_id);
+    /// Play a track from the server, and report how far it got
+    pub async fn stream_track(&self, track_id: &str) -> Result<Playback> {
+        let data = self.download_track(track_id).await?;
         
-        println!("Streaming track: {}", track_id);
+        // Play the audio
+        self.play_audio(&data)
+    }
+    
+    async fn download_track(&self, track_id: &str) -> Result<Vec<u8>> {
+        let url = format!("{}/tracks/{}", self.config.music_server_url, track_id);
         
         // Try with JWT token (primary method)
-        println!("Trying with JWT token...");
         let mut request = self.client.get(&url);
         
         // Add the JWT token
@@ -111,14 +869,8 @@ impl MusicClient {
             .await
             .context("Failed to start streaming track")?;
             
-        println!("Response status: {}", response.status());
-        
         if !response.status().is_success() {
-            let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            println!("Error response body: {}", error);
-            
             // Try with Supabase anon key as apikey header (fallback method)
-            println!("Trying with Supabase anon key...");
             let response = self.client
                 .get(&url)
                 .header("apikey", &self.config.supabase_anon_key)
@@ -126,11 +878,8 @@ impl MusicClient {
                 .await
                 .context("Failed to stream track with anon key")?;
                 
-            println!("Response status with anon key: {}", response.status());
-            
             if !response.status().is_success() {
                 let error = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                println!("Error response body with anon key: {}", error);
                 anyhow::bail!("Failed to stream track: {}", error);
             }
             
@@ -142,7 +891,7 @@ impl MusicClient {
         self.process_stream_response(response).await
     }
     
-    async fn process_stream_response(&self, response: reqwest::Response) -> Result<()> {
+    async fn process_stream_response(&self, response: reqwest::Response) -> Result<Vec<u8>> {
         // Get content length for progress bar
         let content_length = response
             .content_length()
@@ -169,21 +918,17 @@ impl MusicClient {
         
         pb.finish_with_message("Download complete");
         
-        // Play the audio
-        println!("Playing track...");
-        self.play_audio(&buffer)?;
-        
-        Ok(())
+        Ok(buffer)
     }
     
-    fn play_audio(&self, data: &[u8]) -> Result<()> {
-        // Get a output stream handle to the default physical sound device
-        let (_stream, stream_handle) = OutputStream::try_default()
-            .context("Failed to get audio output stream")?;
+    fn play_audio(&self, data: &[u8]) -> Result<Playback> {
+        // Get a output stream handle to the configured (or default) sound device
+        let (_stream, stream_handle) = self.output_stream()?;
             
         // Create a sink to play the audio
         let sink = Sink::try_new(&stream_handle)
             .context("Failed to create audio sink")?;
+        sink.set_volume(self.config.volume);
             
         // Load the audio data
         let cursor = Cursor::new(data.to_vec());
@@ -194,12 +939,107 @@ impl MusicClient {
         sink.append(source);
         
         // Play the audio
+        install_skip_handler();
+        let started_at = Utc::now();
+        let clock = Instant::now();
+        SKIP.store(false, Ordering::SeqCst);
+        PLAYING.store(true, Ordering::SeqCst);
         sink.play();
         
-        // Wait for the audio to finish
-        sink.sleep_until_end();
+        // Poll rather than sleep until the end, so Ctrl-C can cut the track short
+        while !sink.empty() && !SKIP.load(Ordering::SeqCst) {
+            std::thread::sleep(SKIP_POLL_INTERVAL);
+        }
         
-        Ok(())
+        PLAYING.store(false, Ordering::SeqCst);
+        let completed = !SKIP.swap(false, Ordering::SeqCst);
+        sink.stop();
+        
+        Ok(Playback { started_at, listened: clock.elapsed().as_secs_f64(), completed })
+    }
+    
+    /// Open the output device named by `audio_device`, or the default one
+    pub fn output_stream(&self) -> Result<(OutputStream, OutputStreamHandle)> {
+        let name = match &self.config.audio_device {
+            Some(name) => name,
+            None => return OutputStream::try_default().context("Failed to get audio output stream"),
+        };
+        
+        let device = rodio::cpal::default_host()
+            .output_devices()
+            .context("Failed to list audio devices")?
+            .find(|device| device.name().map(|n| n == *name).unwrap_or(false))
+            .with_context(|| format!("Audio device '{}' not found", name))?;
+            
+        OutputStream::try_from_device(&device)
+            .with_context(|| format!("Failed to open audio device '{}'", name))
+    }
+    
+    /// Resolve an endpoint path against the configured server URL
+    pub fn resolve_url(&self, path: &str) -> String {
+        if path.starts_with("http://") || path.starts_with("https://") {
+            return path.to_string();
+        }
+        
+        format!(
+            "{}/{}",
+            self.config.music_server_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+    
+    /// Whether a URL points at the configured server (same scheme, host and port)
+    pub fn is_server_url(&self, url: &str) -> bool {
+        let origin = |url: &str| {
+            reqwest::Url::parse(url)
+                .ok()
+                .map(|url| (url.scheme().to_string(), url.host_str().map(str::to_lowercase), url.port_or_known_default()))
+        };
+        
+        match (origin(url), origin(&self.config.music_server_url)) {
+            (Some(url), Some(server)) => url == server,
+            _ => false,
+        }
+    }
+    
+    /// Send a raw request to the server with the current JWT token attached.
+    ///
+    /// Absolute URLs on other hosts are sent without the token.
+    pub async fn api_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<&serde_json::Value>,
+        headers: &[(String, String)],
+    ) -> Result<reqwest::Response> {
+        let url = self.resolve_url(path);
+        let mut request = self.client.request(method, &url);
+        
+        if let Some(token) = self.config.auth_token.as_ref().filter(|_| self.is_server_url(&url)) {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        
+        let mut request = request
+            .build()
+            .with_context(|| format!("Failed to build request to {}", url))?;
+        
+        // User headers replace the injected token and content type rather than going out next to them
+        for (key, value) in headers {
+            let name = HeaderName::from_bytes(key.as_bytes())
+                .with_context(|| format!("Invalid header name: {}", key))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header {}", key))?;
+            request.headers_mut().insert(name, value);
+        }
+        
+        self.client
+            .execute(request)
+            .await
+            .with_context(|| format!("Failed to send request to {}", url))
     }
     
     pub async fn prefetch_tracks(&self, track_ids: Vec<String>) -> Result<()> {
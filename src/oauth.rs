@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::process::Command;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// How long to wait for the browser to come back with an authorization code
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(300);
+
+const SUCCESS_PAGE: &str = "<html><body><h2>Lynx.fm login complete</h2>\
+    <p>You can close this window and return to the terminal.</p></body></html>";
+
+const FAILURE_PAGE: &str = "<html><body><h2>Lynx.fm login failed</h2>\
+    <p>Return to the terminal for details.</p></body></html>";
+
+/// A PKCE code verifier and its S256 challenge (RFC 7636)
+#[derive(Debug, Clone)]
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    /// Generate a fresh random verifier
+    pub fn new() -> Self {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self::from_verifier(&URL_SAFE_NO_PAD.encode(bytes))
+    }
+    
+    pub fn from_verifier(verifier: &str) -> Self {
+        let digest = Sha256::digest(verifier.as_bytes());
+        
+        Self {
+            verifier: verifier.to_string(),
+            challenge: URL_SAFE_NO_PAD.encode(digest),
+        }
+    }
+}
+
+impl Default for Pkce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A random value that ties the redirect back to this login attempt
+pub fn new_state() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Build the Supabase authorize URL for a provider such as `github` or `google`.
+///
+/// `state` rides along in the redirect URL, so the callback can prove it comes from this login.
+pub fn authorize_url(supabase_url: &str, provider: &str, redirect_to: &str, pkce: &Pkce, state: &str) -> Result<String> {
+    let redirect_to = reqwest::Url::parse_with_params(redirect_to, &[("state", state)])
+        .context("Invalid redirect URL")?;
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/auth/v1/authorize", supabase_url.trim_end_matches('/')),
+        &[
+            ("provider", provider),
+            ("redirect_to", redirect_to.as_str()),
+            ("code_challenge", pkce.challenge.as_str()),
+            ("code_challenge_method", "s256"),
+        ],
+    )
+    .context("Invalid Supabase URL")?;
+    
+    Ok(url.to_string())
+}
+
+/// Bind a temporary loopback listener and return it with its redirect URL
+pub async fn bind_loopback() -> Result<(TcpListener, String)> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Failed to start local redirect listener")?;
+        
+    let port = listener.local_addr()?.port();
+    Ok((listener, format!("http://127.0.0.1:{}/callback", port)))
+}
+
+/// Wait for the browser to hit `/callback` with `state` and return the authorization code
+pub async fn wait_for_callback(listener: &TcpListener, state: &str) -> Result<String> {
+    tokio::time::timeout(CALLBACK_TIMEOUT, accept_callback(listener, state))
+        .await
+        .context("Timed out waiting for the browser to complete login")?
+}
+
+async fn accept_callback(listener: &TcpListener, state: &str) -> Result<String> {
+    loop {
+        let (mut socket, _) = listener.accept().await
+            .context("Failed to accept redirect connection")?;
+            
+        let target = match read_request_target(&mut socket).await {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        
+        let url = reqwest::Url::parse(&format!("http://127.0.0.1{}", target))
+            .context("Invalid redirect request")?;
+            
+        // Browsers also ask for things like /favicon.ico
+        if url.path() != "/callback" {
+            respond(&mut socket, "404 Not Found", "").await;
+            continue;
+        }
+        
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        
+        // Anything else on this machine can reach the listener, so only this login's redirect counts
+        if param("state").as_deref() != Some(state) {
+            respond(&mut socket, "400 Bad Request", FAILURE_PAGE).await;
+            continue;
+        }
+        
+        if let Some(error) = param("error") {
+            respond(&mut socket, "400 Bad Request", FAILURE_PAGE).await;
+            match param("error_description") {
+                Some(desc) => anyhow::bail!("Login failed: {} - {}", error, desc),
+                None => anyhow::bail!("Login failed: {}", error),
+            }
+        }
+        
+        match param("code") {
+            Some(code) => {
+                respond(&mut socket, "200 OK", SUCCESS_PAGE).await;
+                return Ok(code);
+            }
+            None => {
+                respond(&mut socket, "400 Bad Request", FAILURE_PAGE).await;
+                anyhow::bail!("Login failed: redirect did not include an authorization code");
+            }
+        }
+    }
+}
+
+/// Read the request head and return the target of the request line
+async fn read_request_target(socket: &mut tokio::net::TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < 16 * 1024 {
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    
+    let head = String::from_utf8_lossy(&head);
+    let request_line = head.lines().next().context("Empty request")?;
+    
+    request_line
+        .split_whitespace()
+        .nth(1)
+        .map(str::to_string)
+        .context("Malformed request line")
+}
+
+async fn respond(socket: &mut tokio::net::TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    
+    // The code is already in hand, so a browser that hung up early doesn't matter
+    let _ = socket.write_all(response.as_bytes()).await;
+    let _ = socket.shutdown().await;
+}
+
+/// Try to open a URL in the default browser, returning whether it worked
+pub fn open_browser(url: &str) -> bool {
+    let status = if cfg!(target_os = "macos") {
+        Command::new("open").arg(url).status()
+    } else if cfg!(windows) {
+        Command::new("cmd").args(["/C", "start", "", url]).status()
+    } else {
+        Command::new("xdg-open").arg(url).status()
+    };
+    
+    matches!(status, Ok(status) if status.success())
+}
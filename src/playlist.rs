@@ -0,0 +1,445 @@
+use anyhow::{Context, Result};
+use percent_encoding::percent_decode_str;
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::files;
+use crate::music::{self, Playlist, Track};
+
+/// Playlist file formats for import and export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlaylistFormat {
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// Guess the format from a file extension
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+            
+        match extension.as_deref() {
+            Some("m3u") => Ok(Self::M3u),
+            Some("m3u8") => Ok(Self::M3u8),
+            Some("pls") => Ok(Self::Pls),
+            Some("xspf") => Ok(Self::Xspf),
+            _ => anyhow::bail!(
+                "Can't tell the playlist format of {}; use --format m3u, m3u8, pls or xspf",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// One line of an imported playlist, before it's matched against the catalog
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Entry {
+    /// Track ID, `lynx://` URI, stream URL or file path
+    pub location: String,
+    /// Display title; M3U puts `Artist - Title` here
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => write!(f, "{} - {} ({})", artist, title, self.location),
+            (None, Some(title)) => write!(f, "{} ({})", title, self.location),
+            _ => write!(f, "{}", self.location),
+        }
+    }
+}
+
+/// A playlist file, read but not yet resolved
+#[derive(Debug, Default)]
+pub struct ParsedPlaylist {
+    pub title: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+pub fn parse(content: &str, format: PlaylistFormat) -> Result<ParsedPlaylist> {
+    let content = content.trim_start_matches('\u{feff}');
+    
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => Ok(parse_m3u(content)),
+        PlaylistFormat::Pls => Ok(parse_pls(content)),
+        PlaylistFormat::Xspf => parse_xspf(content),
+    }
+}
+
+fn parse_m3u(content: &str) -> ParsedPlaylist {
+    let mut playlist = ParsedPlaylist::default();
+    let mut title = None;
+    
+    for line in content.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // `#EXTINF:<seconds>,<title>`
+            title = info.split_once(',').map(|(_, title)| title.trim().to_string());
+        } else if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            playlist.title = Some(name.trim().to_string());
+        } else if !line.starts_with('#') {
+            playlist.entries.push(Entry {
+                location: line.to_string(),
+                title: title.take().filter(|title| !title.is_empty()),
+                artist: None,
+            });
+        }
+    }
+    
+    playlist
+}
+
+fn parse_pls(content: &str) -> ParsedPlaylist {
+    let mut entries: BTreeMap<usize, Entry> = BTreeMap::new();
+    
+    for line in content.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else { continue };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_string();
+        
+        // `File1=`, `Title1=` and `Length1=` share the number
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let Ok(number) = key[split..].parse::<usize>() else { continue };
+        let entry = entries.entry(number).or_default();
+        
+        match &key[..split] {
+            "file" => entry.location = value,
+            "title" => entry.title = Some(value).filter(|title| !title.is_empty()),
+            _ => {}
+        }
+    }
+    
+    ParsedPlaylist {
+        title: None,
+        entries: entries.into_values().filter(|entry| !entry.location.is_empty()).collect(),
+    }
+}
+
+fn parse_xspf(content: &str) -> Result<ParsedPlaylist> {
+    let mut reader = Reader::from_str(content);
+    reader.config_mut().trim_text(true);
+    
+    let mut playlist = ParsedPlaylist::default();
+    let mut path: Vec<String> = Vec::new();
+    let mut track: Option<Entry> = None;
+    
+    loop {
+        match reader.read_event().context("Invalid XSPF playlist")? {
+            Event::Start(element) => {
+                let name = String::from_utf8_lossy(element.local_name().as_ref()).to_string();
+                if name == "track" {
+                    track = Some(Entry::default());
+                }
+                path.push(name);
+            }
+            Event::End(_) => {
+                let closed = path.pop();
+                let finished = if closed.as_deref() == Some("track") { track.take() } else { None };
+                if let Some(entry) = finished.filter(|entry| !entry.location.is_empty()) {
+                    playlist.entries.push(entry);
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape().context("Invalid XSPF playlist")?.trim().to_string();
+                let parent = path.iter().rev().nth(1).map(String::as_str);
+                
+                match (path.last().map(String::as_str), parent, track.as_mut()) {
+                    (Some("title"), Some("playlist"), _) => playlist.title = Some(text),
+                    (Some("location"), Some("track"), Some(entry)) => entry.location = text,
+                    (Some("title"), Some("track"), Some(entry)) => entry.title = Some(text),
+                    (Some("creator"), Some("track"), Some(entry)) => entry.artist = Some(text),
+                    // Our own exports also carry the Lynx URI
+                    (Some("identifier"), Some("track"), Some(entry)) if entry.location.is_empty() => {
+                        entry.location = text
+                    }
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    
+    Ok(playlist)
+}
+
+/// The absolute stream URL an exported entry points at
+pub fn stream_url(track: &Track, server_url: &str) -> Result<String> {
+    Ok(music::segment_url(server_url, &["tracks", &track.id])?.to_string())
+}
+
+pub fn render(playlist: &Playlist, urls: &[String], format: PlaylistFormat) -> String {
+    let entries = playlist.tracks.iter().zip(urls);
+    let seconds = |track: &Track| track.duration.map(|d| d.round() as i64).unwrap_or(-1);
+    
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => {
+            let mut out = format!("#EXTM3U\n#PLAYLIST:{}\n", playlist.name);
+            for (track, url) in entries {
+                out.push_str(&format!("#EXTINF:{},{}\n{}\n", seconds(track), track.label(), url));
+            }
+            out
+        }
+        PlaylistFormat::Pls => {
+            let mut out = String::from("[playlist]\n");
+            for (i, (track, url)) in entries.enumerate() {
+                let n = i + 1;
+                out.push_str(&format!(
+                    "File{n}={}\nTitle{n}={}\nLength{n}={}\n",
+                    url,
+                    track.label(),
+                    seconds(track)
+                ));
+            }
+            out.push_str(&format!("NumberOfEntries={}\nVersion=2\n", playlist.tracks.len()));
+            out
+        }
+        PlaylistFormat::Xspf => {
+            let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            out.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+            out.push_str(&format!("  <title>{}</title>\n  <trackList>\n", escape(&playlist.name)));
+            
+            for (track, url) in entries {
+                out.push_str("    <track>\n");
+                out.push_str(&format!("      <location>{}</location>\n", escape(url)));
+                out.push_str(&format!("      <identifier>lynx://track/{}</identifier>\n", escape(&track.id)));
+                out.push_str(&format!("      <title>{}</title>\n", escape(track.display_title())));
+                if let Some(artist) = &track.artist {
+                    out.push_str(&format!("      <creator>{}</creator>\n", escape(artist)));
+                }
+                if let Some(album) = &track.album {
+                    out.push_str(&format!("      <album>{}</album>\n", escape(album)));
+                }
+                if let Some(duration) = track.duration {
+                    out.push_str(&format!("      <duration>{}</duration>\n", (duration * 1000.0).round() as i64));
+                }
+                out.push_str("    </track>\n");
+            }
+            
+            out.push_str("  </trackList>\n</playlist>\n");
+            out
+        }
+    }
+}
+
+/// Imported entries matched against the catalog, and the ones that weren't
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub tracks: Vec<Track>,
+    pub unmatched: Vec<Entry>,
+}
+
+/// Match entries to catalog tracks: by the ID in the location first, then by
+/// `Artist - Title` or title from the entry or the file name.
+pub fn resolve(entries: Vec<Entry>, catalog: &[Track]) -> Resolution {
+    let by_id: HashMap<&str, &Track> = catalog.iter().map(|track| (track.id.as_str(), track)).collect();
+    
+    // Titles shared by several tracks can't identify one
+    let mut by_name: HashMap<String, Vec<&Track>> = HashMap::new();
+    for track in catalog {
+        by_name.entry(track.label().to_lowercase()).or_default().push(track);
+        if track.artist.is_some() {
+            by_name.entry(track.display_title().to_lowercase()).or_default().push(track);
+        }
+    }
+    
+    let mut resolution = Resolution::default();
+    for entry in entries {
+        let (id, stem) = location_id(&entry.location);
+        
+        let names = [
+            entry.artist.as_ref().zip(entry.title.as_ref()).map(|(a, t)| format!("{} - {}", a, t)),
+            entry.title.clone(),
+            stem,
+        ];
+        let named = || {
+            names.iter().flatten().find_map(|name| match by_name.get(&name.to_lowercase())?.as_slice() {
+                [track] => Some(*track),
+                _ => None,
+            })
+        };
+        
+        match id.as_deref().and_then(|id| by_id.get(id).copied()).or_else(named) {
+            Some(track) => resolution.tracks.push(track.clone()),
+            None => resolution.unmatched.push(entry),
+        }
+    }
+    
+    resolution
+}
+
+/// The track ID a location names, if any, and the file name to match by title otherwise
+fn location_id(location: &str) -> (Option<String>, Option<String>) {
+    if let Some(rest) = location.strip_prefix("lynx://") {
+        let id = rest.trim_start_matches("tracks/").trim_start_matches("track/").trim_matches('/');
+        return (Some(id.to_string()), None);
+    }
+    
+    if let Ok(url) = reqwest::Url::parse(location) {
+        match url.scheme() {
+            // Stream URLs end in `/tracks/<id>`
+            "http" | "https" => {
+                let segments: Vec<&str> = url.path_segments().map(|s| s.collect()).unwrap_or_default();
+                return match segments.as_slice() {
+                    [.., "tracks", id] => (Some(percent_decode_str(id).decode_utf8_lossy().to_string()), None),
+                    _ => (None, None),
+                };
+            }
+            "file" => {
+                if let Ok(path) = url.to_file_path() {
+                    return file_id(&path);
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    if location.contains(['/', '\\']) || Path::new(location).extension().is_some() {
+        return file_id(Path::new(location));
+    }
+    
+    // A bare word is taken as a track ID
+    (Some(location.to_string()), None)
+}
+
+/// Cached tracks are named after their ID; other files are matched by name
+fn file_id(path: &Path) -> (Option<String>, Option<String>) {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string());
+    (stem.clone(), stem)
+}
+
+/// Playlists kept on disk as JSON, one file each, independent of the server
+pub struct PlaylistStore {
+    dir: PathBuf,
+}
+
+impl PlaylistStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+    
+    /// The active profile's playlists
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::new(Config::playlists_dir(&Config::profile_name()?)?))
+    }
+    
+    pub fn list(&self) -> Result<Vec<Playlist>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        
+        let mut playlists = Vec::new();
+        for entry in fs::read_dir(&self.dir).context("Failed to read playlists directory")? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                playlists.push(Self::read(&path)?);
+            }
+        }
+        
+        playlists.sort_by_key(|playlist| playlist.name.to_lowercase());
+        Ok(playlists)
+    }
+    
+    /// Find a playlist by ID or by name (case-insensitive)
+    pub fn find(&self, reference: &str) -> Result<Playlist> {
+        let playlists = self.list()?;
+        
+        playlists
+            .iter()
+            .find(|p| p.id == reference)
+            .or_else(|| playlists.iter().find(|p| p.name.eq_ignore_ascii_case(reference)))
+            .cloned()
+            .with_context(|| format!("No local playlist named '{}'", reference))
+    }
+    
+    pub fn create(&self, name: &str, description: Option<&str>, tracks: Vec<Track>) -> Result<Playlist> {
+        let existing = self.list()?;
+        if existing.iter().any(|p| p.name.eq_ignore_ascii_case(name)) {
+            anyhow::bail!("A local playlist named '{}' already exists", name);
+        }
+        
+        // IDs are file names, so derive one from the name and keep it unique
+        let base = slug(name);
+        let id = (1..)
+            .map(|n| if n == 1 { base.clone() } else { format!("{}-{}", base, n) })
+            .find(|id| !existing.iter().any(|p| &p.id == id))
+            .expect("unbounded range");
+            
+        let playlist = Playlist {
+            id,
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            tracks,
+            ..Playlist::default()
+        };
+        self.save(&playlist)?;
+        Ok(playlist)
+    }
+    
+    /// Give a playlist a new name, unless another playlist already has it
+    pub fn rename(&self, playlist: &mut Playlist, name: &str) -> Result<()> {
+        let taken = self
+            .list()?
+            .iter()
+            .any(|p| p.id != playlist.id && p.name.eq_ignore_ascii_case(name));
+        if taken {
+            anyhow::bail!("A local playlist named '{}' already exists", name);
+        }
+        
+        playlist.name = name.to_string();
+        self.save(playlist)
+    }
+    
+    pub fn save(&self, playlist: &Playlist) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Failed to create playlists directory")?;
+        
+        let mut playlist = playlist.clone();
+        playlist.track_count = None;
+        playlist.updated_at = Some(chrono::Utc::now().to_rfc3339());
+        
+        let content = serde_json::to_string_pretty(&playlist).context("Failed to serialize playlist")?;
+        files::write_atomic(&self.file(&playlist.id), content.as_bytes())
+            .context("Failed to write playlist")
+    }
+    
+    pub fn delete(&self, id: &str) -> Result<()> {
+        fs::remove_file(self.file(id)).context("Failed to delete playlist")
+    }
+    
+    fn file(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+    
+    fn read(path: &Path) -> Result<Playlist> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read playlist {}", path.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Failed to parse playlist {}", path.display()))
+    }
+}
+
+/// Lowercase letters and digits joined by dashes, safe as a file name
+fn slug(name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+        
+    if slug.is_empty() {
+        "playlist".to_string()
+    } else {
+        slug
+    }
+}
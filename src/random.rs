@@ -0,0 +1,93 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::music::Track;
+use crate::smart::RuleContext;
+
+/// Rating assumed for tracks that have none, so they aren't starved in weighted mode
+const DEFAULT_RATING: f64 = 2.5;
+
+/// What `lynx-fm random` may pick from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RandomFilter {
+    /// Case-insensitive substring of the genre
+    pub genre: Option<String>,
+    /// Case-insensitive substring of the artist
+    pub artist: Option<String>,
+    /// Shortest acceptable length in seconds
+    pub min_duration: Option<i64>,
+    /// Skip tracks played within this many seconds
+    pub exclude_played: Option<i64>,
+}
+
+impl RandomFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    
+    /// The filters `/random` can apply itself. Play history is only known locally.
+    pub fn server_query(&self) -> Vec<(&'static str, String)> {
+        let mut query = Vec::new();
+        if let Some(genre) = &self.genre {
+            query.push(("genre", genre.clone()));
+        }
+        if let Some(artist) = &self.artist {
+            query.push(("artist", artist.clone()));
+        }
+        if let Some(min_duration) = self.min_duration {
+            query.push(("min_duration", min_duration.to_string()));
+        }
+        query
+    }
+    
+    pub fn matches(&self, track: &Track, context: &RuleContext) -> bool {
+        let contains = |field: &Option<String>, wanted: &Option<String>| match wanted {
+            Some(wanted) => field
+                .as_deref()
+                .is_some_and(|value| value.to_lowercase().contains(&wanted.to_lowercase())),
+            None => true,
+        };
+        
+        let long_enough = match self.min_duration {
+            Some(min) => track.duration.is_some_and(|duration| duration >= min as f64),
+            None => true,
+        };
+        
+        let recently_played = match self.exclude_played {
+            Some(span) => context
+                .plays
+                .get(&track.id)
+                .is_some_and(|stats| stats.count > 0 && stats.last_played >= context.now - span),
+            None => false,
+        };
+        
+        contains(&track.genre, &self.genre) && contains(&track.artist, &self.artist) && long_enough && !recently_played
+    }
+}
+
+/// How strongly weighted mode favours a track: higher ratings and fewer plays win
+pub fn weight(track: &Track, context: &RuleContext) -> f64 {
+    let rating = track.rating.unwrap_or(DEFAULT_RATING).clamp(0.0, 5.0);
+    let plays = context.plays.get(&track.id).map_or(0, |stats| stats.count);
+    
+    (1.0 + rating) / (1.0 + plays as f64)
+}
+
+/// Pick a track that passes the filter, uniformly or by [`weight`]
+pub fn pick<'a, R: Rng>(
+    tracks: &'a [Track],
+    filter: &RandomFilter,
+    context: &RuleContext,
+    weighted: bool,
+    rng: &mut R,
+) -> Option<&'a Track> {
+    let candidates: Vec<&Track> = tracks.iter().filter(|track| filter.matches(track, context)).collect();
+    
+    if !weighted {
+        return candidates.choose(rng).copied();
+    }
+    
+    let weights = WeightedIndex::new(candidates.iter().map(|track| weight(track, context))).ok()?;
+    Some(candidates[weights.sample(rng)])
+}
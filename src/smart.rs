@@ -0,0 +1,451 @@
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::display::parse_span;
+use crate::files;
+use crate::music::{Track, TrackFilter, TrackSort};
+
+/// How often and how recently a track was played
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlayStats {
+    pub count: usize,
+    /// Unix timestamp of the latest play
+    pub last_played: i64,
+}
+
+/// What rules look at besides the track itself
+pub struct RuleContext<'a> {
+    /// Unix timestamp that ages like `7d` count back from
+    pub now: i64,
+    pub plays: &'a HashMap<String, PlayStats>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Duration,
+    Bpm,
+    Rating,
+    Track,
+    Plays,
+    Added,
+    Played,
+}
+
+const FIELDS: &[(&str, Field)] = &[
+    ("title", Field::Title),
+    ("artist", Field::Artist),
+    ("album", Field::Album),
+    ("genre", Field::Genre),
+    ("year", Field::Year),
+    ("duration", Field::Duration),
+    ("bpm", Field::Bpm),
+    ("rating", Field::Rating),
+    ("track", Field::Track),
+    ("plays", Field::Plays),
+    ("added", Field::Added),
+    ("played", Field::Played),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `:`, which means "contains" for text and "equals" for numbers
+    Contains,
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+/// A parsed smart playlist rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    And(Box<Rule>, Box<Rule>),
+    Or(Box<Rule>, Box<Rule>),
+    Not(Box<Rule>),
+    /// A bare word, looked for in the title, artist, album and genre
+    Word(String),
+    Text { field: Field, op: Op, value: String },
+    Number { field: Field, op: Op, value: f64 },
+    /// `added:7d` or `played:7d` (within the span) and `played>30d` (longer ago)
+    Age { field: Field, within: bool, secs: i64 },
+}
+
+impl Rule {
+    /// Parse a rule such as `genre:techno AND bpm>125 AND NOT played:7d`.
+    ///
+    /// Conditions next to each other are ANDed; `OR`, `NOT` (or a leading `-`) and
+    /// parentheses work as usual. Quote values with spaces: `artist:"new order"`.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let tokens = tokenize(expr)?;
+        if tokens.is_empty() {
+            anyhow::bail!("The rule is empty");
+        }
+        
+        let mut parser = Parser { tokens, pos: 0 };
+        let rule = parser.or()?;
+        
+        if parser.pos < parser.tokens.len() {
+            anyhow::bail!("Unexpected ')' in rule");
+        }
+        Ok(rule)
+    }
+    
+    pub fn matches(&self, track: &Track, context: &RuleContext) -> bool {
+        match self {
+            Rule::And(left, right) => left.matches(track, context) && right.matches(track, context),
+            Rule::Or(left, right) => left.matches(track, context) || right.matches(track, context),
+            Rule::Not(rule) => !rule.matches(track, context),
+            Rule::Word(word) => [&track.title, &track.artist, &track.album, &track.genre]
+                .iter()
+                .any(|field| field.as_deref().is_some_and(|value| value.to_lowercase().contains(word))),
+            Rule::Text { field, op, value } => {
+                let actual = match field {
+                    Field::Title => track.title.as_deref(),
+                    Field::Artist => track.artist.as_deref(),
+                    Field::Album => track.album.as_deref(),
+                    _ => track.genre.as_deref(),
+                }
+                .map(str::to_lowercase);
+                
+                match (op, actual) {
+                    (Op::Contains, Some(actual)) => actual.contains(value),
+                    (Op::Eq, Some(actual)) => actual == *value,
+                    (Op::Ne, actual) => actual.as_deref() != Some(value),
+                    _ => false,
+                }
+            }
+            Rule::Number { field, op, value } => {
+                let actual = match field {
+                    Field::Year => track.year.map(f64::from),
+                    Field::Duration => track.duration,
+                    Field::Bpm => track.bpm,
+                    Field::Rating => track.rating,
+                    Field::Track => track.track_number.map(f64::from),
+                    _ => Some(context.plays.get(&track.id).map_or(0, |stats| stats.count) as f64),
+                };
+                
+                match actual {
+                    Some(actual) => compare(*op, actual, *value),
+                    None => *op == Op::Ne,
+                }
+            }
+            Rule::Age { field, within, secs } => {
+                let when = match field {
+                    Field::Added => track
+                        .added_at
+                        .as_deref()
+                        .and_then(|added| DateTime::parse_from_rfc3339(added).ok())
+                        .map(|added| added.timestamp()),
+                    _ => context.plays.get(&track.id).map(|stats| stats.last_played),
+                };
+                
+                when.is_some_and(|when| (when >= context.now - secs) == *within)
+            }
+        }
+    }
+}
+
+fn compare(op: Op, actual: f64, value: f64) -> bool {
+    match op {
+        Op::Contains | Op::Eq => (actual - value).abs() < 1e-9,
+        Op::Ne => (actual - value).abs() >= 1e-9,
+        Op::Gt => actual > value,
+        Op::Ge => actual >= value,
+        Op::Lt => actual < value,
+        Op::Le => actual <= value,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    /// A word with its quotes removed, and where the first quote was
+    Word { text: String, quoted_from: Option<usize> },
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut quoted_from = None;
+    let mut in_quotes = false;
+    
+    let flush = |tokens: &mut Vec<Token>, text: &mut String, quoted_from: &mut Option<usize>| {
+        let quoted = quoted_from.take();
+        let word = std::mem::take(text);
+        
+        match (word.as_str(), quoted) {
+            ("", None) => {}
+            ("AND", None) => tokens.push(Token::And),
+            ("OR", None) => tokens.push(Token::Or),
+            ("NOT", None) => tokens.push(Token::Not),
+            // `-word` is short for `NOT word`
+            (word, quoted) if word.len() > 1 && word.starts_with('-') && quoted != Some(0) => {
+                tokens.push(Token::Not);
+                tokens.push(Token::Word { text: word[1..].to_string(), quoted_from: quoted.map(|at| at - 1) });
+            }
+            (word, quoted) => tokens.push(Token::Word { text: word.to_string(), quoted_from: quoted }),
+        }
+    };
+    
+    for c in expr.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                quoted_from.get_or_insert(text.len());
+            }
+            c if in_quotes => text.push(c),
+            c if c.is_whitespace() => flush(&mut tokens, &mut text, &mut quoted_from),
+            '(' | ')' => {
+                flush(&mut tokens, &mut text, &mut quoted_from);
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            c => text.push(c),
+        }
+    }
+    
+    if in_quotes {
+        anyhow::bail!("Unclosed quote in rule");
+    }
+    flush(&mut tokens, &mut text, &mut quoted_from);
+    
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    
+    fn or(&mut self) -> Result<Rule> {
+        let mut rule = self.and()?;
+        
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            rule = Rule::Or(Box::new(rule), Box::new(self.and()?));
+        }
+        
+        Ok(rule)
+    }
+    
+    fn and(&mut self) -> Result<Rule> {
+        let mut rule = self.unary()?;
+        
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                Some(Token::Word { .. } | Token::Not | Token::Open) => {}
+                _ => break,
+            }
+            rule = Rule::And(Box::new(rule), Box::new(self.unary()?));
+        }
+        
+        Ok(rule)
+    }
+    
+    fn unary(&mut self) -> Result<Rule> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Rule::Not(Box::new(self.unary()?)));
+        }
+        
+        match self.next() {
+            Some(Token::Open) => {
+                let rule = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(rule),
+                    _ => anyhow::bail!("Missing ')' in rule"),
+                }
+            }
+            Some(Token::Word { text, quoted_from }) => condition(&text, quoted_from),
+            Some(Token::Close) => anyhow::bail!("Unexpected ')' in rule"),
+            Some(_) => anyhow::bail!("Expected a condition after AND, OR or NOT"),
+            None => anyhow::bail!("The rule ends where a condition was expected"),
+        }
+    }
+}
+
+/// Turn `field<op>value`, or a bare word, into a rule
+fn condition(text: &str, quoted_from: Option<usize>) -> Result<Rule> {
+    // Operators inside quotes are part of the value
+    let unquoted = &text[..quoted_from.unwrap_or(text.len())];
+    let Some(at) = unquoted.find([':', '=', '!', '<', '>']) else {
+        return Ok(Rule::Word(text.to_lowercase()));
+    };
+    
+    let name = text[..at].to_lowercase();
+    let rest = &text[at..];
+    let (op, len) = [(">=", Op::Ge), ("<=", Op::Le), ("!=", Op::Ne), (">", Op::Gt), ("<", Op::Lt), ("=", Op::Eq), (":", Op::Contains)]
+        .iter()
+        .find(|(symbol, _)| rest.starts_with(symbol))
+        .map(|(symbol, op)| (*op, symbol.len()))
+        .with_context(|| format!("Invalid operator in '{}'", text))?;
+    let value = &rest[len..];
+    
+    let field = FIELDS
+        .iter()
+        .find(|(field, _)| *field == name)
+        .map(|(_, field)| *field)
+        .with_context(|| {
+            let names: Vec<&str> = FIELDS.iter().map(|(name, _)| *name).collect();
+            format!("Unknown field '{}' in '{}'; use one of {}", name, text, names.join(", "))
+        })?;
+        
+    if value.is_empty() {
+        anyhow::bail!("Missing value in '{}'", text);
+    }
+    
+    match field {
+        Field::Title | Field::Artist | Field::Album | Field::Genre => match op {
+            Op::Contains | Op::Eq | Op::Ne => Ok(Rule::Text { field, op, value: value.to_lowercase() }),
+            _ => anyhow::bail!("'{}' is text, so it only supports ':', '=' and '!='", name),
+        },
+        Field::Added | Field::Played => {
+            let within = match op {
+                Op::Contains | Op::Lt | Op::Le => true,
+                Op::Gt | Op::Ge => false,
+                Op::Eq | Op::Ne => anyhow::bail!("Use '{0}:7d' for within 7 days or '{0}>7d' for longer ago", name),
+            };
+            Ok(Rule::Age { field, within, secs: parse_span(value)? })
+        }
+        Field::Duration => Ok(Rule::Number { field, op, value: parse_length(value)? as f64 }),
+        _ => {
+            let value = value
+                .parse()
+                .with_context(|| format!("'{}' needs a number, not '{}'", name, value))?;
+            Ok(Rule::Number { field, op, value })
+        }
+    }
+}
+
+/// `3:30`, or a span such as `3m` or `200`
+pub fn parse_length(value: &str) -> Result<i64> {
+    match value.split_once(':') {
+        Some((minutes, seconds)) => {
+            let minutes: i64 = minutes.parse().with_context(|| format!("Invalid length '{}'", value))?;
+            let seconds: i64 = seconds.parse().with_context(|| format!("Invalid length '{}'", value))?;
+            Ok(minutes * 60 + seconds)
+        }
+        None => parse_span(value),
+    }
+}
+
+/// A saved rule, re-evaluated each time it is shown or played
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SmartPlaylist {
+    pub name: String,
+    pub rule: String,
+    /// Defaults to artist order
+    #[serde(default)]
+    pub sort: Option<TrackSort>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl SmartPlaylist {
+    /// The matching tracks, in an order that doesn't depend on how the catalog is stored
+    pub fn evaluate(&self, tracks: Vec<Track>, context: &RuleContext) -> Result<Vec<Track>> {
+        let rule = Rule::parse(&self.rule)?;
+        
+        let mut matching: Vec<Track> = tracks.into_iter().filter(|track| rule.matches(track, context)).collect();
+        matching.sort_by(|a, b| a.id.cmp(&b.id));
+        
+        let filter = TrackFilter {
+            sort: Some(self.sort.unwrap_or(TrackSort::Artist)),
+            limit: self.limit,
+            ..TrackFilter::default()
+        };
+        Ok(filter.apply(matching))
+    }
+}
+
+/// Smart playlists kept in one JSON file per profile
+pub struct SmartStore {
+    path: PathBuf,
+}
+
+impl SmartStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+    
+    /// The active profile's smart playlists
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::new(Config::smart_playlists_file(&Config::profile_name()?)?))
+    }
+    
+    pub fn list(&self) -> Result<Vec<SmartPlaylist>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        
+        let content = fs::read_to_string(&self.path).context("Failed to read smart playlists")?;
+        serde_json::from_str(&content).context("Failed to parse smart playlists")
+    }
+    
+    pub fn find(&self, name: &str) -> Result<SmartPlaylist> {
+        self.list()?
+            .into_iter()
+            .find(|playlist| playlist.name.eq_ignore_ascii_case(name))
+            .with_context(|| format!("No smart playlist named '{}'", name))
+    }
+    
+    /// Save a new smart playlist after checking that its rule parses
+    pub fn add(&self, playlist: SmartPlaylist) -> Result<()> {
+        Rule::parse(&playlist.rule).context("Invalid rule")?;
+        
+        let mut playlists = self.list()?;
+        if playlists.iter().any(|p| p.name.eq_ignore_ascii_case(&playlist.name)) {
+            anyhow::bail!("A smart playlist named '{}' already exists", playlist.name);
+        }
+        
+        playlists.push(playlist);
+        self.write(&playlists)
+    }
+    
+    pub fn remove(&self, name: &str) -> Result<()> {
+        let mut playlists = self.list()?;
+        let before = playlists.len();
+        playlists.retain(|playlist| !playlist.name.eq_ignore_ascii_case(name));
+        
+        if playlists.len() == before {
+            anyhow::bail!("No smart playlist named '{}'", name);
+        }
+        self.write(&playlists)
+    }
+    
+    fn write(&self, playlists: &[SmartPlaylist]) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create data directory")?;
+        }
+        
+        let content = serde_json::to_string_pretty(playlists).context("Failed to serialize smart playlists")?;
+        files::write_atomic(&self.path, content.as_bytes()).context("Failed to write smart playlists")
+    }
+}
@@ -0,0 +1,255 @@
+use chrono::{Datelike, TimeZone, Timelike};
+use quick_xml::escape::escape;
+use std::collections::HashMap;
+
+use crate::display::{format_duration, format_length};
+use crate::history::Play;
+use crate::music::Track;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Heatmap shades from no plays to the busiest hour
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+/// Shareable report formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+}
+
+impl ReportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Html => "html",
+            ReportFormat::Markdown => "md",
+        }
+    }
+}
+
+/// A track, artist or genre with how much it was played
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ranked {
+    pub name: String,
+    pub plays: usize,
+    /// Seconds listened
+    pub listened: f64,
+}
+
+/// What was listened to over a period
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// Describes the period, e.g. `2026` or `Since 2026-10-01`
+    pub period: String,
+    pub plays: usize,
+    pub skipped: usize,
+    /// Seconds listened, skips included
+    pub listened: f64,
+    pub tracks: Vec<Ranked>,
+    pub artists: Vec<Ranked>,
+    /// Only tracks the synced catalog knows a genre for count here
+    pub genres: Vec<Ranked>,
+    /// Plays by weekday (Monday first) and hour
+    pub heatmap: [[usize; 24]; 7],
+}
+
+impl Stats {
+    /// Tally plays, looking up artists and genres in `catalog` where the history lacks them.
+    ///
+    /// The heatmap uses the hours of `tz`; `top` caps each ranking.
+    pub fn compute<Tz: TimeZone>(
+        period: &str,
+        plays: &[Play],
+        catalog: &HashMap<String, Track>,
+        top: usize,
+        tz: &Tz,
+    ) -> Self {
+        let mut tracks: HashMap<&str, Ranked> = HashMap::new();
+        let mut artists: HashMap<&str, Ranked> = HashMap::new();
+        let mut genres: HashMap<&str, Ranked> = HashMap::new();
+        let mut heatmap = [[0; 24]; 7];
+        
+        for play in plays {
+            let known = catalog.get(&play.track_id);
+            
+            // Plays are newest first, so tracks keep their latest name; untitled ones use the catalog's
+            let name = match (&play.title, known) {
+                (None, Some(track)) => track.label(),
+                _ => play.label(),
+            };
+            tally(&mut tracks, play.track_id.as_str(), name, play.listened);
+            
+            if let Some(artist) = play.artist.as_deref().or(known.and_then(|t| t.artist.as_deref())) {
+                tally(&mut artists, artist, artist.to_string(), play.listened);
+            }
+            if let Some(genre) = known.and_then(|t| t.genre.as_deref()) {
+                tally(&mut genres, genre, genre.to_string(), play.listened);
+            }
+            
+            let local = play.started_at.with_timezone(tz);
+            heatmap[local.weekday().num_days_from_monday() as usize][local.hour() as usize] += 1;
+        }
+        
+        Self {
+            period: period.to_string(),
+            plays: plays.len(),
+            skipped: plays.iter().filter(|play| !play.completed).count(),
+            listened: plays.iter().map(|play| play.listened).sum(),
+            tracks: rank(tracks, top),
+            artists: rank(artists, top),
+            genres: rank(genres, top),
+            heatmap,
+        }
+    }
+    
+    /// Share of plays skipped before the end, from 0 to 1
+    pub fn skip_rate(&self) -> f64 {
+        if self.plays == 0 {
+            return 0.0;
+        }
+        self.skipped as f64 / self.plays as f64
+    }
+    
+    /// The heatmap as text: a row per weekday, a column per hour
+    pub fn heatmap_text(&self) -> String {
+        let busiest = self.heatmap.iter().flatten().copied().max().unwrap_or(0);
+        
+        let mut lines = vec![format!("     {:<6}{:<6}{:<6}{}", "00", "06", "12", "18")];
+        for (day, hours) in WEEKDAYS.iter().zip(&self.heatmap) {
+            let cells: String = hours.iter().map(|&count| SHADES[shade(count, busiest)]).collect();
+            lines.push(format!("{}  {}", day, cells));
+        }
+        lines.join("\n")
+    }
+    
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Markdown => self.markdown(),
+            ReportFormat::Html => self.html(),
+        }
+    }
+    
+    fn markdown(&self) -> String {
+        let mut out = format!("# Lynx.fm listening report: {}\n\n", self.period);
+        out.push_str(&format!(
+            "- **Plays:** {}\n- **Listening time:** {}\n- **Skip rate:** {:.0}%\n",
+            self.plays,
+            format_duration(self.listened.round() as i64),
+            self.skip_rate() * 100.0
+        ));
+        
+        for (heading, ranking) in self.rankings() {
+            out.push_str(&format!("\n## {}\n\n", heading));
+            if ranking.is_empty() {
+                out.push_str("Nothing yet.\n");
+                continue;
+            }
+            out.push_str("| # | Name | Plays | Time |\n|---:|---|---:|---:|\n");
+            for (i, item) in ranking.iter().enumerate() {
+                out.push_str(&format!(
+                    "| {} | {} | {} | {} |\n",
+                    i + 1,
+                    item.name.replace('|', "\\|"),
+                    item.plays,
+                    format_length(item.listened)
+                ));
+            }
+        }
+        
+        out.push_str(&format!("\n## Listening by hour\n\n```\n{}\n```\n", self.heatmap_text()));
+        out
+    }
+    
+    fn html(&self) -> String {
+        let title = format!("Lynx.fm listening report: {}", self.period);
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            escape(&title),
+            HTML_STYLE,
+            escape(&title)
+        );
+        out.push_str(&format!(
+            "<ul class=\"totals\">\n<li><b>{}</b> plays</li>\n<li><b>{}</b> listened</li>\n<li><b>{:.0}%</b> skipped</li>\n</ul>\n",
+            self.plays,
+            format_duration(self.listened.round() as i64),
+            self.skip_rate() * 100.0
+        ));
+        
+        for (heading, ranking) in self.rankings() {
+            out.push_str(&format!("<h2>{}</h2>\n", heading));
+            if ranking.is_empty() {
+                out.push_str("<p>Nothing yet.</p>\n");
+                continue;
+            }
+            out.push_str("<table>\n<tr><th>#</th><th>Name</th><th>Plays</th><th>Time</th></tr>\n");
+            for (i, item) in ranking.iter().enumerate() {
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    i + 1,
+                    escape(&item.name),
+                    item.plays,
+                    format_length(item.listened)
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+        
+        let busiest = self.heatmap.iter().flatten().copied().max().unwrap_or(0);
+        out.push_str("<h2>Listening by hour</h2>\n<table class=\"heatmap\">\n<tr><th></th>");
+        for hour in 0..24 {
+            out.push_str(&format!("<th>{:02}</th>", hour));
+        }
+        out.push_str("</tr>\n");
+        for (day, hours) in WEEKDAYS.iter().zip(&self.heatmap) {
+            out.push_str(&format!("<tr><th>{}</th>", day));
+            for &count in hours {
+                let alpha = if busiest == 0 { 0.0 } else { count as f64 / busiest as f64 };
+                out.push_str(&format!(
+                    "<td style=\"background: rgba(29, 185, 84, {:.2})\" title=\"{} plays\"></td>",
+                    alpha, count
+                ));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n</body>\n</html>\n");
+        out
+    }
+    
+    fn rankings(&self) -> [(&'static str, &[Ranked]); 3] {
+        [("Top tracks", &self.tracks), ("Top artists", &self.artists), ("Top genres", &self.genres)]
+    }
+}
+
+const HTML_STYLE: &str = "body { font-family: system-ui, sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; color: #222; } \
+    table { border-collapse: collapse; margin-bottom: 1rem; } \
+    th, td { padding: 0.25rem 0.6rem; text-align: left; } \
+    .totals { list-style: none; padding: 0; display: flex; gap: 2rem; } \
+    .heatmap td { width: 1.2rem; height: 1.2rem; padding: 0; border: 1px solid #eee; } \
+    .heatmap th { font-size: 0.7rem; font-weight: normal; }";
+
+/// Most played first, then longest listened, then by name
+fn rank(ranking: HashMap<&str, Ranked>, top: usize) -> Vec<Ranked> {
+    let mut ranked: Vec<Ranked> = ranking.into_values().collect();
+    ranked.sort_by(|a, b| {
+        b.plays
+            .cmp(&a.plays)
+            .then(b.listened.total_cmp(&a.listened))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    ranked.truncate(top);
+    ranked
+}
+
+fn tally<'a>(ranking: &mut HashMap<&'a str, Ranked>, key: &'a str, name: String, listened: f64) {
+    let entry = ranking.entry(key).or_insert(Ranked { name, plays: 0, listened: 0.0 });
+    entry.plays += 1;
+    entry.listened += listened;
+}
+
+/// Index into `SHADES`: the busiest hour is darkest, and any plays at all show
+fn shade(count: usize, busiest: usize) -> usize {
+    if busiest == 0 {
+        return 0;
+    }
+    (count * (SHADES.len() - 1)).div_ceil(busiest)
+}
@@ -1,21 +1,10 @@
 use anyhow::Result;
 use reqwest::StatusCode;
-use serde_json::json;
-use std::env;
+use serde_json::{json, Value};
 use std::fs;
-use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Config {
-    supabase_url: String,
-    supabase_anon_key: String,
-    music_server_url: String,
-    auth_token: Option<String>,
-    refresh_token: Option<String>,
-    token_expiry: Option<i64>,
-}
+
+use lynx_fm::config::{Config, DEFAULT_PROFILE};
 
 // Helper function to create a test config
 fn create_test_config() -> Config {
@@ -26,18 +15,16 @@ fn create_test_config() -> Config {
         auth_token: None,
         refresh_token: None,
         token_expiry: Some(0),
+        ..Config::default()
     };
     
-    // Try to load the real auth token from the config file
-    if let Ok(home_dir) = env::var("HOME") {
-        let config_path = PathBuf::from(home_dir).join(".lynx-fm/config.json");
-        if config_path.exists() {
-            if let Ok(config_str) = fs::read_to_string(config_path) {
-                if let Ok(real_config) = serde_json::from_str::<Config>(&config_str) {
-                    config.auth_token = real_config.auth_token;
-                    config.refresh_token = real_config.refresh_token;
-                    config.token_expiry = real_config.token_expiry;
-                }
+    // Try to load the real tokens that `lynx-fm login` saved for the default profile
+    if let Ok(path) = Config::credentials_file(DEFAULT_PROFILE) {
+        if let Ok(content) = fs::read_to_string(path) {
+            if let Ok(saved) = serde_json::from_str::<Value>(&content) {
+                config.auth_token = saved["auth_token"].as_str().map(String::from);
+                config.refresh_token = saved["refresh_token"].as_str().map(String::from);
+                config.token_expiry = saved["token_expiry"].as_i64();
             }
         }
     }
@@ -7,7 +7,7 @@ use tempfile::tempdir;
 use serde_json::Value;
 
 // Import the modules from the main crate
-use lynx_fm::config::Config;
+use lynx_fm::config::{Config, DEFAULT_PROFILE};
 use lynx_fm::music::MusicClient;
 
 // Helper function to create a test config
@@ -19,18 +19,16 @@ fn create_test_config() -> Config {
         auth_token: None,
         refresh_token: None,
         token_expiry: Some(0),
+        ..Config::default()
     };
     
-    // Try to load the real auth token from the config file
-    if let Ok(home_dir) = env::var("HOME") {
-        let config_path = PathBuf::from(home_dir).join(".lynx-fm/config.json");
-        if config_path.exists() {
-            if let Ok(config_str) = fs::read_to_string(config_path) {
-                if let Ok(real_config) = serde_json::from_str::<Config>(&config_str) {
-                    config.auth_token = real_config.auth_token;
-                    config.refresh_token = real_config.refresh_token;
-                    config.token_expiry = real_config.token_expiry;
-                }
+    // Try to load the real tokens that `lynx-fm login` saved for the default profile
+    if let Ok(path) = Config::credentials_file(DEFAULT_PROFILE) {
+        if let Ok(content) = fs::read_to_string(path) {
+            if let Ok(saved) = serde_json::from_str::<Value>(&content) {
+                config.auth_token = saved["auth_token"].as_str().map(String::from);
+                config.refresh_token = saved["refresh_token"].as_str().map(String::from);
+                config.token_expiry = saved["token_expiry"].as_i64();
             }
         }
     }
@@ -38,6 +36,75 @@ fn create_test_config() -> Config {
     config
 }
 
+/// Start a local stand-in server that answers each request with `handler(method, path, body)`
+async fn spawn_stand_in<F>(handler: F) -> Result<String>
+where
+    F: Fn(&str, &str, &str) -> (u16, Value) + Send + Sync + 'static,
+{
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let base_url = format!("http://{}", listener.local_addr()?);
+    let handler = Arc::new(handler);
+    
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let mut data = Vec::new();
+                let mut buf = [0u8; 4096];
+                
+                // Read the head, then as much body as Content-Length announces
+                let head_end = loop {
+                    let n = socket.read(&mut buf).await.unwrap_or(0);
+                    if n == 0 {
+                        return;
+                    }
+                    data.extend_from_slice(&buf[..n]);
+                    if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                };
+                
+                let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+                let content_length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length").then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                    
+                while data.len() < head_end + content_length {
+                    let n = socket.read(&mut buf).await.unwrap_or(0);
+                    if n == 0 {
+                        break;
+                    }
+                    data.extend_from_slice(&buf[..n]);
+                }
+                
+                let mut request_line = head.split_whitespace();
+                let method = request_line.next().unwrap_or_default().to_string();
+                let path = request_line.next().unwrap_or_default().to_string();
+                let body = String::from_utf8_lossy(&data[head_end..]).to_string();
+                
+                let (status, json) = handler(&method, &path, &body);
+                let payload = json.to_string();
+                let response = format!(
+                    "HTTP/1.1 {} Stand-in\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    payload.len(),
+                    payload
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    
+    Ok(base_url)
+}
+
 #[tokio::test]
 async fn test_health_check() -> Result<()> {
     let config = create_test_config();
@@ -711,14 +778,18 @@ fn test_config_migration() -> Result<()> {
     let mut file = fs::File::create(&old_config_path)?;
     file.write_all(test_config.as_bytes())?;
     
-    // Run the migration script (simulated here)
+    // Import it the way a first run without ~/.lynx-fm/config.json does
     fs::create_dir_all(&new_config_dir)?;
-    fs::copy(&old_config_path, new_config_dir.join("config.json"))?;
+    let new_config_path = new_config_dir.join("config.json");
+    assert!(Config::import_legacy(&old_config_path, &new_config_path)?);
+    assert!(!Config::import_legacy(&old_config_path, &new_config_path)?);
+    assert!(old_config_path.exists());
     
     // Verify the migration
-    let migrated_config = fs::read_to_string(new_config_dir.join("config.json"))?;
-    let config: Config = serde_json::from_str(&migrated_config)?;
+    let layer = Config::read_layer(&new_config_path)?.expect("imported config");
+    let config = Config::resolve(Some(layer), None)?;
     
+    assert_eq!(config.version, lynx_fm::config::CONFIG_VERSION);
     assert_eq!(config.supabase_url, "https://test-project.supabase.co");
     assert_eq!(config.supabase_anon_key, "test-anon-key");
     assert_eq!(config.music_server_url, "http://test-server:3500");
@@ -726,6 +797,222 @@ fn test_config_migration() -> Result<()> {
     assert_eq!(config.refresh_token, Some("test-refresh-token".to_string()));
     assert_eq!(config.token_expiry, Some(1234567890));
     
+    // The upgraded file is stamped and the original kept alongside it
+    let migrated: Value = serde_json::from_str(&fs::read_to_string(&new_config_path)?)?;
+    assert_eq!(migrated["version"], lynx_fm::config::CONFIG_VERSION);
+    assert!(new_config_dir.join("config.json.v0.bak").exists());
+    
+    // Old files missing newer fields still parse directly
+    let config: Config = serde_json::from_str(test_config)?;
+    assert_eq!(config.volume, 1.0);
+    
+    // A failed migration leaves the file untouched and keeps a backup
+    let broken_path = new_config_dir.join("broken.json");
+    fs::write(&broken_path, r#"{"token_expiry": "soon"}"#)?;
+    assert!(Config::read_layer(&broken_path).is_err());
+    assert_eq!(fs::read_to_string(&broken_path)?, r#"{"token_expiry": "soon"}"#);
+    assert!(new_config_dir.join("broken.json.v0.bak").exists());
+    
+    // Files from a newer version are refused rather than downgraded
+    let newer_path = new_config_dir.join("newer.json");
+    fs::write(&newer_path, r#"{"version": 999}"#)?;
+    assert!(Config::read_layer(&newer_path).is_err());
+    
+    Ok(())
+}
+
+// Test layering of named profiles over the main config
+#[test]
+fn test_profile_layering() -> Result<()> {
+    let base = serde_json::json!({
+        "supabase_url": "https://prod.supabase.co",
+        "supabase_anon_key": "prod-key",
+        "music_server_url": "https://prod.lynx.fm",
+        "auth_token": "prod-token",
+        "refresh_token": "prod-refresh",
+        "token_expiry": 1234567890
+    });
+    let base = base.as_object().cloned();
+    let profile = serde_json::json!({ "music_server_url": "https://staging.lynx.fm" });
+    
+    // Without a profile the main config is used as-is, with defaults filling gaps
+    let config = Config::resolve(base.clone(), None)?;
+    assert_eq!(config.music_server_url, "https://prod.lynx.fm");
+    assert_eq!(config.auth_token.as_deref(), Some("prod-token"));
+    assert_eq!(config.password_command, None);
+    
+    // A profile overrides some settings, inherits the rest, but never the tokens
+    let mut staging = Config::resolve(base.clone(), profile.as_object().cloned())?;
+    assert_eq!(staging.music_server_url, "https://staging.lynx.fm");
+    assert_eq!(staging.supabase_url, "https://prod.supabase.co");
+    assert_eq!(staging.auth_token, None);
+    assert_eq!(staging.token_expiry, None);
+    
+    // Saving a profile keeps only what differs from the main config; tokens are stored separately
+    staging.auth_token = Some("staging-token".to_string());
+    let overrides = staging.profile_overrides(base)?;
+    assert_eq!(overrides["music_server_url"], "https://staging.lynx.fm");
+    assert!(!overrides.contains_key("auth_token"));
+    assert!(!overrides.contains_key("refresh_token"));
+    assert!(!overrides.contains_key("supabase_url"));
+    
+    assert!(Config::validate_profile_name("team-staging_2").is_ok());
+    assert!(Config::validate_profile_name("../etc").is_err());
+    assert!(Config::validate_profile_name("").is_err());
+    
+    // Names that could escape the profile directories are refused before anything is touched
+    for name in ["../config", "a/b", ".."] {
+        assert!(Config::remove_profile(name).unwrap_err().to_string().starts_with("Invalid profile name"));
+        assert!(Config::set_active_profile(name).unwrap_err().to_string().starts_with("Invalid profile name"));
+    }
+    
+    // Removing a profile deletes its data everywhere, and nothing of the other profiles
+    let home = tempfile::tempdir()?;
+    let run = |args: &[&str]| -> Result<()> {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_lynx-fm"))
+            .args(args)
+            .env("LYNX_FM_HOME", home.path())
+            .env_remove("LYNX_FM_PROFILE")
+            .output()?;
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        Ok(())
+    };
+    run(&["profile", "add", "staging", "--server-url", "http://staging:3500"])?;
+    let data = |profile: &str| {
+        [
+            home.path().join("state").join("history").join(format!("{}.db", profile)),
+            home.path().join("data").join("catalog").join(format!("{}.db", profile)),
+            home.path().join("data").join("smart").join(format!("{}.json", profile)),
+            home.path().join("data").join("playlists").join(profile).join("mix.json"),
+            home.path().join("cache").join(profile).join("tracks").join("t1"),
+        ]
+    };
+    for path in data("staging").iter().chain(&data("default")) {
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, b"x")?;
+    }
+    run(&["profile", "remove", "staging"])?;
+    assert!(data("staging").iter().all(|path| !path.exists()));
+    assert!(!home.path().join("data").join("playlists").join("staging").exists());
+    assert!(data("default").iter().all(|path| path.exists()));
+    
+    Ok(())
+}
+
+// Test command-line overrides and value coercion for config fields
+#[test]
+fn test_config_overrides() -> Result<()> {
+    use clap::Parser;
+    use lynx_fm::commands::Cli;
+    use base64::Engine;
+    
+    assert_eq!(Config::parse_field_value("music_server_url", "http://localhost:3500")?, "http://localhost:3500");
+    assert_eq!(Config::parse_field_value("token_expiry", "1700000000")?, 1_700_000_000);
+    assert_eq!(Config::parse_field_value("password_command", "123")?, "123");
+    assert!(Config::parse_field_value("token_expiry", "soon").is_err());
+    assert!(Config::parse_field_value("no_such_field", "x").is_err());
+    
+    let cli = Cli::try_parse_from([
+        "lynx-fm",
+        "--server-url", "http://flag:3500",
+        "--token", "flag-token",
+        "-c", "music_server_url=http://generic:3500",
+        "-c", "request_timeout_secs=42",
+        "health",
+    ])?;
+    let overrides = cli.config_overrides()?;
+    
+    // Dedicated flags beat the generic form
+    assert_eq!(overrides["music_server_url"], "http://flag:3500");
+    assert_eq!(overrides["auth_token"], "flag-token");
+    assert_eq!(overrides["request_timeout_secs"], 42);
+    
+    let config = Config::default().with_overrides(&overrides)?;
+    assert_eq!(config.music_server_url, "http://flag:3500");
+    assert_eq!(config.auth_token.as_deref(), Some("flag-token"));
+    assert_eq!(config.request_timeout_secs, 42);
+    assert_eq!(config.supabase_url, Config::default().supabase_url);
+    
+    let cli = Cli::try_parse_from(["lynx-fm", "-c", "bogus=1", "health"])?;
+    assert!(cli.config_overrides().is_err());
+    
+    // The layout version and token bookkeeping can't be overridden
+    for field in ["version=99", "token_expiry=42", "refresh_token=r", "sealed_refresh_token=s"] {
+        let cli = Cli::try_parse_from(["lynx-fm", "-c", field, "health"])?;
+        assert!(cli.config_overrides().unwrap_err().to_string().starts_with("Unknown setting"), "{}", field);
+    }
+    
+    // Overrides work after the subcommand too
+    let cli = Cli::try_parse_from(["lynx-fm", "sync", "--server-url", "http://late:3500", "-c", "volume=0.5", "--token", "t"])?;
+    let overrides = cli.config_overrides()?;
+    assert_eq!((overrides["music_server_url"].as_str(), overrides["volume"].as_f64()), (Some("http://late:3500"), Some(0.5)));
+    
+    // Plain `config --server-url` saves the URL instead of overriding it
+    let cli = Cli::try_parse_from(["lynx-fm", "config", "--server-url", "http://saved:3500"])?;
+    assert_eq!(cli.server_url.as_deref(), Some("http://saved:3500"));
+    assert!(!cli.config_overrides()?.contains_key("music_server_url"));
+    
+    // A token passed in takes its expiry from the JWT, not from the stored session
+    let jwt = |claims: Value| format!("e30.{}.sig", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string()));
+    let stored = Config { auth_token: Some("stored".to_string()), token_expiry: Some(1), ..Config::default() };
+    let mut token = serde_json::Map::new();
+    token.insert("auth_token".to_string(), Value::String(jwt(serde_json::json!({"exp": 4_000_000_000i64}))));
+    let config = stored.clone().with_overrides(&token)?;
+    assert_eq!(config.token_expiry, Some(4_000_000_000));
+    assert!(config.is_authenticated());
+    
+    token.insert("auth_token".to_string(), Value::String("opaque".to_string()));
+    assert_eq!(stored.with_overrides(&token)?.token_expiry, None);
+    
+    // Only user-facing settings come from the environment; bookkeeping fields are left alone
+    let home = tempfile::tempdir()?;
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_lynx-fm"))
+        .args(["config", "get", "volume"])
+        .env("LYNX_FM_HOME", home.path())
+        .env("LYNX_FM_VERSION", "99")
+        .env("LYNX_FM_TOKEN_EXPIRY", "soon")
+        .env("LYNX_FM_VOLUME", "0.5")
+        .env_remove("LYNX_FM_PROFILE")
+        .output()?;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout)?.trim(), "0.5");
+    
+    Ok(())
+}
+
+#[test]
+fn test_config_settings() -> Result<()> {
+    use clap::Parser;
+    use lynx_fm::commands::{Cli, Commands, ConfigCommands};
+    use lynx_fm::config::Setting;
+    use serde_json::json;
+    
+    assert!(Setting::find("volume")?.validate(&json!(0.5)).is_ok());
+    assert!(Setting::find("volume")?.validate(&json!(2.5)).is_err());
+    assert!(Setting::find("music_server_url")?.validate(&json!("ftp://example.com")).is_err());
+    assert!(Setting::find("music_server_url")?.validate(&json!("not a url")).is_err());
+    assert!(Setting::find("theme")?.validate(&json!("neon")).is_err());
+    assert!(Setting::find("audio_device")?.validate(&Value::Null).is_ok());
+    assert!(Setting::find("auth_token").is_err());
+    
+    let mut layer = serde_json::Map::new();
+    layer.insert("request_timeout_secs".to_string(), json!(10));
+    assert!(Config::check_layer(&layer).is_ok());
+    layer.insert("request_timeout_secs".to_string(), json!("ten"));
+    assert!(Config::check_layer(&layer).is_err());
+    layer.remove("request_timeout_secs");
+    layer.insert("colour".to_string(), json!("plain"));
+    assert!(Config::check_layer(&layer).is_err());
+    
+    let cli = Cli::try_parse_from(["lynx-fm", "config", "set", "volume", "0.8"])?;
+    assert!(matches!(
+        cli.command,
+        Commands::Config { action: Some(ConfigCommands::Set { ref key, ref value }), .. } if key == "volume" && value == "0.8"
+    ));
+    assert!(Cli::try_parse_from(["lynx-fm", "config", "--server-url", "http://x", "get", "volume"]).is_err());
+    
+    assert!(Setting::find("cache_max_mb").is_err());
+    
     Ok(())
 }
 
@@ -751,17 +1038,262 @@ fn test_version_info() {
 // Test config file paths
 #[test]
 fn test_config_paths() -> Result<()> {
-    // Test that the config paths are correct
+    use std::process::Command;
+    
+    // Looking up the config dir can move a real ~/.lynx-fm, so check the paths in a copy of this
+    // test running against a throwaway home
+    let Some(root) = env::var_os("LYNX_FM_TEST_ROOT").map(PathBuf::from) else {
+        let temp = tempfile::tempdir()?;
+        let output = Command::new(env::current_exe()?)
+            .args(["test_config_paths", "--exact", "--nocapture"])
+            .env("LYNX_FM_TEST_ROOT", temp.path())
+            .env("HOME", temp.path().join("home"))
+            .env("XDG_CONFIG_HOME", temp.path().join("config"))
+            .env("XDG_STATE_HOME", temp.path().join("state"))
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env("XDG_DATA_HOME", temp.path().join("data"))
+            .env_remove("LYNX_FM_HOME")
+            .output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success() && stdout.contains("1 passed"), "{}", stdout);
+        return Ok(());
+    };
+    
     let config = Config::default();
     
     let config_dir = Config::config_dir()?;
     let config_file = Config::config_file()?;
     
-    // Check that the config directory ends with .lynx-fm
-    assert!(config_dir.ends_with(".lynx-fm"), "Config directory should end with .lynx-fm");
+    // Check that each kind of file lives under its own XDG directory
+    assert_eq!(config_dir, root.join("config").join("lynx-fm"));
+    assert!(Config::credentials_file("default")?.starts_with(root.join("state")));
+    assert!(Config::history_file("default")?.starts_with(root.join("state")));
+    assert!(Config::profile_cache_dir("default")?.starts_with(root.join("cache")));
+    assert!(Config::catalog_file("default")?.starts_with(root.join("data")));
     
     // Check that the config file is named config.json
-    assert!(config_file.ends_with("config.json"), "Config file should be named config.json");
+    assert_eq!(config_file, config_dir.join("config.json"));
+    
+    Ok(())
+}
+
+// Test the XDG layout and the one-time move out of ~/.lynx-fm, using the real binary
+#[test]
+fn test_xdg_layout() -> Result<()> {
+    use std::process::Command;
+    
+    let temp = tempfile::tempdir()?;
+    let home = temp.path().join("home");
+    let legacy = home.join(".lynx-fm");
+    fs::create_dir_all(legacy.join("cache").join("default").join("tracks"))?;
+    fs::write(legacy.join("cache").join("default").join("tracks").join("t1"), b"audio")?;
+    fs::write(
+        legacy.join("config.json"),
+        r#"{"version": 1, "music_server_url": "http://legacy:3500", "auth_token": "legacy-token", "token_expiry": 42}"#,
+    )?;
+    
+    let run = |args: &[&str], lynx_home: Option<&std::path::Path>| -> Result<String> {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_lynx-fm"));
+        command
+            .args(args)
+            .current_dir(temp.path())
+            .env("HOME", &home)
+            .env("XDG_CONFIG_HOME", temp.path().join("config"))
+            .env("XDG_STATE_HOME", temp.path().join("state"))
+            .env("XDG_CACHE_HOME", temp.path().join("cache"))
+            .env_remove("LYNX_FM_HOME")
+            .env_remove("LYNX_FM_PROFILE")
+            .env_remove("LYNX_FM_TOKEN");
+        if let Some(lynx_home) = lynx_home {
+            command.env("LYNX_FM_HOME", lynx_home);
+        }
+        
+        let output = command.output()?;
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    };
+    
+    let config_file = temp.path().join("config").join("lynx-fm").join("config.json");
+    assert_eq!(run(&["config", "path"], None)?, config_file.display().to_string());
+    assert!(!legacy.exists());
+    
+    // Settings stay in the config dir, tokens move to the state dir, audio to the cache dir
+    assert_eq!(run(&["config", "get", "music_server_url"], None)?, "http://legacy:3500");
+    assert_eq!(run(&["config", "get", "auth_token"], None)?, "legacy-token");
+    assert!(!fs::read_to_string(&config_file)?.contains("legacy-token"));
+    assert!(temp.path().join("state").join("lynx-fm").join("credentials").join("default.json").exists());
+    assert!(temp.path().join("cache").join("lynx-fm").join("default").join("tracks").join("t1").exists());
+    
+    // LYNX_FM_HOME puts everything in one place
+    let lynx_home = temp.path().join("portable");
+    assert_eq!(
+        run(&["config", "path"], Some(&lynx_home))?,
+        lynx_home.join("config.json").display().to_string()
+    );
+    
+    Ok(())
+}
+
+#[test]
+fn test_credential_storage() -> Result<()> {
+    use lynx_fm::credentials;
+    
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("credentials").join("default.json");
+    
+    // Concurrent writers never leave a truncated or interleaved file behind
+    let writers: Vec<_> = (0..8)
+        .map(|i| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let content = serde_json::json!({ "auth_token": format!("token-{}", i).repeat(500) });
+                credentials::write_private(&path, content.to_string().as_bytes())
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().expect("writer thread panicked")?;
+    }
+    let stored: Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    assert!(stored["auth_token"].as_str().is_some_and(|t| t.starts_with("token-")));
+    
+    // A token refresh writes while holding the lock itself
+    let lock = credentials::lock(path.parent().unwrap())?;
+    lock.hold(|| credentials::write_private(&path, br#"{"auth_token": "refreshed"}"#))?;
+    assert!(fs::read_to_string(&path)?.contains("refreshed"));
+    
+    // Another thread of this process waits for the holder, even with the lock lent out
+    let waiter = {
+        let path = path.clone();
+        std::thread::spawn(move || credentials::write_private(&path, br#"{"auth_token": "later"}"#))
+    };
+    lock.hold(|| std::thread::sleep(std::time::Duration::from_millis(100)));
+    assert!(fs::read_to_string(&path)?.contains("refreshed"));
+    drop(lock);
+    waiter.join().expect("writer thread panicked")?;
+    assert!(fs::read_to_string(&path)?.contains("later"));
+    
+    // Locks on different directories don't wait for each other
+    let other = dir.path().join("other");
+    let first = credentials::lock(path.parent().unwrap())?;
+    let second = std::thread::spawn(move || credentials::lock(&other).map(drop));
+    second.join().expect("locking thread panicked")?;
+    drop(first);
+    
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::metadata(path.parent().unwrap())?.permissions().mode() & 0o777, 0o700);
+        
+        // Other files written atomically keep the permissions a plain write would give them
+        let plain = dir.path().join("plain.json");
+        let atomic = dir.path().join("atomic.json");
+        fs::write(&plain, b"{}")?;
+        lynx_fm::files::write_atomic(&atomic, b"{}")?;
+        assert_eq!(fs::metadata(&atomic)?.permissions().mode(), fs::metadata(&plain)?.permissions().mode());
+    }
+    
+    // Sealed tokens round-trip with the right passphrase only
+    let sealed = credentials::seal("refresh-me", "correct horse")?;
+    assert!(!sealed.contains("refresh-me"));
+    assert_ne!(sealed, credentials::seal("refresh-me", "correct horse")?);
+    assert_eq!(credentials::unseal(&sealed, "correct horse")?, "refresh-me");
+    assert!(credentials::unseal(&sealed, "wrong").is_err());
+    assert!(credentials::unseal("v9:a:b:c", "correct horse").is_err());
+    
+    let config = Config {
+        sealed_refresh_token: Some(sealed),
+        ..Config::default()
+    };
+    assert!(config.has_refresh_token());
+    
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list_tracks() -> Result<()> {
+    use lynx_fm::display;
+    use lynx_fm::music::{Track, TrackFilter, TrackSort};
+    use serde_json::json;
+    
+    // 450 tracks served 200 at a time, with numeric IDs
+    let requests = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = requests.clone();
+    let base_url = spawn_stand_in(move |_, path, _| {
+        counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let url = reqwest::Url::parse(&format!("http://stand-in{}", path)).unwrap();
+        let param = |name: &str| -> usize {
+            url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.parse().unwrap()).unwrap_or(0)
+        };
+        let (offset, limit) = (param("offset"), param("limit"));
+        let tracks: Vec<Value> = (offset..(offset + limit).min(450))
+            .map(|i| json!({ "id": i, "title": format!("Song {}", i), "artist": if i % 2 == 0 { "Even" } else { "Odd" } }))
+            .collect();
+        (200, Value::Array(tracks))
+    })
+    .await?;
+    
+    let client = MusicClient::new(Config { music_server_url: base_url, ..create_test_config() });
+    let tracks = client.list_tracks().await?;
+    assert_eq!(tracks.len(), 450);
+    assert_eq!(tracks[449].id, "449");
+    
+    // An unsorted limit stops paging once it has enough; a sort reads every page
+    requests.store(0, std::sync::atomic::Ordering::SeqCst);
+    let first_five = TrackFilter { limit: Some(5), ..TrackFilter::default() };
+    assert_eq!(client.list_filtered_tracks(&first_five).await?.len(), 5);
+    assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+    let odd_only = TrackFilter { artist: Some("odd".to_string()), limit: Some(150), ..TrackFilter::default() };
+    assert_eq!(client.list_filtered_tracks(&odd_only).await?.len(), 150);
+    assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 3);
+    let sorted = TrackFilter { sort: Some(TrackSort::Title), limit: Some(5), ..TrackFilter::default() };
+    assert_eq!(client.list_filtered_tracks(&sorted).await?.len(), 5);
+    assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 6);
+    
+    // A server that ignores paging is read once
+    let unpaged = spawn_stand_in(|_, _, _| {
+        (200, json!({ "tracks": (0..200).map(|i| json!({ "id": format!("t{}", i) })).collect::<Vec<_>>() }))
+    })
+    .await?;
+    let client = MusicClient::new(Config { music_server_url: unpaged, ..create_test_config() });
+    assert_eq!(client.list_tracks().await?.len(), 200);
+    
+    let filter = TrackFilter {
+        artist: Some("odd".to_string()),
+        sort: Some(TrackSort::Title),
+        limit: Some(3),
+        ..TrackFilter::default()
+    };
+    let odd: Vec<String> = filter.apply(tracks).into_iter().map(|t| t.id).collect();
+    assert_eq!(odd, vec!["1", "101", "103"]);
+    
+    let by_length = TrackFilter { sort: Some(TrackSort::Duration), ..TrackFilter::default() }.apply(vec![
+        Track { id: "long".to_string(), duration: Some(300.0), ..Track::default() },
+        Track { id: "short".to_string(), duration: Some(65.0), ..Track::default() },
+    ]);
+    assert_eq!(by_length[0].id, "short");
+    assert_eq!(display::format_length(65.0), "1:05");
+    
+    let table = display::table(&["ID", "TITLE"], &[vec!["1".to_string(), "x".repeat(60)]]);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines[0], "ID  TITLE");
+    assert!(lines[1].ends_with('…'));
+    
+    Ok(())
+}
+
+#[test]
+fn test_doctor_test_tone() -> Result<()> {
+    use rodio::Source;
+    
+    let tone = lynx_fm::doctor::test_tone();
+    assert_eq!(&tone[..4], b"RIFF");
+    
+    let decoder = rodio::Decoder::new(std::io::Cursor::new(tone))?;
+    assert_eq!(decoder.sample_rate(), 44_100);
+    assert_eq!(decoder.channels(), 1);
+    assert_eq!(decoder.count(), 44_100 / 4);
     
     Ok(())
 }
@@ -783,8 +1315,12 @@ fn test_cli_commands() {
     assert!(subcommand_names.contains(&"config"), "Config command should exist");
     assert!(subcommand_names.contains(&"login"), "Login command should exist");
     assert!(subcommand_names.contains(&"logout"), "Logout command should exist");
+    assert!(subcommand_names.contains(&"whoami"), "Whoami command should exist");
+    assert!(subcommand_names.contains(&"account"), "Account command should exist");
+    assert!(subcommand_names.contains(&"profile"), "Profile command should exist");
     assert!(subcommand_names.contains(&"signup"), "Signup command should exist");
     assert!(subcommand_names.contains(&"health"), "Health command should exist");
+    assert!(subcommand_names.contains(&"doctor"), "Doctor command should exist");
     assert!(subcommand_names.contains(&"random"), "Random command should exist");
     assert!(subcommand_names.contains(&"play"), "Play command should exist");
     assert!(subcommand_names.contains(&"prefetch"), "Prefetch command should exist");
@@ -794,6 +1330,407 @@ fn test_cli_commands() {
     assert!(play_cmd.get_positionals().count() > 0, "Play command should have at least one positional argument");
 }
 
+// Test raw API request parsing and URL resolution
+#[test]
+fn test_api_command() -> Result<()> {
+    use clap::Parser;
+    use lynx_fm::commands::{parse_key_value, Cli, Commands};
+    
+    let cli = Cli::try_parse_from([
+        "lynx-fm", "api", "POST", "/api/playlists",
+        "--data", r#"{"name": "Office radio"}"#,
+        "-H", "X-Request-Id=abc=123",
+    ])?;
+    
+    match cli.command {
+        Commands::Api { method, path, data, headers } => {
+            assert_eq!(method, "POST");
+            assert_eq!(path, "/api/playlists");
+            assert_eq!(data, Some(serde_json::json!({"name": "Office radio"})));
+            assert_eq!(headers, vec![("X-Request-Id".to_string(), "abc=123".to_string())]);
+        }
+        other => panic!("Expected api command, got {:?}", other),
+    }
+    
+    // Malformed bodies and headers are rejected up front
+    assert!(Cli::try_parse_from(["lynx-fm", "api", "POST", "/me", "--data", "{oops"]).is_err());
+    assert!(parse_key_value("no-separator").is_err());
+    assert!(parse_key_value("=value").is_err());
+    
+    let client = MusicClient::new(Config {
+        music_server_url: "http://localhost:3500/".to_string(),
+        ..Config::default()
+    });
+    
+    assert_eq!(client.resolve_url("/me"), "http://localhost:3500/me");
+    assert_eq!(client.resolve_url("api/tracks"), "http://localhost:3500/api/tracks");
+    assert_eq!(client.resolve_url("https://other.host/health"), "https://other.host/health");
+    
+    // The token only goes to the configured server
+    assert!(client.is_server_url("http://localhost:3500/me"));
+    assert!(client.is_server_url("HTTP://LOCALHOST:3500"));
+    assert!(!client.is_server_url("https://other.host/health"));
+    assert!(!client.is_server_url("http://localhost:3501/me"));
+    assert!(!client.is_server_url("https://localhost:3500/me"));
+    assert!(!client.is_server_url("http://localhost:3500.attacker.example/me"));
+    
+    Ok(())
+}
+
+// Test that user headers replace the injected token and content type
+#[tokio::test]
+async fn test_api_request_header_overrides() -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let base_url = format!("http://{}", listener.local_addr()?);
+    let server = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.ok()?;
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !data.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = socket.read(&mut buf).await.ok()?;
+            if n == 0 {
+                break;
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+        let _ = socket
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}")
+            .await;
+        Some(String::from_utf8_lossy(&data).to_lowercase())
+    });
+    
+    let client = MusicClient::new(Config {
+        music_server_url: base_url,
+        auth_token: Some("saved-token".to_string()),
+        ..Config::default()
+    });
+    let headers = vec![
+        ("Authorization".to_string(), "Bearer other-token".to_string()),
+        ("Content-Type".to_string(), "application/merge-patch+json".to_string()),
+    ];
+    client
+        .api_request(reqwest::Method::PATCH, "/me", Some(&serde_json::json!({})), &headers)
+        .await?;
+    
+    let head = server.await?.expect("stand-in server should see a request");
+    assert_eq!(head.matches("authorization:").count(), 1, "{}", head);
+    assert!(head.contains("authorization: bearer other-token"), "{}", head);
+    assert_eq!(head.matches("content-type:").count(), 1, "{}", head);
+    assert!(head.contains("content-type: application/merge-patch+json"), "{}", head);
+    
+    Ok(())
+}
+
+// Test local token decoding and identity comparison for whoami
+#[test]
+fn test_token_claims_and_identity() -> Result<()> {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use lynx_fm::auth::TokenClaims;
+    use lynx_fm::music::Identity;
+    
+    let token = encode(
+        &Header::default(),
+        &serde_json::json!({
+            "sub": "user-123",
+            "email": "dj@lynx.fm",
+            "role": "authenticated",
+            "iss": "https://test-project.supabase.co/auth/v1",
+            "exp": 2_000_000_000i64,
+        }),
+        &EncodingKey::from_secret(b"secret"),
+    )?;
+    
+    let claims = TokenClaims::decode(&token)?;
+    assert_eq!(claims.sub.as_deref(), Some("user-123"));
+    assert_eq!(claims.email.as_deref(), Some("dj@lynx.fm"));
+    assert_eq!(claims.role.as_deref(), Some("authenticated"));
+    assert_eq!(claims.iss.as_deref(), Some("https://test-project.supabase.co/auth/v1"));
+    assert_eq!(claims.exp, Some(2_000_000_000));
+    
+    assert!(TokenClaims::decode("not-a-jwt").is_err());
+    
+    let matching: Identity = serde_json::from_value(serde_json::json!({
+        "user_id": "user-123",
+        "email": "DJ@lynx.fm",
+    }))?;
+    assert!(matching.mismatches(&claims).is_empty());
+    
+    let anon: Identity = serde_json::from_value(serde_json::json!({
+        "user_id": "anon-user",
+        "role": "anon",
+    }))?;
+    assert_eq!(anon.mismatches(&claims).len(), 2);
+    
+    Ok(())
+}
+
+// Test non-interactive credential resolution for headless logins
+#[cfg(unix)]
+#[test]
+fn test_credentials_from_password_command() -> Result<()> {
+    use lynx_fm::auth::Credentials;
+    
+    let mut config = Config {
+        email: Some("radio@lynx.fm".to_string()),
+        password_command: Some("printf 'hunter2\\nsecond line'".to_string()),
+        ..Config::default()
+    };
+    
+    let credentials = Credentials::resolve(&config, None, false)?.expect("credentials");
+    assert_eq!(credentials.email, "radio@lynx.fm");
+    assert_eq!(credentials.password, "hunter2");
+    
+    // An explicit email wins over the configured one
+    let credentials = Credentials::resolve(&config, Some("ci@lynx.fm".to_string()), false)?.expect("credentials");
+    assert_eq!(credentials.email, "ci@lynx.fm");
+    
+    config.password_command = Some("exit 3".to_string());
+    assert!(Credentials::resolve(&config, None, false).is_err());
+    
+    config.password_command = None;
+    assert!(Credentials::resolve(&config, None, false)?.is_none());
+    
+    Ok(())
+}
+
+// Test that OTP types serialize to the values Supabase expects
+#[test]
+fn test_otp_types() -> Result<()> {
+    use lynx_fm::auth::OtpType;
+    
+    assert_eq!(serde_json::to_value(OtpType::Signup)?, "signup");
+    assert_eq!(serde_json::to_value(OtpType::Email)?, "email");
+    assert_eq!(serde_json::to_value(OtpType::Recovery)?, "recovery");
+    
+    // Without a terminal the code can't be typed in, and password advice wouldn't help
+    let home = tempfile::tempdir()?;
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_lynx-fm"))
+        .args(["login", "--otp", "--email", "someone@example.com"])
+        .env("LYNX_FM_HOME", home.path())
+        .env_remove("LYNX_FM_PROFILE")
+        .stdin(std::process::Stdio::null())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("emailed login code") && !stderr.contains("LYNX_FM_PASSWORD"), "{}", stderr);
+    
+    Ok(())
+}
+
+// Test the PKCE helpers and loopback redirect listener used for provider logins
+#[tokio::test]
+async fn test_oauth_pkce_flow() -> Result<()> {
+    use lynx_fm::oauth::{authorize_url, bind_loopback, new_state, wait_for_callback, Pkce};
+    
+    // Test vector from RFC 7636 appendix B
+    let pkce = Pkce::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+    assert_eq!(pkce.challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    
+    let generated = Pkce::new();
+    assert_eq!(generated.verifier.len(), 43);
+    assert_ne!(generated.verifier, Pkce::new().verifier);
+    
+    let (listener, redirect_to) = bind_loopback().await?;
+    assert!(redirect_to.starts_with("http://127.0.0.1:"));
+    assert!(redirect_to.ends_with("/callback"));
+    
+    let state = new_state();
+    assert_ne!(state, new_state());
+    let url = authorize_url("https://test-project.supabase.co/", "github", &redirect_to, &pkce, &state)?;
+    assert!(url.starts_with("https://test-project.supabase.co/auth/v1/authorize?provider=github"));
+    assert!(url.contains("code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"));
+    assert!(url.contains("code_challenge_method=s256"));
+    let parsed = reqwest::Url::parse(&url)?;
+    let redirect = parsed.query_pairs().find(|(key, _)| key == "redirect_to").map(|(_, value)| value.into_owned());
+    assert_eq!(redirect, Some(format!("{}?state={}", redirect_to, state)));
+    
+    // Simulate the browser: a stray favicon request and a forged redirect first, then the real one
+    let browser = tokio::spawn({
+        let redirect_to = redirect_to.clone();
+        let state = state.clone();
+        async move {
+            let client = reqwest::Client::new();
+            let favicon = redirect_to.replace("/callback", "/favicon.ico");
+            let status = client.get(&favicon).send().await?.status();
+            assert_eq!(status.as_u16(), 404);
+            
+            for forged in ["?code=forged", "?code=forged&state=wrong", "?error=access_denied"] {
+                let response = client.get(format!("{}{}", redirect_to, forged)).send().await?;
+                assert_eq!(response.status().as_u16(), 400);
+            }
+            
+            let response = client.get(format!("{}?code=auth-code-123&state={}", redirect_to, state)).send().await?;
+            assert!(response.status().is_success());
+            anyhow::Ok(())
+        }
+    });
+    
+    let code = wait_for_callback(&listener, &state).await?;
+    assert_eq!(code, "auth-code-123");
+    browser.await??;
+    
+    // Provider errors are surfaced instead of waiting forever
+    let (listener, redirect_to) = bind_loopback().await?;
+    let browser = tokio::spawn({
+        let state = state.clone();
+        async move {
+            reqwest::get(format!("{}?error=access_denied&error_description=User+cancelled&state={}", redirect_to, state)).await
+        }
+    });
+    
+    let error = wait_for_callback(&listener, &state).await.unwrap_err();
+    assert!(error.to_string().contains("User cancelled"));
+    browser.await??;
+    
+    Ok(())
+}
+
+// Test the device-code login flow against a local stand-in server
+#[tokio::test]
+async fn test_device_login_flow() -> Result<()> {
+    use lynx_fm::device::{qr_code, DeviceFlow};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    let polls = std::sync::Arc::new(AtomicUsize::new(0));
+    let server_polls = polls.clone();
+    
+    let base_url = spawn_stand_in(move |method, path, body| {
+        match (method, path) {
+            ("POST", "/auth/device/code") => (200, serde_json::json!({
+                "device_code": "device-123",
+                "user_code": "WDJB-MJHT",
+                "verification_uri": "https://lynx.fm/activate",
+                "verification_uri_complete": "https://lynx.fm/activate?code=WDJB-MJHT",
+                "expires_in": 30,
+                "interval": 0,
+            })),
+            ("POST", "/auth/device/token") => {
+                let request: Value = serde_json::from_str(body).unwrap_or_default();
+                assert_eq!(request["device_code"], "device-123");
+                
+                // Approve on the third poll
+                if server_polls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    (400, serde_json::json!({"error": "authorization_pending"}))
+                } else {
+                    (200, serde_json::json!({
+                        "access_token": "access-abc",
+                        "refresh_token": "refresh-def",
+                        "expires_in": 3600,
+                    }))
+                }
+            }
+            _ => (404, serde_json::json!({"error": "not_found"})),
+        }
+    }).await?;
+    
+    let flow = DeviceFlow::new(&base_url, 30);
+    let authorization = flow.start().await?;
+    assert_eq!(authorization.user_code, "WDJB-MJHT");
+    assert_eq!(authorization.qr_target(), "https://lynx.fm/activate?code=WDJB-MJHT");
+    
+    let tokens = flow.poll(&authorization).await?;
+    assert_eq!(tokens.access_token, "access-abc");
+    assert_eq!(tokens.refresh_token, "refresh-def");
+    assert_eq!(polls.load(Ordering::SeqCst), 3);
+    
+    let qr = qr_code(authorization.qr_target())?;
+    assert!(qr.lines().count() > 10, "QR code should span several lines");
+    
+    // A denied login stops polling with a clear error
+    let denied_url = spawn_stand_in(|_, _, _| (403, serde_json::json!({"error": "access_denied"}))).await?;
+    let error = DeviceFlow::new(&denied_url, 30).poll(&authorization).await.unwrap_err();
+    assert_eq!(error.to_string(), "Device login was denied");
+    
+    Ok(())
+}
+
+// Test the account request bodies against a local stand-in server
+#[tokio::test]
+async fn test_account_requests() -> Result<()> {
+    use lynx_fm::AuthClient;
+    use std::sync::{Arc, Mutex};
+    
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let base_url = spawn_stand_in({
+        let requests = requests.clone();
+        move |method, path, body| {
+            let body: Value = serde_json::from_str(body).unwrap_or_default();
+            if body["email"] == "taken@example.com" {
+                return (422, serde_json::json!({"error": "email_exists", "error_description": "Email address already registered"}));
+            }
+            requests.lock().unwrap().push((method.to_string(), path.to_string(), body));
+            if path == "/auth/v1/factors" {
+                return (200, serde_json::json!({"id": "f-9", "totp": {"secret": "JBSWY3DP", "uri": "otpauth://totp/Lynx.fm:ada"}}));
+            }
+            (200, serde_json::json!({}))
+        }
+    })
+    .await?;
+    
+    let signed_out = AuthClient::new(Config { supabase_url: base_url.clone(), ..Config::default() });
+    signed_out.recover_password("ada@example.com").await?;
+    signed_out.resend_verification("ada@example.com").await?;
+    
+    // Updates need a session
+    assert!(signed_out.change_password("hunter22").await.is_err());
+    
+    let signed_in = AuthClient::new(Config {
+        supabase_url: base_url,
+        auth_token: Some("access-abc".to_string()),
+        ..Config::default()
+    });
+    signed_in.change_password("hunter22").await?;
+    signed_in.change_email("new@example.com").await?;
+    
+    let enrollment = signed_in.enroll_factor(Some("Phone".to_string())).await?;
+    assert_eq!((enrollment.id.as_str(), enrollment.totp.secret.as_str()), ("f-9", "JBSWY3DP"));
+    
+    let error = signed_in.change_email("taken@example.com").await.unwrap_err();
+    assert_eq!(error.to_string(), "Email change failed: email_exists - Email address already registered");
+    
+    let requests = requests.lock().unwrap().clone();
+    let expected = [
+        ("POST", "/auth/v1/recover", serde_json::json!({"email": "ada@example.com"})),
+        ("POST", "/auth/v1/resend", serde_json::json!({"email": "ada@example.com", "type": "signup"})),
+        ("PUT", "/auth/v1/user", serde_json::json!({"password": "hunter22"})),
+        ("PUT", "/auth/v1/user", serde_json::json!({"email": "new@example.com"})),
+        ("POST", "/auth/v1/factors", serde_json::json!({"factor_type": "totp", "friendly_name": "Phone"})),
+    ];
+    assert_eq!(requests.len(), expected.len());
+    for ((method, path, body), (want_method, want_path, want_body)) in requests.iter().zip(expected) {
+        assert_eq!((method.as_str(), path.as_str(), body), (want_method, want_path, &want_body));
+    }
+    
+    Ok(())
+}
+
+// Test detection of sessions that still need a second factor
+#[test]
+fn test_mfa_required() -> Result<()> {
+    use lynx_fm::auth::{mfa_required, Factor, TokenClaims};
+    
+    let factors: Vec<Factor> = serde_json::from_value(serde_json::json!([
+        {"id": "f-1", "friendly_name": "Phone", "factor_type": "totp", "status": "verified"},
+        {"id": "f-2", "factor_type": "totp", "status": "unverified"},
+    ]))?;
+    assert_eq!(factors[0].label(), "Phone (totp)");
+    assert_eq!(factors[1].label(), "totp");
+    
+    let aal1 = TokenClaims { aal: Some("aal1".to_string()), ..Default::default() };
+    let aal2 = TokenClaims { aal: Some("aal2".to_string()), ..Default::default() };
+    
+    assert!(mfa_required(&aal1, &factors));
+    assert!(!mfa_required(&aal2, &factors));
+    
+    // Unverified factors don't block login
+    assert!(!mfa_required(&aal1, &factors[1..]));
+    assert!(!mfa_required(&aal1, &[]));
+    
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_login() -> Result<()> {
     let config = create_test_config();
@@ -956,4 +1893,688 @@ async fn test_login() -> Result<()> {
     }
 
     Ok(())
-} 
\ No newline at end of file
+} 
+#[tokio::test]
+async fn test_search() -> Result<()> {
+    use lynx_fm::catalog::{self, LocalCatalog};
+    use lynx_fm::music::{CatalogChanges, Track};
+    use serde_json::json;
+    
+    let track = |id: &str, title: &str, artist: &str, album: &str| Track {
+        id: id.to_string(),
+        title: Some(title.to_string()),
+        artist: Some(artist.to_string()),
+        album: Some(album.to_string()),
+        ..Track::default()
+    };
+    
+    let temp = tempfile::tempdir()?;
+    let mut local = LocalCatalog::open(&temp.path().join("catalog.db"))?;
+    local.apply(CatalogChanges::Changed {
+        tracks: vec![
+            track("1", "Blue Monday 88", "New Order", "Substance"),
+            track("2", "Blue Monday", "New Order", "Power, Corruption & Lies"),
+            track("3", "Monday Morning", "Fleetwood Mac", "Fleetwood Mac"),
+        ],
+        complete: true,
+        etag: None,
+        last_modified: None,
+    })?;
+    
+    // Every word has to match the start of a word somewhere, and exact matches come first
+    let ids = |tracks: Vec<Track>| tracks.into_iter().map(|t| t.id).collect::<Vec<_>>();
+    assert_eq!(ids(local.search("new order - blue monday", 10)?), vec!["2", "1"]);
+    assert_eq!(local.search("mon", 10)?.len(), 3);
+    assert!(local.search("monday tuesday", 10)?.is_empty());
+    assert!(local.search("  ", 10)?.is_empty());
+    assert_eq!(local.complete("fleet", 10)?, vec!["Fleetwood Mac"]);
+    assert_eq!(local.complete("%", 10)?, Vec::<String>::new());
+    
+    let results = local.search("blue monday", 10)?;
+    assert_eq!(catalog::resolve_exact(&results, "Blue Monday").map(|t| t.id.as_str()), Some("2"));
+    assert_eq!(local.track("3")?.and_then(|t| t.artist).as_deref(), Some("Fleetwood Mac"));
+    assert!(local.track("4")?.is_none());
+    assert_eq!(catalog::resolve_exact(&local.search("fleetwood", 10)?, "fleetwood").map(|t| t.id.as_str()), Some("3"));
+    assert!(catalog::resolve_exact(&local.search("monday", 10)?, "monday").is_none());
+    
+    // The server's own search endpoint is used when there is one
+    let base_url = spawn_stand_in(|_, path, _| {
+        if path.starts_with("/search?q=blue") {
+            (200, json!({ "tracks": [{ "id": 7, "title": "Blue", "artist": "Joni Mitchell" }] }))
+        } else {
+            (404, json!({ "error": "not found" }))
+        }
+    })
+    .await?;
+    
+    let client = MusicClient::new(Config { music_server_url: base_url, ..create_test_config() });
+    let results = catalog::search(&client, "blue").await?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].label(), "Joni Mitchell - Blue");
+    assert!(client.search_tracks("other").await?.is_none());
+    
+    // A server that answers with an error isn't offline, so its error comes back as is
+    let failing = spawn_stand_in(|_, _, _| (500, json!({ "error": "boom" }))).await?;
+    let client = MusicClient::new(Config { music_server_url: failing, ..create_test_config() });
+    let error = catalog::search_without_sync(&client, "blue").await.unwrap_err();
+    assert!(!catalog::is_connection_error(&error));
+    assert!(error.to_string().contains("Search failed"));
+    
+    // Nothing listening at all is
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", closed.local_addr()?);
+    drop(closed);
+    let client = MusicClient::new(Config { music_server_url: url, ..create_test_config() });
+    assert!(catalog::is_connection_error(&client.search_tracks("blue").await.unwrap_err()));
+    
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_catalog_sync() -> Result<()> {
+    use lynx_fm::catalog::{self, LocalCatalog};
+    use lynx_fm::music::CatalogChanges;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    
+    // The server's catalog, and the requests it has seen
+    let served = Arc::new(Mutex::new(vec![
+        json!({ "id": 1, "title": "One", "artist": "A", "updated_at": "2026-01-01T00:00:00Z" }),
+        json!({ "id": 2, "title": "Two", "artist": "B", "updated_at": "2026-01-02T00:00:00Z" }),
+    ]));
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    
+    let base_url = spawn_stand_in({
+        let (served, paths) = (served.clone(), paths.clone());
+        move |_, path, _| {
+            paths.lock().unwrap().push(path.to_string());
+            let tracks = served.lock().unwrap().clone();
+            (200, json!({ "tracks": tracks }))
+        }
+    })
+    .await?;
+    
+    let client = MusicClient::new(Config { music_server_url: base_url, ..create_test_config() });
+    let temp = tempfile::tempdir()?;
+    let mut local = LocalCatalog::open(&temp.path().join("catalog.db"))?;
+    assert!(local.sync_token()?.is_none());
+    
+    let report = catalog::sync(&client, &mut local, false).await?;
+    assert!(report.initial);
+    assert_eq!((report.added.len(), report.total), (2, 2));
+    assert!(!paths.lock().unwrap()[0].contains("updated_since"));
+    
+    // Later syncs ask only for what changed since the newest update seen
+    *served.lock().unwrap() = vec![
+        json!({ "id": 2, "title": "Two (Remastered)", "artist": "B", "updated_at": "2026-02-01T00:00:00Z" }),
+        json!({ "id": 3, "title": "Three", "artist": "C", "updated_at": "2026-02-02T00:00:00Z" }),
+    ];
+    let report = catalog::sync(&client, &mut local, false).await?;
+    assert!(paths.lock().unwrap()[1].contains("updated_since=2026-01-02T00%3A00%3A00Z"));
+    assert_eq!((report.added.len(), report.updated, report.removed, report.total), (1, 1, 0, 3));
+    assert_eq!(local.search("remastered", 10)?[0].id, "2");
+    assert_eq!(local.sync_token()?.unwrap().cursor.as_deref(), Some("2026-02-02T00:00:00Z"));
+    
+    // Only tracks that arrived after the first sync count as new
+    let new: Vec<String> = local.added_since(0)?.into_iter().map(|t| t.id).collect();
+    assert_eq!(new, vec!["3"]);
+    
+    // A full sync drops what the server no longer lists
+    let report = catalog::sync(&client, &mut local, true).await?;
+    assert!(!paths.lock().unwrap()[2].contains("updated_since"));
+    assert_eq!((report.removed, report.total), (1, 2));
+    assert!(local.search("one", 10)?.is_empty());
+    
+    let report = local.apply(CatalogChanges::Unchanged)?;
+    assert!(report.unchanged);
+    assert_eq!(report.total, 2);
+    
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_browse() -> Result<()> {
+    use lynx_fm::catalog;
+    use lynx_fm::music::Track;
+    use serde_json::json;
+    
+    let track = |id: &str, artist: &str, album: &str, year: i32, number: u32| Track {
+        id: id.to_string(),
+        artist: Some(artist.to_string()),
+        album: Some(album.to_string()),
+        genre: Some("Synth-pop".to_string()),
+        year: Some(year),
+        track_number: Some(number),
+        ..Track::default()
+    };
+    let tracks = vec![
+        track("s2", "New Order", "Substance", 1987, 2),
+        track("p1", "New Order", "Power, Corruption & Lies", 1983, 1),
+        track("s1", "New Order", "Substance", 1987, 1),
+        track("x1", "New Order", "Substance 1987 (Remaster)", 2008, 1),
+        track("d1", "Depeche Mode", "Violator", 1990, 1),
+    ];
+    
+    let artists = catalog::group_artists(&tracks);
+    assert_eq!(artists.iter().map(|a| a.name.as_str()).collect::<Vec<_>>(), vec!["Depeche Mode", "New Order"]);
+    assert_eq!((artists[1].albums, artists[1].tracks), (Some(3), Some(4)));
+    
+    let albums = catalog::group_albums(&tracks);
+    assert_eq!(albums[1].title, "Power, Corruption & Lies");
+    assert_eq!(catalog::group_genres(&tracks)[0].tracks, Some(5));
+    
+    // An exact album name doesn't pull in albums that merely contain it
+    let ids = |tracks: Vec<Track>| tracks.into_iter().map(|t| t.id).collect::<Vec<_>>();
+    assert_eq!(ids(catalog::tracks_for(tracks.clone(), None, Some("substance"))), vec!["s1", "s2"]);
+    assert_eq!(ids(catalog::tracks_for(tracks.clone(), Some("new order"), None)), vec!["p1", "s1", "s2", "x1"]);
+    assert_eq!(ids(catalog::tracks_for(tracks, Some("depeche"), Some("viol"))), vec!["d1"]);
+    
+    // Server listings come bare or wrapped; a missing endpoint means deriving them instead
+    let base_url = spawn_stand_in(|_, path, _| match path {
+        "/artists" => (200, json!([{ "name": "Kraftwerk", "album_count": 10 }])),
+        p if p.starts_with("/albums") => (200, json!({ "albums": [{ "name": "Computer World", "artist": "Kraftwerk" }] })),
+        _ => (404, json!({ "error": "not found" })),
+    })
+    .await?;
+    
+    let client = MusicClient::new(Config { music_server_url: base_url, ..create_test_config() });
+    assert_eq!(client.list_artists().await?.unwrap()[0].albums, Some(10));
+    assert_eq!(catalog::albums(&client, Some("kraft")).await?[0].title, "Computer World");
+    assert!(catalog::albums(&client, Some("other")).await?.is_empty());
+    assert!(client.list_genres().await?.is_none());
+    
+    // A server that refuses is reported, not papered over with the local catalog
+    let refusing = spawn_stand_in(|_, _, _| (401, json!({ "error": "unauthorized" }))).await?;
+    let client = MusicClient::new(Config { music_server_url: refusing, ..create_test_config() });
+    let error = catalog::artists(&client).await.unwrap_err();
+    assert!(error.to_string().contains("401"), "{}", error);
+    assert!(catalog::all_tracks(&client).await.is_err());
+    
+    // Every-track listings come from the server, so tracks added since the last sync show up
+    let listing = spawn_stand_in(|_, path, _| match path {
+        p if p.starts_with("/tracks") => (200, json!([{ "id": "new1", "title": "Fresh" }])),
+        _ => (404, json!({ "error": "not found" })),
+    })
+    .await?;
+    let client = MusicClient::new(Config { music_server_url: listing, ..create_test_config() });
+    assert_eq!(ids(catalog::all_tracks(&client).await?), vec!["new1"]);
+    
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_playlists() -> Result<()> {
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let base_url = spawn_stand_in({
+        let requests = requests.clone();
+        move |method, path, body| {
+            requests.lock().unwrap().push(format!("{} {} {}", method, path, body));
+            match (method, path) {
+                ("GET", "/api/playlists") => (200, json!({ "playlists": [
+                    { "id": 1, "name": "Office Radio", "track_count": 2 },
+                    { "id": 2, "name": "Focus" },
+                ] })),
+                ("GET", "/api/playlists/1") => (200, json!({
+                    "id": 1,
+                    "name": "Office Radio",
+                    "tracks": [{ "id": "a" }, { "id": "b" }],
+                })),
+                ("POST", "/api/playlists") => (201, json!({ "id": 3, "name": "New" })),
+                ("PUT", "/api/playlists/1/tracks") => (204, Value::Null),
+                _ => (501, json!({ "error": "not implemented" })),
+            }
+        }
+    })
+    .await?;
+    
+    let client = MusicClient::new(Config { music_server_url: base_url, ..create_test_config() });
+    
+    let playlists = client.list_playlists().await?;
+    assert_eq!((playlists[0].track_total(), playlists[1].track_total()), (2, 0));
+    
+    let found = client.find_playlist("office radio").await?;
+    assert_eq!(found.id, "1");
+    assert!(client.find_playlist("Gym").await.is_err());
+    assert_eq!(client.get_playlist("1").await?.tracks[1].id, "b");
+    
+    assert_eq!(client.create_playlist("New", None).await?.id, "3");
+    client.reorder_playlist("1", &["b".to_string(), "a".to_string()]).await?;
+    
+    // IDs stay inside their path segment
+    assert!(client.remove_from_playlist("1", "a/../b?x").await.is_err());
+    
+    let requests = requests.lock().unwrap().clone();
+    assert!(requests.contains(&"DELETE /api/playlists/1/tracks/a%2F..%2Fb%3Fx ".to_string()), "{:?}", requests);
+    assert!(requests.contains(&r#"POST /api/playlists {"name":"New"}"#.to_string()));
+    assert!(requests.contains(&r#"PUT /api/playlists/1/tracks {"track_ids":["b","a"]}"#.to_string()));
+    
+    // Operations the server lacks say so
+    let error = client.rename_playlist("1", "Renamed").await.unwrap_err();
+    assert!(error.to_string().contains("doesn't support playlists"), "{}", error);
+    
+    Ok(())
+}
+
+#[test]
+fn test_local_playlists() -> Result<()> {
+    use lynx_fm::music::{Playlist, Track};
+    use lynx_fm::playlist::{self, PlaylistFormat, PlaylistStore};
+    
+    let track = |id: &str, title: &str, artist: &str| Track {
+        id: id.to_string(),
+        title: Some(title.to_string()),
+        artist: Some(artist.to_string()),
+        duration: Some(200.4),
+        ..Track::default()
+    };
+    let catalog = vec![
+        track("42", "Blue Monday", "New Order"),
+        track("43", "Enjoy the Silence", "Depeche Mode"),
+        track("44", "Intro", "The xx"),
+        track("45", "Intro", "M83"),
+    ];
+    
+    // IDs, lynx:// URIs, stream URLs, cached files and titles all resolve; ambiguous titles don't
+    let m3u = "\u{feff}#EXTM3U\n#PLAYLIST:Mix\n42\nlynx://track/43\n#EXTINF:200,The xx - Intro\n/music/01 intro.mp3\n\
+               #EXTINF:-1,Intro\n/music/other.mp3\nhttp://go.lynx.fm:3500/tracks/45\n/home/me/.cache/lynx-fm/default/tracks/42\n";
+    let parsed = playlist::parse(m3u, PlaylistFormat::M3u8)?;
+    assert_eq!(parsed.title.as_deref(), Some("Mix"));
+    let resolved = playlist::resolve(parsed.entries, &catalog);
+    let ids: Vec<&str> = resolved.tracks.iter().map(|t| t.id.as_str()).collect();
+    assert_eq!(ids, vec!["42", "43", "44", "45", "42"]);
+    assert_eq!(resolved.unmatched.len(), 1);
+    assert_eq!(resolved.unmatched[0].location, "/music/other.mp3");
+    
+    let pls = "[playlist]\nFile2=lynx://track/44\nFile1=Depeche Mode - Enjoy the Silence.flac\nTitle1=\nNumberOfEntries=2\n";
+    let resolved = playlist::resolve(playlist::parse(pls, PlaylistFormat::Pls)?.entries, &catalog);
+    assert_eq!(resolved.tracks.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(), vec!["43", "44"]);
+    
+    // Exports read back as the same tracks
+    let mix = Playlist {
+        id: "mix".to_string(),
+        name: "Rock & Roll".to_string(),
+        tracks: catalog[..2].to_vec(),
+        ..Playlist::default()
+    };
+    let urls: Vec<String> = mix.tracks.iter().map(|t| playlist::stream_url(t, "http://server/")).collect::<Result<_>>()?;
+    assert_eq!(urls[0], "http://server/tracks/42");
+    
+    // IDs are encoded into a single path segment and decoded again on import
+    let odd = Track { id: "a/b?c#d".to_string(), ..catalog[0].clone() };
+    let url = playlist::stream_url(&odd, "http://server/base/")?;
+    assert_eq!(url, "http://server/base/tracks/a%2Fb%3Fc%23d");
+    let m3u = format!("#EXTM3U\n{}\n", url);
+    let resolved = playlist::resolve(playlist::parse(&m3u, PlaylistFormat::M3u)?.entries, std::slice::from_ref(&odd));
+    assert_eq!(resolved.tracks, vec![odd]);
+    
+    for format in [PlaylistFormat::M3u, PlaylistFormat::Pls, PlaylistFormat::Xspf] {
+        let rendered = playlist::render(&mix, &urls, format);
+        let parsed = playlist::parse(&rendered, format)?;
+        let resolved = playlist::resolve(parsed.entries, &catalog);
+        assert_eq!(resolved.tracks, mix.tracks, "{:?}", format);
+    }
+    let xspf = playlist::render(&mix, &urls, PlaylistFormat::Xspf);
+    assert!(xspf.contains("<title>Rock &amp; Roll</title>"));
+    assert!(xspf.contains("<duration>200400</duration>"));
+    assert_eq!(playlist::parse(&xspf, PlaylistFormat::Xspf)?.title.as_deref(), Some("Rock & Roll"));
+    assert_eq!(PlaylistFormat::from_path(std::path::Path::new("a.M3U8"))?, PlaylistFormat::M3u8);
+    assert!(PlaylistFormat::from_path(std::path::Path::new("a.txt")).is_err());
+    
+    let temp = tempfile::tempdir()?;
+    let store = PlaylistStore::new(temp.path().to_path_buf());
+    assert!(store.list()?.is_empty());
+    store.create("Office Radio!", None, catalog[..1].to_vec())?;
+    assert!(store.create("office radio!", None, Vec::new()).is_err());
+    let other = store.create("Office  Radio", Some("second"), Vec::new())?;
+    assert_eq!(other.id, "office-radio-2");
+    
+    let mut found = store.find("OFFICE RADIO!")?;
+    assert_eq!((found.id.as_str(), found.tracks.len()), ("office-radio", 1));
+    assert!(store.rename(&mut found, "office  RADIO").is_err());
+    store.rename(&mut found, "Renamed")?;
+    assert_eq!(store.find("office-radio")?.name, "Renamed");
+    store.rename(&mut found, "RENAMED")?;
+    
+    store.delete("office-radio-2")?;
+    assert_eq!(store.list()?.len(), 1);
+    
+    Ok(())
+}
+
+#[test]
+fn test_smart_rules() -> Result<()> {
+    use lynx_fm::music::{Track, TrackSort};
+    use lynx_fm::smart::{PlayStats, Rule, RuleContext, SmartPlaylist, SmartStore};
+    use std::collections::HashMap;
+    
+    let now = 1_800_000_000;
+    let track = |id: &str, genre: &str, bpm: f64, rating: f64| Track {
+        id: id.to_string(),
+        title: Some(format!("Track {}", id)),
+        artist: Some(if id < "c" { "Surgeon" } else { "New Order" }.to_string()),
+        genre: Some(genre.to_string()),
+        bpm: Some(bpm),
+        rating: Some(rating),
+        duration: Some(210.0),
+        added_at: Some("2027-01-10T00:00:00Z".to_string()),
+        ..Track::default()
+    };
+    let tracks = vec![
+        track("a", "Techno", 130.0, 4.5),
+        track("b", "Techno", 120.0, 5.0),
+        track("c", "Synth-pop", 128.0, 4.0),
+        Track { id: "d".to_string(), ..Track::default() },
+    ];
+    let plays = HashMap::from([("a".to_string(), PlayStats { count: 3, last_played: now - 3_600 })]);
+    let context = RuleContext { now, plays: &plays };
+    
+    let matching = |rule: &str| -> Result<Vec<String>> {
+        let rule = Rule::parse(rule)?;
+        Ok(tracks.iter().filter(|t| rule.matches(t, &context)).map(|t| t.id.clone()).collect())
+    };
+    
+    assert_eq!(matching("genre:techno AND bpm>125 AND rating>=4")?, vec!["a"]);
+    assert_eq!(matching("genre:techno AND NOT played:7d")?, vec!["b"]);
+    assert_eq!(matching("genre:techno -played:7d")?, vec!["b"]);
+    assert_eq!(matching("played>30m plays=3")?, vec!["a"]);
+    assert_eq!(matching("artist:\"new order\" OR (genre=techno bpm<125)")?, vec!["b", "c"]);
+    assert_eq!(matching("duration:3:30 added<30d")?, vec!["a", "b", "c"]);
+    assert_eq!(matching("genre!=techno")?, vec!["c", "d"]);
+    assert_eq!(matching("surgeon")?, vec!["a", "b"]);
+    assert_eq!(matching("\"bpm>1\"")?, Vec::<String>::new());
+    
+    for bad in ["", "genre:", "mood:happy", "bpm>fast", "title>a", "played=7d", "(genre:techno", "genre:techno)", "NOT", "\"open"] {
+        assert!(Rule::parse(bad).is_err(), "{}", bad);
+    }
+    
+    // Ties break by ID, so the order doesn't depend on storage order
+    let smart = SmartPlaylist { name: "Peak".to_string(), rule: "genre:techno".to_string(), sort: Some(TrackSort::Artist), limit: None };
+    let mut reversed = tracks.clone();
+    reversed.reverse();
+    assert_eq!(smart.evaluate(reversed, &context)?, smart.evaluate(tracks.clone(), &context)?);
+    
+    let temp = tempfile::tempdir()?;
+    let store = SmartStore::new(temp.path().join("smart.json"));
+    store.add(smart.clone())?;
+    assert!(store.add(smart.clone()).is_err());
+    assert!(store.add(SmartPlaylist { name: "Bad".to_string(), rule: "mood:x".to_string(), sort: None, limit: None }).is_err());
+    assert_eq!(store.find("peak")?, smart);
+    store.remove("PEAK")?;
+    assert!(store.list()?.is_empty());
+    
+    Ok(())
+}
+
+// Catalogs created before bpm and rating existed gain the columns on open
+#[test]
+fn test_catalog_upgrade() -> Result<()> {
+    use lynx_fm::catalog::LocalCatalog;
+    
+    let temp = tempfile::tempdir()?;
+    let version_1 = |path: &std::path::Path, extra: &str| -> Result<()> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(&format!(
+            "CREATE TABLE tracks (id TEXT PRIMARY KEY, title TEXT, artist TEXT, album TEXT, genre TEXT,
+                 duration REAL, track_number INTEGER, year INTEGER, added_at TEXT, updated_at TEXT,
+                 first_sync INTEGER NOT NULL, last_sync INTEGER NOT NULL);
+             CREATE VIRTUAL TABLE tracks_fts USING fts5(id UNINDEXED, title, artist, album, genre);
+             CREATE TABLE syncs (id INTEGER PRIMARY KEY, synced_at INTEGER NOT NULL, complete INTEGER NOT NULL,
+                 added INTEGER NOT NULL DEFAULT 0, updated INTEGER NOT NULL DEFAULT 0, removed INTEGER NOT NULL DEFAULT 0);
+             CREATE TABLE sync_state (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+             INSERT INTO tracks (id, title, first_sync, last_sync) VALUES ('1', 'Old', 1, 1);
+             INSERT INTO syncs (synced_at, complete) VALUES (1, 1);
+             INSERT INTO sync_state (key, value) VALUES ('cursor', '2026-01-01T00:00:00Z');
+             {}
+             PRAGMA user_version = 1;",
+            extra
+        ))?;
+        Ok(())
+    };
+    
+    let path = temp.path().join("catalog.db");
+    version_1(&path, "")?;
+    
+    // The upgrade forgets the sync cursor, so the next sync fetches the new fields for every track
+    let catalog = LocalCatalog::open(&path)?;
+    let tracks = catalog.tracks()?;
+    assert_eq!((tracks[0].title.as_deref(), tracks[0].bpm), (Some("Old"), None));
+    assert_eq!(catalog.sync_token()?.and_then(|token| token.cursor), None);
+    
+    // Reopening doesn't run the upgrade twice
+    drop(catalog);
+    LocalCatalog::open(&path)?;
+    
+    // An upgrade that fails halfway leaves the database as it was
+    let broken = temp.path().join("broken.db");
+    version_1(&broken, "ALTER TABLE tracks ADD COLUMN rating REAL;")?;
+    assert!(LocalCatalog::open(&broken).is_err());
+    let conn = rusqlite::Connection::open(&broken)?;
+    assert_eq!(conn.query_row("PRAGMA user_version", [], |row| row.get::<_, i32>(0))?, 1);
+    assert!(conn.prepare("SELECT bpm FROM tracks").is_err());
+    assert_eq!(conn.query_row("SELECT count(*) FROM sync_state", [], |row| row.get::<_, i64>(0))?, 1);
+    
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_random_filters() -> Result<()> {
+    use lynx_fm::music::{RandomPick, Track};
+    use lynx_fm::random::{self, RandomFilter};
+    use lynx_fm::smart::{PlayStats, RuleContext};
+    use rand::SeedableRng;
+    use serde_json::json;
+    use std::collections::HashMap;
+    
+    let now = 1_800_000_000;
+    let track = |id: &str, genre: &str, duration: f64, rating: Option<f64>| Track {
+        id: id.to_string(),
+        artist: Some("Surgeon".to_string()),
+        genre: Some(genre.to_string()),
+        duration: Some(duration),
+        rating,
+        ..Track::default()
+    };
+    let tracks = vec![
+        track("a", "Techno", 400.0, Some(5.0)),
+        track("b", "Detroit Techno", 150.0, Some(1.0)),
+        track("c", "Techno", 300.0, None),
+        track("d", "Ambient", 600.0, Some(5.0)),
+    ];
+    let plays = HashMap::from([
+        ("a".to_string(), PlayStats { count: 9, last_played: now - 3_600 }),
+        ("c".to_string(), PlayStats { count: 1, last_played: now - 3 * 86_400 }),
+    ]);
+    let context = RuleContext { now, plays: &plays };
+    
+    let filter = RandomFilter {
+        genre: Some("techno".to_string()),
+        min_duration: Some(180),
+        exclude_played: Some(86_400),
+        ..RandomFilter::default()
+    };
+    let allowed: Vec<&str> = tracks.iter().filter(|t| filter.matches(t, &context)).map(|t| t.id.as_str()).collect();
+    assert_eq!(allowed, vec!["c"]);
+    assert_eq!(
+        filter.server_query(),
+        vec![("genre", "techno".to_string()), ("min_duration", "180".to_string())]
+    );
+    assert!(RandomFilter::default().is_empty());
+    
+    // Heavy rotation and low ratings both count against a track
+    assert!(random::weight(&tracks[3], &context) > random::weight(&tracks[0], &context));
+    assert!(random::weight(&tracks[3], &context) > random::weight(&tracks[1], &context));
+    
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let mut picks = HashMap::new();
+    for _ in 0..2_000 {
+        let pick = random::pick(&tracks, &RandomFilter::default(), &context, true, &mut rng).unwrap();
+        *picks.entry(pick.id.clone()).or_insert(0) += 1;
+    }
+    assert!(picks["d"] > picks["a"] * 5);
+    assert!(random::pick(&tracks, &filter, &context, false, &mut rng).is_some_and(|t| t.id == "c"));
+    let nothing = RandomFilter { genre: Some("polka".to_string()), ..RandomFilter::default() };
+    assert!(random::pick(&tracks, &nothing, &context, true, &mut rng).is_none());
+    
+    // Filters are forwarded as query parameters
+    let base_url = spawn_stand_in(|_, path, _| match path {
+        "/random?genre=techno&min_duration=180" => (200, json!({ "id": 5, "title": "Klonk", "genre": "Techno", "duration": 320 })),
+        "/random?genre=ambient" => (200, json!({ "track_id": "6" })),
+        _ => (400, json!({ "error": "unknown filter" })),
+    })
+    .await?;
+    
+    let client = MusicClient::new(Config { music_server_url: base_url, ..create_test_config() });
+    let pick = client.random_track_matching(&filter.server_query()).await?.unwrap();
+    assert_eq!(pick.id, "5");
+    assert!(pick.track.is_some_and(|t| filter.matches(&t, &context)));
+    assert_eq!(
+        client.random_track_matching(&[("genre", "ambient".to_string())]).await?,
+        Some(RandomPick { id: "6".to_string(), track: None })
+    );
+    assert!(client.random_track_matching(&[("artist", "x".to_string())]).await?.is_none());
+    
+    Ok(())
+}
+
+#[test]
+fn test_listening_history() -> Result<()> {
+    use chrono::{DateTime, Duration, Utc};
+    use lynx_fm::history::{self, History, Play, PlaySource};
+    use lynx_fm::music::{Playback, Track};
+    
+    let temp = tempfile::tempdir()?;
+    let path = temp.path().join("history.db");
+    let history = History::open(&path)?;
+    
+    let at = |secs: i64| DateTime::from_timestamp(1_800_000_000 + secs, 0).unwrap();
+    let track = Track {
+        id: "7".to_string(),
+        title: Some("Blue Monday, 1988".to_string()),
+        artist: Some("New Order".to_string()),
+        ..Track::default()
+    };
+    let played = |secs: i64, completed: bool| Playback { started_at: at(secs), listened: if completed { 449.0 } else { 12.5 }, completed };
+    
+    history.record(&Play::new(&track, &played(0, true), PlaySource::Playlist))?;
+    history.record(&Play::new(&track, &played(600, false), PlaySource::Random))?;
+    history.record(&Play::new(&Track { id: "8".to_string(), ..Track::default() }, &played(300, true), PlaySource::Track))?;
+    
+    // Newest first, and the history survives reopening
+    drop(history);
+    let history = History::open(&path)?;
+    let plays = history.plays(None, None)?;
+    let order: Vec<(&str, bool)> = plays.iter().map(|p| (p.track_id.as_str(), p.completed)).collect();
+    assert_eq!(order, vec![("7", false), ("8", true), ("7", true)]);
+    assert_eq!(plays[0].source, PlaySource::Random);
+    assert_eq!(plays[0].label(), "New Order - Blue Monday, 1988");
+    assert_eq!(plays[1].label(), "8");
+    
+    assert_eq!(history.plays(Some(at(300)), None)?.len(), 2);
+    assert_eq!(history.plays(None, Some(1))?[0].started_at, at(600));
+    
+    let stats = history.stats()?;
+    assert_eq!((stats["7"].count, stats["7"].last_played), (2, at(600).timestamp()));
+    assert_eq!(stats["8"].count, 1);
+    
+    // Fields with commas are quoted
+    let csv = history::to_csv(&plays);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "started_at,track_id,artist,title,listened,completed,source");
+    assert_eq!(lines[1], "2027-01-15T08:10:00+00:00,7,New Order,\"Blue Monday, 1988\",12.5,false,random");
+    assert_eq!(lines.len(), 4);
+    
+    let json: Vec<Play> = serde_json::from_str(&serde_json::to_string(&plays)?)?;
+    assert_eq!(json, plays);
+    
+    assert_eq!(history::parse_since("2026-10-01")?, "2026-10-01T00:00:00Z".parse::<DateTime<Utc>>()?);
+    let day_ago = history::parse_since("24h")?;
+    assert!((Utc::now() - Duration::hours(24) - day_ago).num_seconds().abs() < 5);
+    assert!(history::parse_since("yesterday").is_err());
+    assert!(history::parse_since("999999999999d").is_err());
+    assert!(history::parse_since("400000y").is_err());
+    
+    Ok(())
+}
+
+#[test]
+fn test_listening_stats() -> Result<()> {
+    use chrono::{DateTime, Utc};
+    use lynx_fm::history::{Play, PlaySource};
+    use lynx_fm::music::Track;
+    use lynx_fm::stats::{ReportFormat, Stats};
+    use std::collections::HashMap;
+    
+    // 2027-01-15 was a Friday
+    let play = |id: &str, artist: Option<&str>, hour: u32, listened: f64, completed: bool| Play {
+        track_id: id.to_string(),
+        title: Some(format!("Track <{}>", id)),
+        artist: artist.map(str::to_string),
+        started_at: format!("2027-01-15T{:02}:00:00Z", hour).parse::<DateTime<Utc>>().unwrap(),
+        listened,
+        completed,
+        source: PlaySource::Random,
+    };
+    let plays = vec![
+        play("1", Some("New Order"), 9, 240.0, true),
+        play("1", Some("New Order"), 9, 240.0, true),
+        play("2", Some("New Order"), 9, 30.0, false),
+        play("3", None, 22, 300.0, true),
+    ];
+    
+    // The history didn't record track 3's artist, but the catalog knows it
+    let catalog: HashMap<String, Track> = [("1", "Synth-pop", None), ("3", "Techno", Some("Surgeon"))]
+        .into_iter()
+        .map(|(id, genre, artist)| {
+            let track = Track { id: id.to_string(), genre: Some(genre.to_string()), artist: artist.map(str::to_string), ..Track::default() };
+            (id.to_string(), track)
+        })
+        .collect();
+        
+    let stats = Stats::compute("2027", &plays, &catalog, 2, &Utc);
+    assert_eq!((stats.plays, stats.skipped, stats.listened), (4, 1, 810.0));
+    assert_eq!(stats.skip_rate(), 0.25);
+    
+    let names = |ranking: &[lynx_fm::stats::Ranked]| ranking.iter().map(|r| (r.name.clone(), r.plays)).collect::<Vec<_>>();
+    // Ties go to the track listened to longest
+    assert_eq!(names(&stats.tracks), vec![("New Order - Track <1>".to_string(), 2), ("Track <3>".to_string(), 1)]);
+    assert_eq!(names(&stats.artists), vec![("New Order".to_string(), 3), ("Surgeon".to_string(), 1)]);
+    assert_eq!(names(&stats.genres), vec![("Synth-pop".to_string(), 2), ("Techno".to_string(), 1)]);
+    
+    assert_eq!(stats.heatmap[4][9], 3);
+    assert_eq!(stats.heatmap[4][22], 1);
+    assert_eq!(stats.heatmap.iter().flatten().sum::<usize>(), 4);
+    
+    // Plays recorded without a title are named from the catalog rather than by ID
+    let untitled = Play { title: None, ..play("5", None, 8, 200.0, true) };
+    let known = Track {
+        id: "5".to_string(),
+        title: Some("Flying Horse".to_string()),
+        artist: Some("Surgeon".to_string()),
+        ..Track::default()
+    };
+    let catalog_names = HashMap::from([("5".to_string(), known)]);
+    let named = Stats::compute("2027", &[untitled], &catalog_names, 1, &Utc);
+    assert_eq!(named.tracks[0].name, "Surgeon - Flying Horse");
+    let heatmap = stats.heatmap_text();
+    assert_eq!(heatmap.lines().count(), 8);
+    assert!(heatmap.lines().nth(5).is_some_and(|fri| fri.starts_with("Fri") && fri.contains('█')));
+    
+    let markdown = stats.render(ReportFormat::Markdown);
+    assert!(markdown.starts_with("# Lynx.fm listening report: 2027"));
+    assert!(markdown.contains("- **Skip rate:** 25%"));
+    assert!(markdown.contains("| 1 | New Order | 3 | 8:30 |"));
+    
+    // Names are escaped in HTML
+    let html = stats.render(ReportFormat::Html);
+    assert!(html.contains("New Order - Track &lt;1&gt;"));
+    assert!(!html.contains("Track <1>"));
+    
+    let empty = Stats::compute("All time", &[], &HashMap::new(), 10, &Utc);
+    assert_eq!(empty.skip_rate(), 0.0);
+    assert!(empty.render(ReportFormat::Markdown).contains("Nothing yet."));
+    
+    Ok(())
+}